@@ -0,0 +1,95 @@
+//! Finds where two runs of a program diverge, either two instruction traces
+//! or the same ROM run with two quirk settings.
+//!
+//! Exits with 0 when the runs match, 1 when they differ and 2 on errors.
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use chip_8::quirks::Quirks;
+
+mod replay;
+mod traces;
+
+#[derive(Parser)]
+#[command(about = "Finds the first difference between two runs of a CHIP-8 program")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compares two instruction traces
+    Traces {
+        a: PathBuf,
+        b: PathBuf,
+        /// Lines shown before and after the difference
+        #[arg(long, default_value_t = 3)]
+        context: usize,
+    },
+    /// Runs a ROM with two quirk settings side by side
+    Quirks {
+        rom: PathBuf,
+        /// vip, schip or xo-chip
+        #[arg(value_parser = parse_quirks)]
+        a: Quirks,
+        #[arg(value_parser = parse_quirks)]
+        b: Quirks,
+        #[arg(long, default_value_t = 3600)]
+        frames: u32,
+        #[arg(long, default_value_t = 10)]
+        cycles_per_frame: u32,
+        /// Seed of the random number generator of both runs
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Instructions shown before and after the difference
+        #[arg(long, default_value_t = 3)]
+        context: usize,
+        /// Compares the state hash once per frame and only replays the
+        /// first different frame instruction by instruction
+        #[arg(long)]
+        hash_per_frame: bool,
+    },
+}
+
+fn parse_quirks(name: &str) -> Result<Quirks, String> {
+    Quirks::from_name(name)
+        .ok_or_else(|| format!("unknown quirks {}, use vip, schip or xo-chip", name))
+}
+
+fn main() {
+    let result = match Args::parse().command {
+        Command::Traces { a, b, context } => traces::compare(&a, &b, context),
+        Command::Quirks {
+            rom,
+            a,
+            b,
+            frames,
+            cycles_per_frame,
+            seed,
+            context,
+            hash_per_frame,
+        } => replay::compare(
+            &rom,
+            (a, b),
+            &replay::Options {
+                frames,
+                cycles_per_frame,
+                seed,
+                context,
+                hash_per_frame,
+            },
+        ),
+    };
+
+    match result {
+        Ok(false) => {}
+        Ok(true) => std::process::exit(1),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
+    }
+}
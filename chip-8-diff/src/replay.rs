@@ -0,0 +1,176 @@
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use chip_8::cpu::Cpu;
+use chip_8::quirks::Quirks;
+use chip_8::trace::TraceEntry;
+
+use crate::traces;
+
+/// Memory addresses listed before the rest are only counted
+const SHOWN_ADDRESSES: usize = 8;
+
+pub struct Options {
+    pub frames: u32,
+    pub cycles_per_frame: u32,
+    pub seed: u64,
+    pub context: usize,
+    pub hash_per_frame: bool,
+}
+
+/// Runs `rom` once with each of `quirks`, without input, and prints the first
+/// instruction after which the machines differ. Returns whether there is one.
+pub fn compare(rom: &Path, quirks: (Quirks, Quirks), options: &Options) -> io::Result<bool> {
+    let rom = std::fs::read(rom)?;
+    let machine = |quirks| -> io::Result<Cpu> {
+        let mut cpu = Cpu::from_bytes(&rom, StdRng::seed_from_u64(options.seed))?;
+        cpu.set_quirks(quirks);
+        Ok(cpu)
+    };
+    let (mut a, mut b) = (machine(quirks.0)?, machine(quirks.1)?);
+    let mut history = VecDeque::with_capacity(options.context + 1);
+
+    for frame in 0..options.frames {
+        if options.hash_per_frame {
+            let start = (a.clone(), b.clone());
+            run_frame(&mut a, options.cycles_per_frame);
+            run_frame(&mut b, options.cycles_per_frame);
+            if a.state_hash() == b.state_hash() {
+                continue;
+            }
+            // Replay the frame to find the instruction
+            a = start.0;
+            b = start.1;
+        }
+
+        for step in 0..options.cycles_per_frame {
+            let entry = TraceEntry::capture(a.view());
+            a.next();
+            b.next();
+
+            let differences = differences(&a, &b);
+            if !differences.is_empty() {
+                println!("First difference at frame {}, instruction {}", frame, step);
+                for difference in differences {
+                    println!("  {}", difference);
+                }
+                println!("Before:");
+                for entry in history.iter() {
+                    println!("  {}", entry);
+                }
+                println!("> {}", entry);
+                println!("After:");
+                for _ in 0..options.context {
+                    println!("a {}", TraceEntry::capture(a.view()));
+                    println!("b {}", TraceEntry::capture(b.view()));
+                    a.next();
+                    b.next();
+                }
+                return Ok(true);
+            }
+
+            history.push_back(entry);
+            if history.len() > options.context {
+                history.pop_front();
+            }
+        }
+        a.decrease_timers();
+        b.decrease_timers();
+    }
+
+    println!("No difference in {} frames", options.frames);
+    Ok(false)
+}
+
+fn run_frame(cpu: &mut Cpu, cycles: u32) {
+    for _ in 0..cycles {
+        cpu.next();
+    }
+    cpu.decrease_timers();
+}
+
+/// Registers, memory and framebuffer differences between `a` and `b`
+fn differences(a: &Cpu, b: &Cpu) -> Vec<String> {
+    let (a, b) = (a.view(), b.view());
+    let mut differences = traces::differences(&TraceEntry::capture(a), &TraceEntry::capture(b));
+
+    let addresses: Vec<usize> = (0..a.memory().len())
+        .filter(|&address| a.memory()[address] != b.memory()[address])
+        .collect();
+    for &address in addresses.iter().take(SHOWN_ADDRESSES) {
+        differences.push(format!(
+            "memory {:03X}: {:02X} / {:02X}",
+            address,
+            a.memory()[address],
+            b.memory()[address]
+        ));
+    }
+    if addresses.len() > SHOWN_ADDRESSES {
+        differences.push(format!(
+            "and {} more memory addresses",
+            addresses.len() - SHOWN_ADDRESSES
+        ));
+    }
+
+    let pixels = a
+        .display()
+        .get_video_mem()
+        .iter()
+        .zip(b.display().get_video_mem().iter())
+        .filter(|(a, b)| a != b)
+        .count();
+    if pixels > 0 {
+        differences.push(format!("framebuffer: {} pixels differ", pixels));
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use chip_8::cpu::Cpu;
+    use chip_8::quirks::Quirks;
+
+    use crate::replay::differences;
+
+    #[test]
+    fn shift_quirk() {
+        // V1 = 4, V0 = 1, V0 = V1 >> 1 or V0 >> 1, I = 0x300, V0 to [I] which
+        // moves I on the VIP
+        let rom = [0x61, 0x04, 0x60, 0x01, 0x80, 0x16, 0xA3, 0x00, 0xF0, 0x55];
+        let machine = |quirks| {
+            let mut cpu = Cpu::from_bytes(&rom, StdRng::seed_from_u64(0)).unwrap();
+            cpu.set_quirks(quirks);
+            cpu
+        };
+        let (mut a, mut b) = (machine(Quirks::VIP), machine(Quirks::SCHIP));
+        for _ in 0..2 {
+            a.next();
+            b.next();
+        }
+        assert!(differences(&a, &b).is_empty());
+
+        a.next();
+        b.next();
+        assert_eq!(differences(&a, &b), ["V0: 02 / 00", "VF: 00 / 01"]);
+        a.next();
+        b.next();
+        a.next();
+        b.next();
+        assert_eq!(
+            differences(&a, &b),
+            [
+                "I: 0301 / 0300",
+                "V0: 02 / 00",
+                "VF: 00 / 01",
+                "memory 300: 02 / 00"
+            ]
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::io;
+use std::path::Path;
+
+use chip_8::trace::TraceEntry;
+
+/// Prints the first line where the traces `a` and `b` differ, returns
+/// whether there is one.
+pub fn compare(a: &Path, b: &Path, context: usize) -> io::Result<bool> {
+    let a = std::fs::read_to_string(a)?;
+    let b = std::fs::read_to_string(b)?;
+    let a: Vec<&str> = a.lines().collect();
+    let b: Vec<&str> = b.lines().collect();
+
+    let index = match first_difference(&a, &b) {
+        Some(index) => index,
+        None => {
+            println!("The traces are identical ({} lines)", a.len());
+            return Ok(false);
+        }
+    };
+
+    println!("First difference at line {}", index + 1);
+    let entries = (
+        a.get(index).and_then(|line| line.parse().ok()),
+        b.get(index).and_then(|line| line.parse().ok()),
+    );
+    match entries {
+        (Some(a), Some(b)) => {
+            for difference in differences(&a, &b) {
+                println!("  {}", difference);
+            }
+        }
+        _ if a.len() != b.len() && index == a.len().min(b.len()) => {
+            println!("  one trace ends ({} / {} lines)", a.len(), b.len())
+        }
+        _ => {}
+    }
+
+    println!("Before:");
+    for line in &a[index.saturating_sub(context)..index] {
+        println!("  {}", line);
+    }
+    println!("After:");
+    for i in index..index + context + 1 {
+        for (name, trace) in [("a", &a), ("b", &b)] {
+            if let Some(line) = trace.get(i) {
+                println!("{} {}", name, line);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Index of the first line that differs between the traces `a` and `b`
+fn first_difference(a: &[&str], b: &[&str]) -> Option<usize> {
+    (0..a.len().max(b.len())).find(|&i| !same_line(a.get(i), b.get(i)))
+}
+
+/// Lines are the same when they hold the same state, even if the cycle
+/// count or the disassembly are written differently.
+fn same_line(a: Option<&&str>, b: Option<&&str>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => match (a.parse::<TraceEntry>(), b.parse::<TraceEntry>()) {
+            (Ok(a), Ok(b)) => differences(&a, &b).is_empty(),
+            _ => a.trim_end() == b.trim_end(),
+        },
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Every field of the entries that differs, the cycle count is ignored.
+pub fn differences(a: &TraceEntry, b: &TraceEntry) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut word = |name: &str, a: u16, b: u16| {
+        if a != b {
+            differences.push(format!("{}: {:04X} / {:04X}", name, a, b));
+        }
+    };
+    word("PC", a.program_counter, b.program_counter);
+    word("opcode", a.opcode, b.opcode);
+    word("I", a.i, b.i);
+
+    let mut byte = |name: &str, a: u8, b: u8| {
+        if a != b {
+            differences.push(format!("{}: {:02X} / {:02X}", name, a, b));
+        }
+    };
+    for (index, (a, b)) in a.v.iter().zip(b.v.iter()).enumerate() {
+        byte(&format!("V{:X}", index), *a, *b);
+    }
+    byte("SP", a.stack_pointer, b.stack_pointer);
+    byte("DT", a.delay_timer, b.delay_timer);
+    byte("ST", a.sound_timer, b.sound_timer);
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use chip_8::cpu::Cpu;
+    use chip_8::trace::TraceEntry;
+
+    use crate::traces::{differences, first_difference, same_line};
+
+    /// The trace of V0 = 1, V1 = 2, I = 0x300, V0 += V1 and a jump to itself
+    fn trace() -> Vec<TraceEntry> {
+        let rom = [0x60, 0x01, 0x61, 0x02, 0xA3, 0x00, 0x80, 0x14, 0x12, 0x08];
+        let mut cpu = Cpu::from_bytes(&rom, StepRng::new(0, 1)).unwrap();
+        (0..6)
+            .map(|_| {
+                let entry = TraceEntry::capture(cpu.view());
+                cpu.next();
+                entry
+            })
+            .collect()
+    }
+
+    fn lines(trace: &[TraceEntry]) -> Vec<String> {
+        trace.iter().map(|entry| entry.to_string()).collect()
+    }
+
+    #[test]
+    fn first_different_line() {
+        let a = lines(&trace());
+        let mut changed = trace();
+        changed[4].v[0] = 4;
+        let b = lines(&changed);
+        let a: Vec<&str> = a.iter().map(String::as_str).collect();
+        let b: Vec<&str> = b.iter().map(String::as_str).collect();
+
+        assert_eq!(first_difference(&a, &a), None);
+        assert_eq!(first_difference(&a, &b), Some(4));
+        assert_eq!(first_difference(&a, &a[..3]), Some(3));
+    }
+
+    #[test]
+    fn same_lines() {
+        let entry = trace()[3];
+        let line = entry.to_string();
+        // Another cycle count and disassembly are the same state
+        let later = TraceEntry {
+            cycle: entry.cycle + 100,
+            ..entry
+        };
+        let state = later.to_string();
+        let state = state.split(" ; ").next().unwrap();
+        assert!(same_line(Some(&line.as_str()), Some(&state)));
+
+        let other = TraceEntry { i: 0x301, ..entry }.to_string();
+        assert!(!same_line(Some(&line.as_str()), Some(&other.as_str())));
+        assert!(same_line(Some(&"text "), Some(&"text")));
+        assert!(!same_line(Some(&line.as_str()), None));
+        assert!(same_line(None, None));
+    }
+
+    #[test]
+    fn entry_differences() {
+        let entry = trace()[4];
+        let other = TraceEntry {
+            cycle: 0,
+            i: 0x302,
+            v: [7; 16],
+            ..entry
+        };
+        let mut expected = vec![String::from("I: 0300 / 0302")];
+        for (index, value) in entry.v.iter().enumerate() {
+            expected.push(format!("V{:X}: {:02X} / 07", index, value));
+        }
+        assert_eq!(differences(&entry, &other), expected);
+        assert!(differences(&entry, &TraceEntry { cycle: 9, ..entry }).is_empty());
+    }
+}
@@ -0,0 +1,276 @@
+//! Octo cartridges, GIF images carrying a program and its options.
+//!
+//! The payload is a 32 bit big endian length followed by that many bytes of
+//! UTF-8 JSON, `{"program": "...", "options": {...}}`. Every byte is spread
+//! over four pixels, two bits per pixel (the low bits of the palette index,
+//! most significant pair first), across all the frames of the image. The
+//! rest of the palette index only selects the colour of the label.
+//!
+//! `program` is Octo source code, assembled by [`crate::octo`]. Cartridges
+//! exported by this interpreter store their ROM as a `main` label followed by
+//! byte literals.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use chip_8::display::{Display, HEIGHT, WIDTH};
+use chip_8::quirks::Quirks;
+
+use crate::octo;
+
+const LABEL_WIDTH: u16 = 128;
+const LABEL_HEIGHT: u16 = 64;
+const LABEL_SCALE: usize = LABEL_WIDTH as usize / WIDTH;
+/// Payload bytes stored in a single frame
+const BYTES_PER_FRAME: usize = LABEL_WIDTH as usize * LABEL_HEIGHT as usize / 4;
+
+/// The options Octo stores in a cartridge, the ones this interpreter doesn't
+/// support are ignored.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Options {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tickrate: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shift_quirks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_store_quirks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clip_quirks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jump_quirks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub v_blank_quirks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logic_quirks: Option<bool>,
+}
+
+impl Options {
+    pub fn apply_quirks(&self, quirks: &mut Quirks) {
+        if let Some(shift) = self.shift_quirks {
+            quirks.shift = shift;
+        }
+        if let Some(load_store) = self.load_store_quirks {
+            quirks.memory_leave_i_unchanged = load_store;
+        }
+        if let Some(clip) = self.clip_quirks {
+            quirks.wrap = !clip;
+        }
+        if let Some(jump) = self.jump_quirks {
+            quirks.jump = jump;
+        }
+        if let Some(vblank) = self.v_blank_quirks {
+            quirks.vblank = vblank;
+        }
+        if let Some(logic) = self.logic_quirks {
+            quirks.logic = logic;
+        }
+    }
+
+    pub fn from_quirks(quirks: Quirks) -> Options {
+        Options {
+            shift_quirks: Some(quirks.shift),
+            load_store_quirks: Some(quirks.memory_leave_i_unchanged),
+            clip_quirks: Some(!quirks.wrap),
+            jump_quirks: Some(quirks.jump),
+            v_blank_quirks: Some(quirks.vblank),
+            logic_quirks: Some(quirks.logic),
+            ..Options::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Payload {
+    program: String,
+    #[serde(default)]
+    options: Options,
+}
+
+pub struct Cartridge {
+    pub rom: Vec<u8>,
+    pub options: Options,
+}
+
+pub fn is_cartridge<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"))
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Cartridge> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(File::open(path)?).map_err(invalid_data)?;
+
+    let mut data = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(invalid_data)? {
+        for pixels in frame.buffer.chunks_exact(4) {
+            data.push(
+                pixels
+                    .iter()
+                    .fold(0, |byte, pixel| byte << 2 | (pixel & 0x3)),
+            );
+        }
+    }
+
+    if data.len() < 4 {
+        return Err(invalid_data("the image holds no cartridge"));
+    }
+    let length = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let json = data
+        .get(4..4 + length)
+        .ok_or_else(|| invalid_data("the cartridge is truncated"))?;
+    let payload: Payload = serde_json::from_slice(json)?;
+
+    Ok(Cartridge {
+        rom: octo::assemble(&payload.program).map_err(invalid_data)?,
+        options: payload.options,
+    })
+}
+
+/// Writes `rom` and `options` as a cartridge, `label` is the picture on it.
+pub fn save<P: AsRef<Path>>(
+    path: P,
+    rom: &[u8],
+    options: Options,
+    label: &Display,
+    colors: ([u8; 3], [u8; 3]),
+) -> Result<()> {
+    let payload = Payload {
+        program: program_source(rom),
+        options,
+    };
+    write(path, &payload, label, colors)
+}
+
+fn write<P: AsRef<Path>>(
+    path: P,
+    payload: &Payload,
+    label: &Display,
+    colors: ([u8; 3], [u8; 3]),
+) -> Result<()> {
+    let payload = serde_json::to_vec(payload)?;
+    let mut data = (payload.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(&payload);
+    let frames = data.len().div_ceil(BYTES_PER_FRAME);
+    data.resize(frames * BYTES_PER_FRAME, 0);
+
+    // The label shade selects one of two groups of four identical colours,
+    // the data bits select the colour inside the group.
+    let (back, front) = colors;
+    let palette: Vec<u8> = [back, front]
+        .iter()
+        .flat_map(|color| color.repeat(4))
+        .collect();
+
+    let mut shades = vec![0; LABEL_WIDTH as usize * LABEL_HEIGHT as usize];
+    for (i, shade) in shades.iter_mut().enumerate() {
+        let x = i % LABEL_WIDTH as usize / LABEL_SCALE;
+        let y = i / LABEL_WIDTH as usize / LABEL_SCALE;
+        if y < HEIGHT && label.is_pixel_set(x, y) {
+            *shade = 1 << 2;
+        }
+    }
+
+    let mut encoder = gif::Encoder::new(File::create(path)?, LABEL_WIDTH, LABEL_HEIGHT, &palette)
+        .map_err(invalid_data)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(invalid_data)?;
+    for chunk in data.chunks(BYTES_PER_FRAME) {
+        let mut pixels = shades.clone();
+        for (i, byte) in chunk.iter().enumerate() {
+            for j in 0..4 {
+                pixels[i * 4 + j] |= byte >> (6 - j * 2) & 0x3;
+            }
+        }
+
+        let frame = gif::Frame {
+            width: LABEL_WIDTH,
+            height: LABEL_HEIGHT,
+            buffer: Cow::Owned(pixels),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame).map_err(invalid_data)?;
+    }
+
+    Ok(())
+}
+
+/// Octo source for a ROM, a `main` label followed by the raw bytes.
+fn program_source(rom: &[u8]) -> String {
+    let mut source = String::from(": main\n");
+    for line in rom.chunks(16) {
+        let bytes: Vec<String> = line.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+        source.push_str(&bytes.join(" "));
+        source.push('\n');
+    }
+    source
+}
+
+fn invalid_data<E>(error: E) -> Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    Error::new(ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use chip_8::display::Display;
+    use chip_8::quirks::Quirks;
+
+    use crate::cartridge::{self, Options};
+
+    #[test]
+    fn round_trip() {
+        let rom: Vec<u8> = (0..=255).cycle().take(3000).collect();
+        let mut label = Display::new();
+        label.draw(0, 0, &[0xFF, 0x81, 0xFF]);
+        let path = std::env::temp_dir().join("chip-8-round-trip.gif");
+        let options = Options {
+            tickrate: Some(30),
+            ..Options::from_quirks(Quirks::VIP)
+        };
+        cartridge::save(&path, &rom, options, &label, ([0; 3], [0xFF; 3])).unwrap();
+
+        let cartridge = cartridge::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cartridge.rom, rom);
+        assert_eq!(cartridge.options.tickrate, Some(30));
+        let mut quirks = Quirks::default();
+        cartridge.options.apply_quirks(&mut quirks);
+        assert_eq!(quirks, Quirks::VIP);
+    }
+
+    /// A cartridge holding the Octo source of `fixtures/bounce.8o`
+    #[test]
+    fn octo_source() {
+        let cartridge =
+            cartridge::load(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/bounce.gif")).unwrap();
+        assert_eq!(
+            cartridge.rom,
+            [
+                0x12, 0x2A, 0x60, 0xF0, 0xF0, 0x60, // jump main, ball
+                0xA2, 0x02, 0xD0, 0x14, 0x80, 0x24, 0x81, 0x34, // move
+                0x40, 0x3C, 0x62, 0xFF, 0x40, 0x00, 0x62, 0x01, //
+                0x6F, 0x1C, 0x8F, 0x17, 0x3F, 0x01, 0x12, 0x22, 0x63, 0xFF, 0x12, 0x26, //
+                0x41, 0x00, 0x63, 0x01, 0xD0, 0x14, 0x00, 0xEE, //
+                0xA2, 0x02, 0x62, 0x01, 0x63, 0x01, 0xD0, 0x14, // main
+                0x6F, 0x02, 0xFF, 0x15, 0xFF, 0x07, 0x3F, 0x00, 0x12, 0x36, //
+                0x22, 0x06, 0x12, 0x32,
+            ]
+        );
+        assert_eq!(cartridge.options.tickrate, Some(20));
+        assert_eq!(cartridge.options.fill_color.as_deref(), Some("#FFCC00"));
+    }
+}
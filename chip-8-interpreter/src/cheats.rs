@@ -0,0 +1,128 @@
+//! Cheat codes of every ROM, kept in a TOML file under the SHA-1 of the ROM
+//! they were found for:
+//!
+//! ```toml
+//! [[a3c5e8b8f3a1e3d5d8c4e0b6f2a7c9d1e4b2f6a8]]
+//! name = "Infinite lives"
+//! address = 0x2F4
+//! value = 3
+//! frozen = true    # written every frame, once when false
+//! ```
+//!
+//! Cheats are found and added with the control server, see `control.rs`.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use chip_8::cheat::{Cheat, Search};
+
+use crate::Machine;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub address: u16,
+    pub value: u8,
+    #[serde(default)]
+    pub frozen: bool,
+}
+
+impl Entry {
+    pub fn cheat(&self) -> Cheat {
+        Cheat {
+            address: self.address,
+            value: self.value,
+            frozen: self.frozen,
+        }
+    }
+}
+
+/// The cheats of the running ROM and the search in progress
+pub struct Cheats {
+    path: PathBuf,
+    hash: String,
+    entries: Vec<Entry>,
+    pub search: Option<Search>,
+}
+
+impl Cheats {
+    /// The cheats saved in `path` for `rom`, none when the file doesn't
+    /// exist
+    pub fn load<P: Into<PathBuf>>(path: P, rom: &[u8]) -> Result<Cheats, String> {
+        let path = path.into();
+        let hash = sha1_smol::Sha1::from(rom).digest().to_string();
+        let entries = read(&path)?.remove(&hash).unwrap_or_default();
+        Ok(Cheats {
+            path,
+            hash,
+            entries,
+            search: None,
+        })
+    }
+
+    /// Switches to the cheats saved for `rom` when the program changes, the
+    /// unsaved cheats of the previous one are dropped
+    pub fn reload(&mut self, rom: &[u8]) -> Result<(), String> {
+        self.hash = sha1_smol::Sha1::from(rom).digest().to_string();
+        self.entries = Vec::new();
+        self.search = None;
+        self.entries = read(&self.path)?.remove(&self.hash).unwrap_or_default();
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Adds a cheat, replacing the one at the same address
+    pub fn add(&mut self, entry: Entry) {
+        self.remove(entry.address);
+        self.entries.push(entry);
+    }
+
+    /// Returns whether there was a cheat at `address`
+    pub fn remove(&mut self, address: u16) -> bool {
+        let count = self.entries.len();
+        self.entries.retain(|entry| entry.address != address);
+        self.entries.len() != count
+    }
+
+    /// Applies every cheat, when the ROM starts
+    pub fn apply_all(&self, cpu: &mut Machine) {
+        for entry in &self.entries {
+            entry.cheat().apply(&mut cpu.poke());
+        }
+    }
+
+    /// Applies the frozen cheats, before every frame
+    pub fn apply_frozen(&self, cpu: &mut Machine) {
+        for entry in self.entries.iter().filter(|entry| entry.frozen) {
+            entry.cheat().apply(&mut cpu.poke());
+        }
+    }
+
+    /// Writes the cheats of this ROM to the file, keeping the other ROMs'
+    pub fn save(&self) -> Result<(), String> {
+        let mut roms = read(&self.path)?;
+        if self.entries.is_empty() {
+            roms.remove(&self.hash);
+        } else {
+            roms.insert(self.hash.clone(), self.entries.clone());
+        }
+        let text = toml::to_string(&roms).map_err(|error| error.to_string())?;
+        std::fs::write(&self.path, text)
+            .map_err(|error| format!("cannot write {}: {}", self.path.display(), error))
+    }
+}
+
+/// Cheats of every ROM by SHA-1
+fn read(path: &Path) -> Result<BTreeMap<String, Vec<Entry>>, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(format!("cannot read {}: {}", path.display(), error)),
+    };
+    toml::from_str(&text).map_err(|error| format!("invalid cheats {}: {}", path.display(), error))
+}
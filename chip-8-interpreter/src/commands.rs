@@ -0,0 +1,176 @@
+//! The subcommands that don't open a window.
+
+use chip_8::disasm::Instruction;
+use chip_8::display;
+use chip_8::quirks::Quirks;
+use chip_8::symbols::Symbols;
+use chip_8::trace::TraceEntry;
+
+use crate::{database, format_color, load_symbols, parse_quirks, read_rom, Error, LoadArgs};
+
+#[derive(clap::Args)]
+pub struct HeadlessArgs {
+    rom: String,
+    /// Frames run, 60 per second
+    #[arg(long, default_value_t = 600)]
+    frames: u32,
+    /// The tickrate of a cartridge, or 10
+    #[arg(long)]
+    cycles_per_frame: Option<i32>,
+    /// vip, schip or xo-chip
+    #[arg(long, value_parser = parse_quirks)]
+    quirks: Option<Quirks>,
+    /// Seed of the random number generator
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    #[command(flatten)]
+    load: LoadArgs,
+    /// Labels and data regions, to locate a fault
+    #[arg(long)]
+    symbols: Option<String>,
+    /// Prints the screen after the last frame
+    #[arg(long)]
+    screen: bool,
+}
+
+/// Prints every two bytes of the ROM in `path` as an instruction, and the
+/// data regions of the symbols byte by byte
+pub fn disasm(path: &str, load_address: u16, symbols: Option<&str>) -> Result<(), Error> {
+    let (rom, _) = read_rom(path)?;
+    let symbols = match symbols {
+        Some(path) => load_symbols(path)?,
+        None => Symbols::new(),
+    };
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = (load_address as usize + offset) as u16;
+        let data = symbols.data(address);
+        let label = symbols.label(address).or_else(|| {
+            data.as_ref()
+                .filter(|(range, _)| range.start == address)
+                .map(|(_, label)| *label)
+        });
+        if let Some(label) = label {
+            println!("{}:", label);
+        }
+        match rom.get(offset..offset + 2) {
+            Some(&[high, low]) if data.is_none() => {
+                println!(
+                    "{:03X}  {:02X}{:02X}  {}",
+                    address,
+                    high,
+                    low,
+                    symbols.instruction(Instruction::decode(u16::from_be_bytes([high, low])))
+                );
+                offset += 2;
+            }
+            _ => {
+                println!(
+                    "{:03X}  {:02X}    DB {:#04X}",
+                    address, rom[offset], rom[offset]
+                );
+                offset += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn info(path: &str, database: Option<&str>) -> Result<(), Error> {
+    let (rom, cartridge) = read_rom(path)?;
+    println!("Size       {} bytes", rom.len());
+    println!("SHA-1      {}", sha1_smol::Sha1::from(&rom).digest());
+
+    if let Some(options) = cartridge {
+        let mut quirks = Quirks::default();
+        options.apply_quirks(&mut quirks);
+        println!("Cartridge");
+        if let Some(tickrate) = options.tickrate {
+            println!("  Tickrate {}", tickrate);
+        }
+        if let (Some(back), Some(front)) = (&options.background_color, &options.fill_color) {
+            println!("  Colours  {} {}", back, front);
+        }
+        println!("  Quirks   {:?}", quirks);
+    }
+
+    if let Some(directory) = database {
+        let database = database::Database::load(directory)
+            .map_err(|error| format!("cannot load the ROM database {}: {}", directory, error))?;
+        match database.lookup(&rom) {
+            Some(settings) => {
+                println!("Database   {}", settings.title);
+                if let Some(platform) = settings.platform {
+                    println!("  Platform {}", platform);
+                }
+                if let Some(tickrate) = settings.tickrate {
+                    println!("  Tickrate {}", tickrate);
+                }
+                if let Some((back, front)) = settings.colors {
+                    println!("  Colours  {} {}", format_color(back), format_color(front));
+                }
+                if let Some(quirks) = settings.quirks {
+                    println!("  Quirks   {:?}", quirks);
+                }
+            }
+            None => println!("Database   not found"),
+        }
+    }
+    Ok(())
+}
+
+/// Runs the ROM without input, then prints the state of the machine. Fails
+/// when the program stops on a fault.
+pub fn headless(args: &HeadlessArgs) -> Result<(), Error> {
+    let (rom, cartridge) = read_rom(&args.rom)?;
+    let mut cpu = args
+        .load
+        .loader()?
+        .read(rom.as_slice())
+        .map_err(|error| format!("cannot load {}: {}", args.rom, error))?;
+    cpu.seed_rng(args.seed);
+
+    let mut quirks = cpu.quirks();
+    if let Some(options) = &cartridge {
+        options.apply_quirks(&mut quirks);
+    }
+    cpu.set_quirks(args.quirks.unwrap_or(quirks));
+    let cycles_per_frame = args
+        .cycles_per_frame
+        .or_else(|| cartridge.and_then(|options| options.tickrate))
+        .unwrap_or(10);
+
+    for _ in 0..args.frames {
+        for _ in 0..cycles_per_frame {
+            cpu.next();
+        }
+        cpu.decrease_timers();
+    }
+
+    println!("{}", TraceEntry::capture(cpu.view()));
+    if args.screen {
+        for y in 0..display::HEIGHT {
+            let row: String = (0..display::WIDTH)
+                .map(|x| {
+                    if cpu.get_display().is_pixel_set(x, y) {
+                        '#'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            println!("{}", row);
+        }
+    }
+    match cpu.fault() {
+        Some(fault) => {
+            let symbols = match &args.symbols {
+                Some(path) => load_symbols(path)?,
+                None => Symbols::new(),
+            };
+            let location = symbols.describe(cpu.view().program_counter());
+            Err(format!("the program stopped at {}: {}", location, fault).into())
+        }
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,838 @@
+//! JSON-RPC 2.0 control server.
+//!
+//! Clients connect over localhost TCP (or a Unix socket) and send one request
+//! per line, every request gets a response on its own line and notifications
+//! (requests without an `id`) get none. Requests are queued and run by the
+//! main loop between frames, so they never race with the emulation.
+//!
+//! | method         | params                        | result                        |
+//! |----------------|-------------------------------|-------------------------------|
+//! | `pause`        |                               | `{"paused": true}`            |
+//! | `resume`       |                               | `{"paused": false}`           |
+//! | `step`         | `{"count": 1}`                | registers                     |
+//! | `press_key`    | `{"key": 5}`                  | `null`                        |
+//! | `release_key`  | `{"key": 5}`                  | `null`                        |
+//! | `read_memory`  | `{"address": 512, "length": 2}` | `{"data": [18, 52]}`        |
+//! | `write_memory` | `{"address": 512, "data": [0]}` | `null`                      |
+//! | `registers`    |                               | `{"v": [...], "i": 0, "pc": 512, "dt": 0, "st": 0, "stack": [...]}` |
+//! | `set_register` | `{"name": "v3", "value": 7}`  | `null`                        |
+//! | `framebuffer`  |                               | `{"width": 64, "height": 32, "pixels": [...]}` |
+//! | `save_state`   | `{"slot": "boss"}`            | `null`                        |
+//! | `load_state`   | `{"slot": "boss"}`            | `null`                        |
+//! | `load_rom`     | `{"path": "game.ch8"}`        | `null`                        |
+//! | `cheat_search_start` |                         | `{"count": 4096}`             |
+//! | `cheat_search` | `{"compare": "decreased"}` or `{"value": 3}` | `{"count": 2, "candidates": [{"address": 756, "value": 2}]}` |
+//! | `cheat_add`    | `{"name": "Lives", "address": 756, "value": 3, "frozen": true}` | `null` |
+//! | `cheat_remove` | `{"address": 756}`            | `{"removed": true}`           |
+//! | `cheats`       |                               | `[{"name": "Lives", ...}]`    |
+//! | `cheat_save`   |                               | `null`                        |
+//!
+//! `step` runs at most 100000 instructions, traced and profiled like the
+//! others. `load_rom` keeps the quirks of the running program, a cartridge
+//! brings its own quirks, tickrate and colours, and the reset hotkey restarts
+//! the new one.
+//!
+//! `set_register` accepts `v0` to `vf`, `i`, `pc`, `dt` and `st`. With
+//! symbols, the registers also hold `"location": "draw_player+0x4"`, the
+//! label of the program counter.
+//!
+//! `cheat_search` compares memory to the previous search with `equal`,
+//! `changed`, `increased` or `decreased` and lists at most 32 candidates.
+//! `cheat_add` writes the value at once, and every frame when frozen.
+//! Cheats are off during netplay, and so are `pause`, `step`,
+//! `write_memory`, `set_register`, `load_state` and `load_rom`.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use chip_8::cheat::{Comparison, Search};
+use chip_8::display;
+use chip_8::rom::RomLoader;
+use chip_8::symbols::Symbols;
+
+use crate::cartridge;
+use crate::cheats::{self, Cheats};
+use crate::{reload_rom, Instruments, Machine};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// Candidates listed by `cheat_search`
+const CANDIDATES_SHOWN: usize = 32;
+/// Instructions a `step` request runs at most, the window freezes meanwhile
+const MAX_STEPS: u32 = 100_000;
+/// Methods that would desynchronize the netplay peers
+const LOCAL_ONLY: &[&str] = &[
+    "pause",
+    "step",
+    "write_memory",
+    "set_register",
+    "load_state",
+    "load_rom",
+];
+
+#[derive(Deserialize)]
+pub struct ControlConfig {
+    /// Localhost TCP address, e.g. `127.0.0.1:6502`
+    pub address: Option<String>,
+    /// Path of a Unix socket, used instead of `address`
+    pub socket: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+struct Error {
+    code: i64,
+    message: String,
+}
+
+impl Error {
+    fn new(code: i64, message: impl Into<String>) -> Error {
+        Error {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+type Reply = Sender<String>;
+
+pub struct ControlServer {
+    requests: Receiver<(Request, Reply)>,
+    paused: bool,
+    keys: u16,
+    states: HashMap<String, Machine>,
+    loader: RomLoader,
+    symbols: Symbols,
+    /// Program of the last `load_rom` and its cartridge options
+    loaded: Option<(Vec<u8>, Option<cartridge::Options>)>,
+    /// Refuse the [`LOCAL_ONLY`] methods
+    netplay: bool,
+}
+
+impl ControlServer {
+    /// `loader` loads the programs sent with `load_rom`, `symbols` name the
+    /// program counter in `registers`. During `netplay` the methods changing
+    /// the machine are refused.
+    pub fn start(
+        config: &ControlConfig,
+        loader: RomLoader,
+        symbols: Symbols,
+        netplay: bool,
+    ) -> std::io::Result<ControlServer> {
+        let (sender, requests) = channel();
+
+        match (&config.socket, &config.address) {
+            #[cfg(unix)]
+            (Some(path), _) => {
+                use std::os::unix::fs::FileTypeExt;
+                use std::os::unix::net::UnixListener;
+
+                // A socket left by an earlier run, anything else stays
+                let stale = std::fs::symlink_metadata(path)
+                    .is_ok_and(|metadata| metadata.file_type().is_socket());
+                if stale {
+                    std::fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                thread::spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        if let Ok(writer) = stream.try_clone() {
+                            serve(stream, writer, sender.clone());
+                        }
+                    }
+                });
+            }
+            (_, Some(address)) => {
+                // Clients can read and write the whole machine, other hosts
+                // must not reach it
+                for socket in address.to_socket_addrs()? {
+                    if !socket.ip().is_loopback() {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!("{} is not a localhost address", socket.ip()),
+                        ));
+                    }
+                }
+                let listener = TcpListener::bind(address.as_str())?;
+                thread::spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        if let Ok(writer) = stream.try_clone() {
+                            serve(stream, writer, sender.clone());
+                        }
+                    }
+                });
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "the control server needs an address or a socket",
+                ))
+            }
+        }
+
+        Ok(ControlServer {
+            requests,
+            paused: false,
+            keys: 0,
+            states: HashMap::new(),
+            loader,
+            symbols,
+            loaded: None,
+            netplay,
+        })
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Keys currently held down by clients
+    pub fn keys(&self) -> u16 {
+        self.keys
+    }
+
+    /// Runs every queued request against `cpu`, returns the program
+    /// `load_rom` replaced it with, if any, and its cartridge options.
+    pub fn poll(
+        &mut self,
+        cpu: &mut Machine,
+        instruments: &mut Instruments,
+        mut cheats: Option<&mut Cheats>,
+    ) -> Option<(Vec<u8>, Option<cartridge::Options>)> {
+        while let Ok((request, reply)) = self.requests.try_recv() {
+            let result = if request.method.starts_with("cheat") {
+                match cheats.as_mut() {
+                    Some(cheats) => cheat(cpu, cheats, &request.method, request.params),
+                    None => Err(Error::new(SERVER_ERROR, "cheats are off during netplay")),
+                }
+            } else {
+                self.execute(cpu, instruments, &request.method, request.params)
+            };
+            let response = match result {
+                Ok(result) => json!({"jsonrpc": "2.0", "id": request.id, "result": result}),
+                Err(error) => error_response(request.id, error),
+            };
+            let _ = reply.send(response.to_string());
+        }
+        self.loaded.take()
+    }
+
+    fn execute(
+        &mut self,
+        cpu: &mut Machine,
+        instruments: &mut Instruments,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, Error> {
+        if self.netplay && LOCAL_ONLY.contains(&method) {
+            return Err(Error::new(
+                SERVER_ERROR,
+                format!("{} is off during netplay", method),
+            ));
+        }
+        match method {
+            "pause" => {
+                self.paused = true;
+                Ok(json!({ "paused": true }))
+            }
+            "resume" => {
+                self.paused = false;
+                Ok(json!({ "paused": false }))
+            }
+            "step" => {
+                #[derive(Deserialize)]
+                struct Step {
+                    #[serde(default = "one")]
+                    count: u32,
+                }
+                fn one() -> u32 {
+                    1
+                }
+
+                let Step { count } = parse_params(params)?;
+                if count > MAX_STEPS {
+                    return Err(Error::new(
+                        INVALID_PARAMS,
+                        format!("count must be at most {}", MAX_STEPS),
+                    ));
+                }
+                for _ in 0..count {
+                    instruments.step(cpu);
+                }
+                Ok(registers(cpu, &self.symbols))
+            }
+            "press_key" | "release_key" => {
+                #[derive(Deserialize)]
+                struct Key {
+                    key: u8,
+                }
+
+                let Key { key } = parse_params(params)?;
+                if key > 0xF {
+                    return Err(Error::new(INVALID_PARAMS, "key must be between 0 and 15"));
+                }
+                if method == "press_key" {
+                    self.keys |= 1 << key;
+                } else {
+                    self.keys &= !(1 << key);
+                }
+                Ok(Value::Null)
+            }
+            "read_memory" => {
+                #[derive(Deserialize)]
+                struct ReadMemory {
+                    address: usize,
+                    length: usize,
+                }
+
+                let ReadMemory { address, length } = parse_params(params)?;
+                let data = address
+                    .checked_add(length)
+                    .and_then(|end| cpu.view().memory_range(address..end))
+                    .ok_or_else(|| Error::new(INVALID_PARAMS, "range out of memory"))?;
+                Ok(json!({ "data": data }))
+            }
+            "write_memory" => {
+                #[derive(Deserialize)]
+                struct WriteMemory {
+                    address: usize,
+                    data: Vec<u8>,
+                }
+
+                let WriteMemory { address, data } = parse_params(params)?;
+                let mut poke = cpu.poke();
+                let memory = address
+                    .checked_add(data.len())
+                    .and_then(|end| poke.memory_range(address..end))
+                    .ok_or_else(|| Error::new(INVALID_PARAMS, "range out of memory"))?;
+                memory.copy_from_slice(&data);
+                Ok(Value::Null)
+            }
+            "registers" => Ok(registers(cpu, &self.symbols)),
+            "set_register" => {
+                #[derive(Deserialize)]
+                struct Register {
+                    name: String,
+                    value: u16,
+                }
+
+                let Register { name, value } = parse_params(params)?;
+                let name = name.to_lowercase();
+                let byte = || match value {
+                    0..=0xFF => Ok(value as u8),
+                    _ => Err(Error::new(
+                        INVALID_PARAMS,
+                        format!("{} must be between 0 and 255", name),
+                    )),
+                };
+                match name.as_str() {
+                    "i" => cpu.poke().set_index_register(value),
+                    "pc" => cpu.poke().set_program_counter(value),
+                    "dt" => cpu.poke().set_delay_timer(byte()?),
+                    "st" => cpu.poke().set_sound_timer(byte()?),
+                    _ => {
+                        let index = name
+                            .strip_prefix('v')
+                            .and_then(|index| usize::from_str_radix(index, 16).ok())
+                            .filter(|index| *index < 16)
+                            .ok_or_else(|| Error::new(INVALID_PARAMS, "unknown register"))?;
+                        cpu.poke().registers()[index] = byte()?;
+                    }
+                }
+                Ok(Value::Null)
+            }
+            "framebuffer" => Ok(json!({
+                "width": display::WIDTH,
+                "height": display::HEIGHT,
+                "pixels": &cpu.get_display().get_video_mem()[..],
+            })),
+            "save_state" | "load_state" => {
+                #[derive(Deserialize)]
+                struct Slot {
+                    slot: String,
+                }
+
+                let Slot { slot } = parse_params(params)?;
+                if method == "save_state" {
+                    self.states.insert(slot, cpu.clone());
+                } else {
+                    *cpu = self
+                        .states
+                        .get(&slot)
+                        .ok_or_else(|| Error::new(INVALID_PARAMS, "empty save slot"))?
+                        .clone();
+                }
+                Ok(Value::Null)
+            }
+            "load_rom" => {
+                #[derive(Deserialize)]
+                struct Rom {
+                    path: String,
+                }
+
+                let Rom { path } = parse_params(params)?;
+                let (reloaded, rom, options) = reload_rom(&path, &self.loader, cpu)
+                    .map_err(|error| Error::new(SERVER_ERROR, error.to_string()))?;
+                *cpu = reloaded;
+                self.loaded = Some((rom, options));
+                Ok(Value::Null)
+            }
+            _ => Err(Error::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {}", method),
+            )),
+        }
+    }
+}
+
+fn cheat(
+    cpu: &mut Machine,
+    cheats: &mut Cheats,
+    method: &str,
+    params: Value,
+) -> Result<Value, Error> {
+    match method {
+        "cheat_search_start" => {
+            cheats.search = Some(Search::new(cpu.view()));
+            Ok(json!({ "count": cpu.view().memory().len() }))
+        }
+        "cheat_search" => {
+            #[derive(Deserialize)]
+            struct CheatSearch {
+                compare: Option<String>,
+                value: Option<u8>,
+            }
+
+            let CheatSearch { compare, value } = parse_params(params)?;
+            let comparison = match (compare.as_deref(), value) {
+                (_, Some(value)) => Comparison::Value(value),
+                (Some("equal"), None) => Comparison::Equal,
+                (Some("changed"), None) => Comparison::Changed,
+                (Some("increased"), None) => Comparison::Increased,
+                (Some("decreased"), None) => Comparison::Decreased,
+                _ => {
+                    return Err(Error::new(
+                        INVALID_PARAMS,
+                        "compare must be equal, changed, increased or decreased",
+                    ))
+                }
+            };
+            let search = cheats.search.get_or_insert_with(|| Search::new(cpu.view()));
+            search.narrow(cpu.view(), comparison);
+            let memory = cpu.view().memory();
+            let candidates: Vec<Value> = search
+                .candidates()
+                .take(CANDIDATES_SHOWN)
+                .map(|address| json!({"address": address, "value": memory[address as usize]}))
+                .collect();
+            Ok(json!({ "count": search.count(), "candidates": candidates }))
+        }
+        "cheat_add" => {
+            let entry: cheats::Entry = parse_params(params)?;
+            entry.cheat().apply(&mut cpu.poke());
+            cheats.add(entry);
+            Ok(Value::Null)
+        }
+        "cheat_remove" => {
+            #[derive(Deserialize)]
+            struct Address {
+                address: u16,
+            }
+
+            let Address { address } = parse_params(params)?;
+            Ok(json!({ "removed": cheats.remove(address) }))
+        }
+        "cheats" => Ok(json!(cheats.entries())),
+        "cheat_save" => {
+            cheats
+                .save()
+                .map_err(|error| Error::new(SERVER_ERROR, error))?;
+            Ok(Value::Null)
+        }
+        _ => Err(Error::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method {}", method),
+        )),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, Error> {
+    // Methods without required params accept a missing params member
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|error| Error::new(INVALID_PARAMS, error.to_string()))
+}
+
+fn registers(cpu: &Machine, symbols: &Symbols) -> Value {
+    let view = cpu.view();
+    let mut registers = json!({
+        "v": view.registers(),
+        "i": view.index_register(),
+        "pc": view.program_counter(),
+        "dt": view.delay_timer(),
+        "st": view.sound_timer(),
+        "stack": view.call_stack(),
+    });
+    if let Some(location) = symbols.locate(view.program_counter()) {
+        registers["location"] = json!(location.to_string());
+    }
+    registers
+}
+
+fn error_response(id: Value, error: Error) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": error.code, "message": error.message},
+    })
+}
+
+/// Reads requests from a client on its own thread and forwards them to the
+/// main loop, waiting for each response before reading the next line.
+fn serve<S, W>(stream: S, mut writer: W, requests: Sender<(Request, Reply)>)
+where
+    S: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        let (reply, responses) = channel();
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = match serde_json::from_str::<Value>(&line) {
+                Err(error) => {
+                    error_response(Value::Null, Error::new(PARSE_ERROR, error.to_string()))
+                        .to_string()
+                }
+                Ok(value) => {
+                    let notification = value.get("id").is_none();
+                    match serde_json::from_value::<Request>(value) {
+                        Err(error) => error_response(
+                            Value::Null,
+                            Error::new(INVALID_REQUEST, error.to_string()),
+                        )
+                        .to_string(),
+                        Ok(request) => {
+                            if requests.send((request, reply.clone())).is_err() {
+                                break;
+                            }
+                            match responses.recv() {
+                                Ok(_) if notification => continue,
+                                Ok(response) => response,
+                                Err(_) => break,
+                            }
+                        }
+                    }
+                }
+            };
+
+            if writeln!(writer, "{}", response).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Cursor, Write};
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{json, Value};
+
+    use chip_8::cpu::Cpu;
+    use chip_8::rom::RomLoader;
+    use chip_8::symbols::Symbols;
+
+    use crate::control::{
+        serve, ControlServer, Request, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND,
+        PARSE_ERROR, SERVER_ERROR,
+    };
+    use crate::{Instruments, Machine};
+
+    // V0 = 5, loop
+    const ROM: [u8; 4] = [0x60, 0x05, 0x12, 0x02];
+
+    fn server(netplay: bool) -> ControlServer {
+        ControlServer {
+            requests: channel().1,
+            paused: false,
+            keys: 0,
+            states: HashMap::new(),
+            loader: RomLoader::new(),
+            symbols: Symbols::new(),
+            loaded: None,
+            netplay,
+        }
+    }
+
+    fn instruments() -> Instruments {
+        Instruments {
+            tracer: None,
+            symbols: Symbols::new(),
+        }
+    }
+
+    /// The result of `method`, or the code of its error
+    fn call(
+        server: &mut ControlServer,
+        cpu: &mut Machine,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, i64> {
+        server
+            .execute(cpu, &mut instruments(), method, params)
+            .map_err(|error| error.code)
+    }
+
+    #[test]
+    fn dispatch() {
+        let mut server = server(false);
+        let mut cpu = Cpu::new(Cursor::new(ROM)).unwrap().with_observer(None);
+
+        let registers = call(&mut server, &mut cpu, "step", Value::Null).unwrap();
+        assert_eq!(registers["v"][0], 5);
+        assert_eq!(registers["pc"], 0x202);
+        assert_eq!(
+            call(&mut server, &mut cpu, "pause", Value::Null),
+            Ok(json!({"paused": true}))
+        );
+        assert!(server.paused());
+
+        call(
+            &mut server,
+            &mut cpu,
+            "write_memory",
+            json!({"address": 0x300, "data": [1, 2]}),
+        )
+        .unwrap();
+        assert_eq!(
+            call(
+                &mut server,
+                &mut cpu,
+                "read_memory",
+                json!({"address": 0x300, "length": 3})
+            ),
+            Ok(json!({"data": [1, 2, 0]}))
+        );
+
+        call(
+            &mut server,
+            &mut cpu,
+            "save_state",
+            json!({"slot": "start"}),
+        )
+        .unwrap();
+        call(
+            &mut server,
+            &mut cpu,
+            "set_register",
+            json!({"name": "VA", "value": 255}),
+        )
+        .unwrap();
+        call(
+            &mut server,
+            &mut cpu,
+            "set_register",
+            json!({"name": "i", "value": 0x1234}),
+        )
+        .unwrap();
+        assert_eq!(cpu.view().registers()[0xA], 255);
+        assert_eq!(cpu.view().index_register(), 0x1234);
+        call(
+            &mut server,
+            &mut cpu,
+            "load_state",
+            json!({"slot": "start"}),
+        )
+        .unwrap();
+        assert_eq!(cpu.view().registers()[0xA], 0);
+
+        call(&mut server, &mut cpu, "press_key", json!({"key": 15})).unwrap();
+        assert_eq!(server.keys(), 0x8000);
+    }
+
+    #[test]
+    fn errors() {
+        let mut server = server(false);
+        let mut cpu = Cpu::new(Cursor::new(ROM)).unwrap().with_observer(None);
+        let mut error =
+            |method: &str, params: Value| call(&mut server, &mut cpu, method, params).err();
+
+        assert_eq!(error("reset", Value::Null), Some(METHOD_NOT_FOUND));
+        assert_eq!(error("press_key", json!({"key": 16})), Some(INVALID_PARAMS));
+        assert_eq!(
+            error("press_key", json!({"button": 1})),
+            Some(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error("step", json!({"count": 100_001})),
+            Some(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error("read_memory", json!({"address": 0xFFF, "length": 2})),
+            Some(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error("write_memory", json!({"address": usize::MAX, "data": [0]})),
+            Some(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error("set_register", json!({"name": "v0", "value": 256})),
+            Some(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error("set_register", json!({"name": "dt", "value": 300})),
+            Some(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error("set_register", json!({"name": "vg", "value": 0})),
+            Some(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error("load_state", json!({"slot": "empty"})),
+            Some(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error("load_rom", json!({"path": "/nonexistent.ch8"})),
+            Some(SERVER_ERROR)
+        );
+        assert_eq!(cpu.view().registers()[0], 0);
+    }
+
+    #[test]
+    fn netplay() {
+        let mut server = server(true);
+        let mut cpu = Cpu::new(Cursor::new(ROM)).unwrap().with_observer(None);
+        let hash = cpu.state_hash();
+
+        for method in ["pause", "step", "load_rom"] {
+            assert_eq!(
+                call(&mut server, &mut cpu, method, Value::Null),
+                Err(SERVER_ERROR)
+            );
+        }
+        let write = json!({"address": 0x300, "data": [1]});
+        assert_eq!(
+            call(&mut server, &mut cpu, "write_memory", write),
+            Err(SERVER_ERROR)
+        );
+        let register = json!({"name": "v0", "value": 1});
+        assert_eq!(
+            call(&mut server, &mut cpu, "set_register", register),
+            Err(SERVER_ERROR)
+        );
+        call(&mut server, &mut cpu, "save_state", json!({"slot": "a"})).unwrap();
+        let slot = json!({"slot": "a"});
+        assert_eq!(
+            call(&mut server, &mut cpu, "load_state", slot),
+            Err(SERVER_ERROR)
+        );
+        assert_eq!(cpu.state_hash(), hash);
+
+        assert!(call(&mut server, &mut cpu, "registers", Value::Null).is_ok());
+        assert!(call(&mut server, &mut cpu, "press_key", json!({"key": 1})).is_ok());
+    }
+
+    #[test]
+    fn poll() {
+        let (sender, requests) = channel();
+        let mut server = ControlServer {
+            requests,
+            ..server(true)
+        };
+        let mut cpu = Cpu::new(Cursor::new(ROM)).unwrap().with_observer(None);
+        let (reply, responses) = channel();
+        for (id, method) in [(1, "registers"), (2, "cheats")] {
+            let request = Request {
+                id: json!(id),
+                method: String::from(method),
+                params: Value::Null,
+            };
+            sender.send((request, reply.clone())).unwrap();
+        }
+
+        assert!(server.poll(&mut cpu, &mut instruments(), None).is_none());
+        let response: Value = serde_json::from_str(&responses.recv().unwrap()).unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["pc"], 0x200);
+        let response: Value = serde_json::from_str(&responses.recv().unwrap()).unwrap();
+        assert_eq!(response["id"], 2);
+        assert_eq!(response["error"]["code"], SERVER_ERROR);
+    }
+
+    /// Collects what a client would read
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn responses() {
+        let input = concat!(
+            "{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"pause\"}\n",
+            "{\"jsonrpc\": \"2.0\", \"method\": \"resume\"}\n",
+            "\n",
+            "{\"jsonrpc\": \"2.0\", \"id\": 2\n",
+            "{\"jsonrpc\": \"2.0\", \"id\": 3}\n",
+            "{\"jsonrpc\": \"2.0\", \"id\": null, \"method\": \"pause\"}\n",
+        );
+        let output = Output::default();
+        let (sender, requests) = channel();
+        serve(Cursor::new(input), output.clone(), sender);
+
+        // Stands in for the main loop until the client hangs up
+        let mut methods = Vec::new();
+        while let Ok((request, reply)) = requests.recv() {
+            methods.push(request.method);
+            let response = json!({"id": request.id, "result": null});
+            reply.send(response.to_string()).unwrap();
+        }
+        assert_eq!(methods, ["pause", "resume", "pause"]);
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let responses: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[1]["error"]["code"], PARSE_ERROR);
+        assert_eq!(responses[2]["error"]["code"], INVALID_REQUEST);
+        assert_eq!(responses[3]["id"], Value::Null);
+        assert!(responses[3].get("result").is_some());
+    }
+}
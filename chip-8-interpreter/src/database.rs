@@ -0,0 +1,364 @@
+//! ROM metadata from the community chip-8-database
+//! (<https://github.com/chip-8/chip-8-database>).
+//!
+//! `database` in the configuration points to its `database` directory, the
+//! programs are looked up by the SHA-1 of the ROM in `programs.json` and the
+//! quirks of each platform are read from `platforms.json` when it exists.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use sdl2::keyboard::Keycode;
+use serde::Deserialize;
+
+use chip_8::quirks::Quirks;
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<i32>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkOverrides>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Platform {
+    id: String,
+    #[serde(default)]
+    quirks: QuirkOverrides,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct QuirkOverrides {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>,
+}
+
+impl QuirkOverrides {
+    fn apply(&self, quirks: &mut Quirks) {
+        let fields = [
+            (self.shift, &mut quirks.shift),
+            (
+                self.memory_increment_by_x,
+                &mut quirks.memory_increment_by_x,
+            ),
+            (
+                self.memory_leave_i_unchanged,
+                &mut quirks.memory_leave_i_unchanged,
+            ),
+            (self.wrap, &mut quirks.wrap),
+            (self.jump, &mut quirks.jump),
+            (self.vblank, &mut quirks.vblank),
+            (self.logic, &mut quirks.logic),
+        ];
+        for (value, quirk) in fields {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+    }
+}
+
+/// Settings recommended by the database for a ROM.
+pub struct RomSettings {
+    pub title: String,
+    pub platform: Option<String>,
+    pub quirks: Option<Quirks>,
+    pub tickrate: Option<i32>,
+    /// Background and foreground colours
+    pub colors: Option<([u8; 3], [u8; 3])>,
+    pub keys: Vec<(Keycode, u8)>,
+}
+
+pub struct Database {
+    programs: Vec<Program>,
+    /// SHA-1 of every ROM to its index in `programs`
+    hashes: HashMap<String, usize>,
+    platforms: HashMap<String, Quirks>,
+}
+
+impl Database {
+    pub fn load<P: AsRef<Path>>(directory: P) -> std::io::Result<Database> {
+        let directory = directory.as_ref();
+        let programs: Vec<Program> =
+            serde_json::from_slice(&std::fs::read(directory.join("programs.json"))?)?;
+
+        let hashes = programs
+            .iter()
+            .enumerate()
+            .flat_map(|(index, program)| {
+                program
+                    .roms
+                    .keys()
+                    .map(move |hash| (hash.to_lowercase(), index))
+            })
+            .collect();
+
+        let platforms = match std::fs::read(directory.join("platforms.json")) {
+            Ok(platforms) => serde_json::from_slice::<Vec<Platform>>(&platforms)?
+                .into_iter()
+                .map(|platform| {
+                    let mut quirks = Quirks::default();
+                    platform.quirks.apply(&mut quirks);
+                    (platform.id, quirks)
+                })
+                .collect(),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Database {
+            programs,
+            hashes,
+            platforms,
+        })
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomSettings> {
+        let hash = sha1_smol::Sha1::from(rom).digest().to_string();
+        let program = &self.programs[*self.hashes.get(&hash)?];
+        let rom = program
+            .roms
+            .iter()
+            .find(|(rom_hash, _)| rom_hash.to_lowercase() == hash)?
+            .1;
+
+        let platform = rom.platforms.first().cloned();
+        let quirks = platform.as_ref().and_then(|platform| {
+            let mut quirks = self
+                .platforms
+                .get(platform)
+                .copied()
+                .or_else(|| builtin_quirks(platform))?;
+            if let Some(overrides) = rom.quirky_platforms.get(platform) {
+                overrides.apply(&mut quirks);
+            }
+            Some(quirks)
+        });
+
+        let colors = rom.colors.as_ref().and_then(|colors| {
+            Some((
+                parse_color(colors.pixels.first()?)?,
+                parse_color(colors.pixels.get(1)?)?,
+            ))
+        });
+
+        let keys = rom
+            .keys
+            .iter()
+            .filter_map(|(name, key)| Some((keycode(name)?, *key)))
+            .collect();
+
+        Some(RomSettings {
+            title: program.title.clone(),
+            platform,
+            quirks,
+            tickrate: rom.tickrate,
+            colors,
+            keys,
+        })
+    }
+}
+
+/// Quirks of the most common platforms, used without `platforms.json`.
+fn builtin_quirks(platform: &str) -> Option<Quirks> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some(Quirks::VIP),
+        "modernChip8" => Some(Quirks::default()),
+        "chip48" | "superchip1" | "superchip" => Some(Quirks::SCHIP),
+        "xochip" => Some(Quirks::XO_CHIP),
+        _ => None,
+    }
+}
+
+/// Parses `#RRGGBB` or `#RGB`
+pub fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let color = color.strip_prefix('#').unwrap_or(color);
+    if !color.is_ascii() {
+        return None;
+    }
+    let channel = |digits: &str| u8::from_str_radix(digits, 16).ok();
+    match color.len() {
+        6 => Some([
+            channel(&color[0..2])?,
+            channel(&color[2..4])?,
+            channel(&color[4..6])?,
+        ]),
+        3 => Some([
+            channel(&color[0..1])? * 0x11,
+            channel(&color[1..2])? * 0x11,
+            channel(&color[2..3])? * 0x11,
+        ]),
+        _ => None,
+    }
+}
+
+/// Keyboard key for the database's semantic key names
+fn keycode(name: &str) -> Option<Keycode> {
+    let keycode = match name {
+        "up" => Keycode::Up,
+        "down" => Keycode::Down,
+        "left" => Keycode::Left,
+        "right" => Keycode::Right,
+        "a" => Keycode::Space,
+        "b" => Keycode::Return,
+        "player2Up" => Keycode::I,
+        "player2Down" => Keycode::K,
+        "player2Left" => Keycode::J,
+        "player2Right" => Keycode::L,
+        "player2A" => Keycode::U,
+        "player2B" => Keycode::O,
+        _ => return None,
+    };
+    Some(keycode)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use sdl2::keyboard::Keycode;
+
+    use chip_8::quirks::Quirks;
+
+    use crate::database::{builtin_quirks, parse_color, Database, QuirkOverrides};
+
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Pong",
+            "roms": {
+                "0E514A0662BCB69DC863953D1CE26E3D40E81A87": {
+                    "platforms": ["originalChip8"],
+                    "tickrate": 15,
+                    "colors": {"pixels": ["#102030", "#FFF"]},
+                    "keys": {"up": 1, "player2Up": 12, "start": 5},
+                    "quirkyPlatforms": {"originalChip8": {"shift": true}}
+                }
+            }
+        },
+        {
+            "title": "Octojam",
+            "roms": {"0000000000000000000000000000000000000000": {"platforms": ["xochip"]}}
+        }
+    ]"##;
+
+    /// A database directory holding `PROGRAMS` and `platforms`
+    fn database_directory(name: &str, platforms: Option<&str>) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("chip-8-database-{}", name));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("programs.json"), PROGRAMS).unwrap();
+        if let Some(platforms) = platforms {
+            std::fs::write(directory.join("platforms.json"), platforms).unwrap();
+        }
+        directory
+    }
+
+    /// The ROM with the hash of `PROGRAMS`, in upper case there
+    const ROM: &[u8] = b"pong";
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#102030"), Some([0x10, 0x20, 0x30]));
+        assert_eq!(parse_color("fA0"), Some([0xFF, 0xAA, 0x00]));
+        assert_eq!(parse_color("#12345"), None);
+        assert_eq!(parse_color("#GG0000"), None);
+        assert_eq!(parse_color("#é0000"), None);
+    }
+
+    #[test]
+    fn overrides() {
+        let overrides = QuirkOverrides {
+            shift: Some(true),
+            wrap: Some(false),
+            ..QuirkOverrides::default()
+        };
+        let mut quirks = Quirks::XO_CHIP;
+        overrides.apply(&mut quirks);
+        assert_eq!(
+            quirks,
+            Quirks {
+                shift: true,
+                wrap: false,
+                ..Quirks::XO_CHIP
+            }
+        );
+
+        assert_eq!(builtin_quirks("hybridVIP"), Some(Quirks::VIP));
+        assert_eq!(builtin_quirks("superchip"), Some(Quirks::SCHIP));
+        assert_eq!(builtin_quirks("megachip8"), None);
+    }
+
+    #[test]
+    fn lookup() {
+        let directory = database_directory("lookup", None);
+        let database = Database::load(&directory).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let settings = database.lookup(ROM).unwrap();
+        assert_eq!(settings.title, "Pong");
+        assert_eq!(settings.platform.as_deref(), Some("originalChip8"));
+        assert_eq!(
+            settings.quirks,
+            Some(Quirks {
+                shift: true,
+                ..Quirks::VIP
+            })
+        );
+        assert_eq!(settings.tickrate, Some(15));
+        assert_eq!(settings.colors, Some(([0x10, 0x20, 0x30], [0xFF; 3])));
+        let mut keys = settings.keys;
+        keys.sort_by_key(|(_, key)| *key);
+        assert_eq!(keys, [(Keycode::Up, 1), (Keycode::I, 12)]);
+
+        assert!(database.lookup(b"unknown").is_none());
+    }
+
+    #[test]
+    fn platforms() {
+        let platforms = r#"[{"id": "originalChip8", "quirks": {"logic": false}}]"#;
+        let directory = database_directory("platforms", Some(platforms));
+        let database = Database::load(&directory).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let quirks = database.lookup(ROM).unwrap().quirks.unwrap();
+        assert_eq!(
+            quirks,
+            Quirks {
+                shift: true,
+                logic: false,
+                ..Quirks::default()
+            }
+        );
+
+        // A platforms.json that can't be read isn't skipped
+        let directory = database_directory("unreadable", None);
+        std::fs::create_dir(directory.join("platforms.json")).unwrap();
+        let result = Database::load(&directory);
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(result.is_err());
+    }
+}
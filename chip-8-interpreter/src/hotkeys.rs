@@ -0,0 +1,295 @@
+//! Keys that control the interpreter instead of the program: pause, frame
+//! and instruction advance, fast-forward, slow motion, reset and the debug
+//! overlay.
+//!
+//! ```toml
+//! [hotkeys]
+//! pause = "P"
+//! frame_advance = "N"        # while paused
+//! step = "M"                 # one instruction, while paused
+//! fast_forward = "Tab"       # held
+//! slow_motion = "Left Shift" # held
+//! reset = "F5"
+//! overlay = "F1"
+//! fast_forward_speed = 4.0   # 0 runs as fast as possible
+//! slow_motion_speed = 0.25
+//! ```
+//!
+//! Key names are the ones of SDL, `SDL_GetKeyFromName`.
+
+use std::collections::HashMap;
+
+use sdl2::keyboard::Keycode;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct HotkeyConfig {
+    pub pause: String,
+    pub frame_advance: String,
+    pub step: String,
+    pub fast_forward: String,
+    pub slow_motion: String,
+    pub reset: String,
+    pub overlay: String,
+    pub fast_forward_speed: f32,
+    pub slow_motion_speed: f32,
+}
+
+impl Default for HotkeyConfig {
+    fn default() -> Self {
+        HotkeyConfig {
+            pause: String::from("P"),
+            frame_advance: String::from("N"),
+            step: String::from("M"),
+            fast_forward: String::from("Tab"),
+            slow_motion: String::from("Left Shift"),
+            reset: String::from("F5"),
+            overlay: String::from("F1"),
+            fast_forward_speed: 4.0,
+            slow_motion_speed: 0.25,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Pause,
+    FrameAdvance,
+    Step,
+    FastForward,
+    SlowMotion,
+    Reset,
+    Overlay,
+}
+
+pub struct Controls {
+    keys: HashMap<Keycode, Action>,
+    fast_forward_speed: f32,
+    slow_motion_speed: f32,
+    paused: bool,
+    fast_forward: bool,
+    slow_motion: bool,
+    frame_advances: u32,
+    steps: u32,
+    reset: bool,
+    overlay: bool,
+}
+
+impl Controls {
+    pub fn new(config: &HotkeyConfig) -> Result<Controls, String> {
+        Controls::bind(config, Keycode::from_name)
+    }
+
+    /// Controls with the hotkeys of `config`, `lookup` finds the key of a
+    /// name. Two hotkeys can't share a key.
+    fn bind<F>(config: &HotkeyConfig, lookup: F) -> Result<Controls, String>
+    where
+        F: Fn(&str) -> Option<Keycode>,
+    {
+        let bindings = [
+            ("pause", &config.pause, Action::Pause),
+            ("frame_advance", &config.frame_advance, Action::FrameAdvance),
+            ("step", &config.step, Action::Step),
+            ("fast_forward", &config.fast_forward, Action::FastForward),
+            ("slow_motion", &config.slow_motion, Action::SlowMotion),
+            ("reset", &config.reset, Action::Reset),
+            ("overlay", &config.overlay, Action::Overlay),
+        ];
+        let mut keys = HashMap::new();
+        let mut hotkeys = HashMap::new();
+        for (hotkey, name, action) in bindings.iter() {
+            let keycode = lookup(name).ok_or_else(|| format!("unknown hotkey {}", name))?;
+            if let Some(other) = hotkeys.insert(keycode, *hotkey) {
+                return Err(format!(
+                    "the hotkeys {} and {} are both {}",
+                    other, hotkey, name
+                ));
+            }
+            keys.insert(keycode, *action);
+        }
+        if config.fast_forward_speed < 0.0 || config.slow_motion_speed <= 0.0 {
+            return Err(String::from("hotkey speeds must be positive"));
+        }
+
+        Ok(Controls {
+            keys,
+            fast_forward_speed: config.fast_forward_speed,
+            slow_motion_speed: config.slow_motion_speed,
+            paused: false,
+            fast_forward: false,
+            slow_motion: false,
+            frame_advances: 0,
+            steps: 0,
+            reset: false,
+            overlay: false,
+        })
+    }
+
+    /// Handles a key press, returns whether `keycode` is a hotkey
+    pub fn key_down(&mut self, keycode: Keycode, repeat: bool) -> bool {
+        let action = match self.keys.get(&keycode) {
+            Some(action) => *action,
+            None => return false,
+        };
+        match action {
+            Action::Pause if !repeat => self.paused = !self.paused,
+            Action::FrameAdvance if self.paused => self.frame_advances += 1,
+            Action::Step if self.paused => self.steps += 1,
+            Action::FastForward => self.fast_forward = true,
+            Action::SlowMotion => self.slow_motion = true,
+            Action::Reset if !repeat => self.reset = true,
+            Action::Overlay if !repeat => self.overlay = !self.overlay,
+            _ => {}
+        }
+        true
+    }
+
+    /// Handles a key release, returns whether `keycode` is a hotkey
+    pub fn key_up(&mut self, keycode: Keycode) -> bool {
+        match self.keys.get(&keycode) {
+            Some(Action::FastForward) => self.fast_forward = false,
+            Some(Action::SlowMotion) => self.slow_motion = false,
+            Some(_) => {}
+            None => return false,
+        }
+        true
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn overlay(&self) -> bool {
+        self.overlay
+    }
+
+    /// Frames to run one by one, only while paused
+    pub fn take_frame_advances(&mut self) -> u32 {
+        std::mem::take(&mut self.frame_advances)
+    }
+
+    /// Instructions to run one by one, only while paused
+    pub fn take_steps(&mut self) -> u32 {
+        std::mem::take(&mut self.steps)
+    }
+
+    pub fn take_reset(&mut self) -> bool {
+        std::mem::take(&mut self.reset)
+    }
+
+    /// Shown in the window title, nothing at normal speed
+    pub fn status(&self) -> Option<String> {
+        if self.paused {
+            return Some(String::from("paused"));
+        }
+        match self.speed() {
+            None => Some(String::from("fast-forward")),
+            Some(speed) if speed != 1.0 => Some(format!("x{}", speed)),
+            Some(_) => None,
+        }
+    }
+
+    /// Multiple of real time, `None` when uncapped
+    pub fn speed(&self) -> Option<f32> {
+        if self.fast_forward {
+            Some(self.fast_forward_speed).filter(|speed| *speed > 0.0)
+        } else if self.slow_motion {
+            Some(self.slow_motion_speed)
+        } else {
+            Some(1.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::keyboard::Keycode;
+
+    use crate::hotkeys::{Controls, HotkeyConfig};
+
+    /// The default hotkeys, without asking SDL
+    fn lookup(name: &str) -> Option<Keycode> {
+        match name {
+            "P" => Some(Keycode::P),
+            "N" => Some(Keycode::N),
+            "M" => Some(Keycode::M),
+            "Tab" => Some(Keycode::Tab),
+            "Left Shift" => Some(Keycode::LShift),
+            "F5" => Some(Keycode::F5),
+            "F1" => Some(Keycode::F1),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn bindings() {
+        assert!(Controls::bind(&HotkeyConfig::default(), lookup).is_ok());
+
+        let config = HotkeyConfig {
+            reset: String::from("P"),
+            ..HotkeyConfig::default()
+        };
+        assert_eq!(
+            Controls::bind(&config, lookup).err().as_deref(),
+            Some("the hotkeys pause and reset are both P")
+        );
+        let config = HotkeyConfig {
+            overlay: String::from("Nowhere"),
+            ..HotkeyConfig::default()
+        };
+        assert!(Controls::bind(&config, lookup).is_err());
+        let config = HotkeyConfig {
+            slow_motion_speed: 0.0,
+            ..HotkeyConfig::default()
+        };
+        assert!(Controls::bind(&config, lookup).is_err());
+    }
+
+    #[test]
+    fn actions() {
+        let mut controls = Controls::bind(&HotkeyConfig::default(), lookup).unwrap();
+        assert!(!controls.key_down(Keycode::A, false));
+
+        // Frame advance and step only count while paused
+        controls.key_down(Keycode::N, false);
+        assert_eq!(controls.take_frame_advances(), 0);
+        controls.key_down(Keycode::P, false);
+        controls.key_down(Keycode::P, true);
+        assert!(controls.paused());
+        assert_eq!(controls.status().as_deref(), Some("paused"));
+        controls.key_down(Keycode::N, false);
+        controls.key_down(Keycode::N, true);
+        controls.key_down(Keycode::M, false);
+        assert_eq!(controls.take_frame_advances(), 2);
+        assert_eq!(controls.take_frame_advances(), 0);
+        assert_eq!(controls.take_steps(), 1);
+        controls.key_down(Keycode::P, false);
+
+        controls.key_down(Keycode::LShift, false);
+        assert_eq!(controls.speed(), Some(0.25));
+        assert_eq!(controls.status().as_deref(), Some("x0.25"));
+        controls.key_down(Keycode::Tab, false);
+        assert_eq!(controls.speed(), Some(4.0));
+        controls.key_up(Keycode::Tab);
+        controls.key_up(Keycode::LShift);
+        assert_eq!(controls.speed(), Some(1.0));
+        assert_eq!(controls.status(), None);
+
+        controls.key_down(Keycode::F5, false);
+        assert!(controls.take_reset());
+        assert!(!controls.take_reset());
+    }
+
+    #[test]
+    fn uncapped() {
+        let config = HotkeyConfig {
+            fast_forward_speed: 0.0,
+            ..HotkeyConfig::default()
+        };
+        let mut controls = Controls::bind(&config, lookup).unwrap();
+        controls.key_down(Keycode::Tab, false);
+        assert_eq!(controls.speed(), None);
+        assert_eq!(controls.status().as_deref(), Some("fast-forward"));
+    }
+}
@@ -1,71 +1,679 @@
-#![windows_subsystem = "windows"]
+//! Runs CHIP-8 programs in a window, or without one, and inspects ROMs.
+//!
+//! Exits with 0 on success, 1 on errors and 2 when the arguments are wrong.
 
 extern crate gl;
 
-use std::io::Read;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
+use clap::{Parser, Subcommand};
+use rand::rngs::StdRng;
+use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
 use sdl2::render::{Canvas, Texture};
 use sdl2::video::Window;
 use serde::Deserialize;
 
-use chip_8::{cpu, display};
+use chip_8::bus::DirectBus;
 use chip_8::cpu::Cpu;
+use chip_8::display;
+use chip_8::font::{Font, FONT_SIZE};
+use chip_8::profile::Profiler;
+use chip_8::quirks::Quirks;
+use chip_8::rom::RomLoader;
+use chip_8::symbols::Symbols;
+use chip_8::trace::{TraceFormat, Tracer};
 
 mod audio;
+mod cartridge;
+mod cheats;
+mod commands;
+mod control;
+mod database;
+mod hotkeys;
+mod netplay;
+mod octo;
+mod overlay;
+mod timestep;
+mod vip;
+mod watch;
 
-#[derive(Deserialize)]
+/// Errors are shown to the user as they are, with what was being done
+type Error = Box<dyn std::error::Error>;
+
+/// The machine of the window, the profiler listens to it when profiling.
+/// Save states and resets share the profiler.
+type Machine = Cpu<StdRng, DirectBus, Option<Rc<RefCell<Profiler>>>>;
+
+/// Read when `run` is not given `--config`, if it exists
+const DEFAULT_CONFIG: &str = "config.toml";
+/// Cheat codes file when the configuration doesn't name one
+const DEFAULT_CHEATS: &str = "cheats.toml";
+
+#[derive(Deserialize, Clone, Copy)]
 struct ColorConfig {
     back: [u8; 3],
     front: [u8; 3],
 }
 
+impl Default for ColorConfig {
+    fn default() -> Self {
+        ColorConfig {
+            back: [0, 0, 0],
+            front: [255, 255, 255],
+        }
+    }
+}
+
 #[derive(Deserialize)]
 struct Config {
+    #[serde(default)]
     color: ColorConfig,
-    executable: String,
+    /// ROM or Octo cartridge, the ROM given on the command line wins
+    executable: Option<String>,
+    #[serde(default = "default_cycles_per_frame")]
     cycles_per_frame: i32,
+    /// "vip", "schip" or "xo-chip", XO-CHIP by default
+    quirks: Option<String>,
+    /// Window pixels per CHIP-8 pixel
+    #[serde(default = "default_scale")]
+    scale: u32,
+    #[serde(default)]
+    mute: bool,
+    /// "vsync", "sleep" or "uncapped", how the window waits between pictures
+    #[serde(default)]
+    present: timestep::Present,
+    #[serde(default)]
+    hotkeys: hotkeys::HotkeyConfig,
+    netplay: Option<netplay::NetplayConfig>,
+    control: Option<control::ControlConfig>,
+    /// Address the program is loaded to, 0x200 by default
+    load_address: Option<u16>,
+    /// First instruction executed, the load address by default
+    entry_point: Option<u16>,
+    /// "vip", "dream6800", "eti660", "schip" or an 80 byte file
+    font: Option<String>,
+    /// Where the font is loaded, 0x50 by default
+    font_address: Option<u16>,
+    trace: Option<TraceConfig>,
+    profile: Option<ProfileConfig>,
+    /// Directory of the chip-8-database, used to tune known ROMs
+    database: Option<String>,
+    /// Cheat codes of every ROM, cheats.toml by default
+    cheats: Option<String>,
+    /// Labels shown by the trace, the profile and fault messages
+    symbols: Option<String>,
+}
+
+fn default_cycles_per_frame() -> i32 {
+    10
+}
+
+fn default_scale() -> u32 {
+    10
+}
+
+#[derive(Deserialize)]
+struct TraceConfig {
+    path: String,
+    /// Only the instructions inside these address ranges are traced
+    #[serde(default)]
+    ranges: Vec<AddressRange>,
+    /// "native" or "cadmium", the layout of the lines
+    format: Option<String>,
+}
+
+/// Files the profile is written to when the interpreter exits
+#[derive(Deserialize)]
+struct ProfileConfig {
+    report: Option<String>,
+    /// Call stacks for flamegraph tools
+    collapsed: Option<String>,
+}
+
+/// Addresses from `start` up to, but not including, `end`
+#[derive(Deserialize)]
+struct AddressRange {
+    start: u16,
+    end: u16,
+}
+
+/// Frames run before taking the picture for an exported cartridge's label
+const LABEL_FRAMES: u32 = 120;
+
+/// Time spent running frames between two pictures when fast-forwarding
+/// without a speed limit
+const UNCAPPED_BUDGET: Duration = Duration::from_millis(15);
+
+#[derive(Parser)]
+#[command(about = "A CHIP-8 interpreter")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs a ROM or an Octo cartridge in a window
+    Run(RunArgs),
+    /// Lists the instructions of a ROM
+    Disasm {
+        rom: String,
+        /// Address of the first byte
+        #[arg(long, value_parser = parse_address, default_value = "0x200")]
+        load_address: u16,
+        /// Labels and data regions, `address label` lines
+        #[arg(long)]
+        symbols: Option<String>,
+    },
+    /// Shows the size and hash of a ROM and what is known about it
+    Info {
+        rom: String,
+        /// Directory of the chip-8-database
+        #[arg(long)]
+        database: Option<String>,
+    },
+    /// Runs a ROM without a window or input and prints the final state
+    Headless(commands::HeadlessArgs),
+    /// Runs a ROM on an emulated COSMAC VIP with its original interpreter
+    Vip(vip::VipArgs),
+}
+
+#[derive(clap::Args)]
+struct RunArgs {
+    /// The executable of the configuration by default
+    rom: Option<String>,
+    /// config.toml when it exists
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Background colour, #RRGGBB
+    #[arg(long, value_parser = parse_color)]
+    back: Option<[u8; 3]>,
+    /// Foreground colour, #RRGGBB
+    #[arg(long, value_parser = parse_color)]
+    front: Option<[u8; 3]>,
+    /// Instructions run every frame, 10 by default
+    #[arg(long)]
+    cycles_per_frame: Option<i32>,
+    /// vip, schip or xo-chip
+    #[arg(long, value_parser = parse_quirks)]
+    quirks: Option<Quirks>,
+    /// Window pixels per CHIP-8 pixel
+    #[arg(long)]
+    scale: Option<u32>,
+    /// Disables the sound
+    #[arg(long)]
+    mute: bool,
+    /// How the window waits between pictures, the emulation runs at 60
+    /// frames per second regardless
+    #[arg(long, value_enum)]
+    present: Option<timestep::Present>,
+    #[command(flatten)]
+    load: LoadArgs,
+    /// Writes every instruction executed to this file
+    #[arg(long)]
+    trace: Option<String>,
+    /// Layout of the trace, native or cadmium
+    #[arg(long)]
+    trace_format: Option<String>,
+    /// Writes a profile report to this file on exit
+    #[arg(long)]
+    profile: Option<String>,
+    /// Directory of the chip-8-database
+    #[arg(long)]
+    database: Option<String>,
+    /// Cheat codes file, cheats.toml by default
+    #[arg(long)]
+    cheats: Option<String>,
+    /// Labels and data regions, `address label` lines
+    #[arg(long)]
+    symbols: Option<String>,
+    /// Writes an Octo cartridge with the ROM and its settings instead of
+    /// running it
+    #[arg(long)]
+    export_cartridge: Option<String>,
+}
+
+/// Where the program and the font go
+#[derive(clap::Args)]
+struct LoadArgs {
+    /// 0x200 by default
+    #[arg(long, value_parser = parse_address)]
+    load_address: Option<u16>,
+    /// The load address by default
+    #[arg(long, value_parser = parse_address)]
+    entry_point: Option<u16>,
+    /// vip, dream6800, eti660, schip or an 80 byte file
+    #[arg(long)]
+    font: Option<String>,
+    /// 0x50 by default
+    #[arg(long, value_parser = parse_address)]
+    font_address: Option<u16>,
+}
+
+impl LoadArgs {
+    fn loader(&self) -> Result<RomLoader, Error> {
+        let mut loader = RomLoader::new();
+        if let Some(address) = self.load_address {
+            loader = loader.load_address(address);
+        }
+        if let Some(address) = self.entry_point {
+            loader = loader.entry_point(address);
+        }
+        let mut font = match &self.font {
+            Some(name) => load_font(name)
+                .map_err(|error| format!("cannot load the font {}: {}", name, error))?,
+            None => Font::default(),
+        };
+        if let Some(address) = self.font_address {
+            font.address = address;
+        }
+        Ok(loader.font(font))
+    }
+}
+
+/// `0x` prefixed hexadecimal or decimal
+fn parse_address(text: &str) -> Result<u16, String> {
+    let address = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    address.map_err(|error| format!("invalid address {}: {}", text, error))
+}
+
+fn parse_color(text: &str) -> Result<[u8; 3], String> {
+    database::parse_color(text).ok_or_else(|| format!("invalid colour {}, use #RRGGBB", text))
+}
+
+fn parse_quirks(name: &str) -> Result<Quirks, String> {
+    Quirks::from_name(name)
+        .ok_or_else(|| format!("unknown quirks {}, use vip, schip or xo-chip", name))
 }
 
 fn main() {
-    let config = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| "config.toml".into());
+    let result = match Args::parse().command {
+        Command::Run(args) => run(args),
+        Command::Disasm {
+            rom,
+            load_address,
+            symbols,
+        } => commands::disasm(&rom, load_address, symbols.as_deref()),
+        Command::Info { rom, database } => commands::info(&rom, database.as_deref()),
+        Command::Headless(args) => commands::headless(&args),
+        Command::Vip(args) => vip::run(&args),
+    };
+
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
+}
+
+/// The colours and speed set by the ROM database, a cartridge or the
+/// command line, which win over the configuration file
+#[derive(Default)]
+struct Overrides {
+    cycles_per_frame: Option<i32>,
+    back: Option<[u8; 3]>,
+    front: Option<[u8; 3]>,
+}
+
+impl Overrides {
+    /// The tickrate and colours of a cartridge
+    fn cartridge(options: &cartridge::Options) -> Overrides {
+        Overrides {
+            cycles_per_frame: options.tickrate,
+            back: options
+                .background_color
+                .as_deref()
+                .and_then(database::parse_color),
+            front: options
+                .fill_color
+                .as_deref()
+                .and_then(database::parse_color),
+        }
+    }
+
+    /// Takes the values `other` sets
+    fn extend(&mut self, other: &Overrides) {
+        self.cycles_per_frame = other.cycles_per_frame.or(self.cycles_per_frame);
+        self.back = other.back.or(self.back);
+        self.front = other.front.or(self.front);
+    }
+
+    /// Takes the colours and speed edited in the configuration file, from
+    /// `file` to `reloaded`, they win from now on. Returns the names of the
+    /// edited settings.
+    fn edit(&mut self, file: (ColorConfig, i32), reloaded: &Config) -> Vec<&'static str> {
+        let (color, cycles_per_frame) = file;
+        let mut edited = Vec::new();
+        if reloaded.cycles_per_frame != cycles_per_frame {
+            self.cycles_per_frame = Some(reloaded.cycles_per_frame);
+            edited.push("cycles_per_frame");
+        }
+        if reloaded.color.back != color.back {
+            self.back = Some(reloaded.color.back);
+            edited.push("color.back");
+        }
+        if reloaded.color.front != color.front {
+            self.front = Some(reloaded.color.front);
+            edited.push("color.front");
+        }
+        edited
+    }
+
+    fn apply(&self, config: &mut Config) {
+        config.cycles_per_frame = self.cycles_per_frame.unwrap_or(config.cycles_per_frame);
+        config.color.back = self.back.unwrap_or(config.color.back);
+        config.color.front = self.front.unwrap_or(config.color.front);
+    }
+}
 
+/// The quirks, colours and speed given on the command line, which win over
+/// everything else, reloaded programs included
+struct CommandLine {
+    quirks: Option<Quirks>,
+    overrides: Overrides,
+}
+
+impl CommandLine {
+    fn apply(&self, cpu: &mut Machine, overrides: &mut Overrides) {
+        if let Some(quirks) = self.quirks {
+            cpu.set_quirks(quirks);
+        }
+        overrides.extend(&self.overrides);
+    }
+}
 
-    let mut interpreter_config = String::new();
-    let _ = std::fs::File::open(config.as_str()).unwrap().read_to_string(&mut interpreter_config);
+/// `path`, or config.toml when it exists
+fn config_path(path: Option<&Path>) -> Option<&Path> {
+    match path {
+        Some(path) => Some(path),
+        None => Some(Path::new(DEFAULT_CONFIG)).filter(|path| path.exists()),
+    }
+}
 
+/// The configuration file, every setting at its default without one
+fn load_config(path: Option<&Path>) -> Result<Config, Error> {
+    let path = config_path(path);
+    let text = match path {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|error| format!("cannot read {}: {}", path.display(), error))?,
+        None => String::new(),
+    };
+    let config = toml::from_str(&text).map_err(|error| {
+        format!(
+            "invalid configuration {}: {}",
+            path.unwrap_or_else(|| Path::new(DEFAULT_CONFIG)).display(),
+            error
+        )
+    })?;
+    Ok(config)
+}
 
-    let config = toml::from_str::<Config>(interpreter_config.as_str())
-        .expect("No se puede cargar el archivo de configuración");
+/// The program in `path` and, for a cartridge, its options
+fn read_rom(path: &str) -> Result<(Vec<u8>, Option<cartridge::Options>), Error> {
+    if cartridge::is_cartridge(path) {
+        let cartridge = cartridge::load(path)
+            .map_err(|error| format!("cannot load the cartridge {}: {}", path, error))?;
+        Ok((cartridge.rom, Some(cartridge.options)))
+    } else {
+        let rom =
+            std::fs::read(path).map_err(|error| format!("cannot read {}: {}", path, error))?;
+        Ok((rom, None))
+    }
+}
 
-    let file = std::fs::File::open(config.executable.as_str()).expect("No se puede abrir el archivo");
-    let mut cpu = cpu::Cpu::new(file).expect("No se pudo leer la memoria del archivo");
+fn run(args: RunArgs) -> Result<(), Error> {
+    let mut config = load_config(args.config.as_deref())?;
+    let executable = args
+        .rom
+        .or_else(|| config.executable.take())
+        .ok_or("no ROM given, pass one or set executable in the configuration")?;
+    if let Some(path) = args.trace {
+        config.trace = Some(TraceConfig {
+            path,
+            ranges: Vec::new(),
+            format: None,
+        });
+    }
+    if let (Some(trace), Some(format)) = (config.trace.as_mut(), args.trace_format) {
+        trace.format = Some(format);
+    }
+    if let Some(report) = args.profile {
+        config.profile = Some(ProfileConfig {
+            report: Some(report),
+            collapsed: config.profile.and_then(|profile| profile.collapsed),
+        });
+    }
+    if args.database.is_some() {
+        config.database = args.database;
+    }
+    if args.cheats.is_some() {
+        config.cheats = args.cheats;
+    }
+    if args.symbols.is_some() {
+        config.symbols = args.symbols;
+    }
+    let symbols = match &config.symbols {
+        Some(path) => load_symbols(path)?,
+        None => Symbols::new(),
+    };
+    let load = LoadArgs {
+        load_address: args.load.load_address.or(config.load_address),
+        entry_point: args.load.entry_point.or(config.entry_point),
+        font: args.load.font.or_else(|| config.font.take()),
+        font_address: args.load.font_address.or(config.font_address),
+    };
+
+    let (rom, cartridge_options) = read_rom(&executable)?;
+    let loader = load.loader()?;
+    let profiler = config
+        .profile
+        .as_ref()
+        .map(|_| Rc::new(RefCell::new(Profiler::new().symbols(symbols.clone()))));
+    let mut cpu = loader
+        .read(rom.as_slice())
+        .map_err(|error| format!("cannot load {}: {}", executable, error))?
+        .with_observer(profiler);
+    println!("Loaded {} ({} bytes)", executable, cpu.rom_size());
+    if let Some(name) = &config.quirks {
+        cpu.set_quirks(parse_quirks(name)?);
+    }
+
+    let mut title = String::from("CHIP-8");
+    let mut key_bindings = HashMap::new();
+    let mut overrides = Overrides::default();
+    if let Some(directory) = &config.database {
+        let database = database::Database::load(directory)
+            .map_err(|error| format!("cannot load the ROM database {}: {}", directory, error))?;
+        if let Some(settings) = database.lookup(&rom) {
+            title = format!("CHIP-8 - {}", settings.title);
+            if let Some(platform) = &settings.platform {
+                println!(
+                    "{} found in the ROM database, platform {}",
+                    settings.title, platform
+                );
+            }
+            if let Some(quirks) = settings.quirks {
+                cpu.set_quirks(quirks);
+            }
+            overrides.cycles_per_frame = settings.tickrate;
+            if let Some((back, front)) = settings.colors {
+                overrides.back = Some(back);
+                overrides.front = Some(front);
+            }
+            key_bindings.extend(settings.keys);
+        }
+    }
+
+    if let Some(options) = &cartridge_options {
+        apply_cartridge(options, &mut cpu, &mut overrides);
+    }
+
+    // The command line wins over the configuration, the database and the
+    // cartridge
+    let command_line = CommandLine {
+        quirks: args.quirks,
+        overrides: Overrides {
+            cycles_per_frame: args.cycles_per_frame,
+            back: args.back,
+            front: args.front,
+        },
+    };
+    command_line.apply(&mut cpu, &mut overrides);
+    // What the file says, to tell its edits apart on hot reload
+    let mut file = (config.color, config.cycles_per_frame);
+    overrides.apply(&mut config);
+    config.scale = args.scale.unwrap_or(config.scale);
+    config.mute |= args.mute;
+    config.present = args.present.unwrap_or(config.present);
+
+    if let Some(path) = args.export_cartridge {
+        let mut preview = cpu.clone();
+        for _ in 0..LABEL_FRAMES {
+            for _ in 0..config.cycles_per_frame {
+                preview.next();
+            }
+            preview.decrease_timers();
+        }
+
+        let options = cartridge::Options {
+            tickrate: Some(config.cycles_per_frame),
+            fill_color: Some(format_color(config.color.front)),
+            background_color: Some(format_color(config.color.back)),
+            ..cartridge::Options::from_quirks(cpu.quirks())
+        };
+        let colors = (config.color.back, config.color.front);
+        cartridge::save(&path, &rom, options, preview.get_display(), colors)
+            .map_err(|error| format!("cannot export the cartridge {}: {}", path, error))?;
+        println!("Cartridge exported to {}", path);
+        return Ok(());
+    }
+
+    let mut session = match config.netplay.as_ref() {
+        Some(netplay_config) => {
+            println!("Connecting to netplay peer on {}", netplay_config.address);
+            let session = netplay::Session::connect(
+                netplay_config,
+                cpu.state_hash(),
+                cpu.quirks(),
+                config.cycles_per_frame,
+            )
+            .map_err(|error| format!("cannot connect to the netplay peer: {}", error))?;
+            cpu.seed_rng(session.seed());
+            Some(session)
+        }
+        None => None,
+    };
+
+    let mut control = match config.control.as_ref() {
+        Some(control_config) => Some(
+            control::ControlServer::start(
+                control_config,
+                loader,
+                symbols.clone(),
+                session.is_some(),
+            )
+            .map_err(|error| format!("cannot start the control server: {}", error))?,
+        ),
+        None => None,
+    };
+
+    let tracer = match config.trace.as_ref() {
+        Some(trace_config) => {
+            let format = match &trace_config.format {
+                Some(name) => name
+                    .parse()
+                    .map_err(|_| format!("unknown trace format {}, use native or cadmium", name))?,
+                None => TraceFormat::Native,
+            };
+            let file = std::fs::File::create(&trace_config.path).map_err(|error| {
+                format!("cannot create the trace {}: {}", trace_config.path, error)
+            })?;
+            Some(
+                trace_config.ranges.iter().fold(
+                    Tracer::new(BufWriter::new(file))
+                        .symbols(symbols.clone())
+                        .format(format),
+                    |tracer, range| tracer.range(range.start..range.end),
+                ),
+            )
+        }
+        None => None,
+    };
+
+    let mut instruments = Instruments { tracer, symbols };
+
+    // Reloading and changing the speed would desynchronize the netplay peers
+    let watching = session.is_none();
+    let mut controls = if watching {
+        Some(
+            hotkeys::Controls::new(&config.hotkeys)
+                .map_err(|error| format!("invalid hotkeys: {}", error))?,
+        )
+    } else {
+        None
+    };
+    let mut initial = cpu.clone();
+    let mut cheats = if watching {
+        let path = config.cheats.as_deref().unwrap_or(DEFAULT_CHEATS);
+        let cheats = cheats::Cheats::load(path, &rom)?;
+        cheats.apply_all(&mut cpu);
+        Some(cheats)
+    } else {
+        None
+    };
+    let mut status = None;
+    let mut rom_watcher = Some(watch::Watcher::new(&executable)).filter(|_| watching);
+    let mut config_watcher = config_path(args.config.as_deref())
+        .map(watch::Watcher::new)
+        .filter(|_| watching);
 
     // SDL Context creation
-    let sdl_context = sdl2::init().expect("Cannot initialize sdl");
-    let sdl_video = sdl_context.video().expect("Cannot initialize video");
+    let sdl_context = sdl2::init().map_err(|error| format!("cannot initialize SDL: {}", error))?;
+    let sdl_video = sdl_context
+        .video()
+        .map_err(|error| format!("cannot initialize the video: {}", error))?;
 
-    let audio_device = audio::initialize(&sdl_context).expect("No se puede cargar el audio");
+    let audio_device = if config.mute {
+        None
+    } else {
+        Some(
+            audio::initialize(&sdl_context)
+                .map_err(|error| format!("cannot initialize the audio: {}", error))?,
+        )
+    };
     let mut playing = false;
 
     // SDL Window
     let window = sdl_video
-        .window("CHIP-8", 640, 320)
+        .window(
+            &title,
+            display::WIDTH as u32 * config.scale,
+            display::HEIGHT as u32 * config.scale,
+        )
         .opengl()
         .resizable()
         .build()
-        .expect("Cannot create windows");
+        .map_err(|error| format!("cannot create the window: {}", error))?;
 
-    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut event_pump = sdl_context.event_pump()?;
 
-    let mut canvas = window
-        .into_canvas()
-        .accelerated()
-        .present_vsync()
+    let mut canvas = window.into_canvas().accelerated();
+    if config.present == timestep::Present::Vsync {
+        canvas = canvas.present_vsync();
+    }
+    let mut canvas = canvas
         .build()
-        .expect("No se puede obtener un contexto gráfico");
+        .map_err(|error| format!("cannot create the renderer: {}", error))?;
 
     let texture_creator = canvas.texture_creator();
     let mut texture = texture_creator
@@ -74,216 +682,445 @@ fn main() {
             display::WIDTH as u32,
             display::HEIGHT as u32,
         )
-        .expect("No se puede crear la textura");
-
-    texture
-        .with_lock(None, |buffer, _| {
-            for i in (0..buffer.len()).step_by(3) {
-                let color = &config.color.back;
-                buffer[i] = color[0];
-                buffer[i + 1] = color[1];
-                buffer[i + 2] = color[2];
-            }
-            for data in buffer {
-                *data = 0;
-            }
-        })
-        .expect("No se pudo copiar");
+        .map_err(|error| format!("cannot create the texture: {}", error))?;
+    let mut overlay_texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            overlay::WIDTH as u32,
+            overlay::HEIGHT as u32,
+        )
+        .map_err(|error| format!("cannot create the texture: {}", error))?;
+    let mut rates = overlay::Rates::new(&cpu);
+    let mut timestep = timestep::Timestep::new();
+
+    let mut keys: u16 = 0;
+    let mut frame_keys: u16 = 0;
 
     'running: loop {
         use sdl2::event::Event;
-        use sdl2::keyboard::Keycode;
 
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } => {
                     break 'running;
                 }
-                // 1 2 3 C
-                // 4 5 6 D
-                // 7 8 9 E
-                // A 0 B F
-                // Mapped to
-                // 1 2 3 4
-                // q w e r
-                // a s d f
-                // z x c v
-                Event::KeyDown {
-                    keycode: Some(Keycode::Num1),
-                    ..
-                } => cpu.set_key(1, true),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Num2),
-                    ..
-                } => cpu.set_key(2, true),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Num3),
-                    ..
-                } => cpu.set_key(3, true),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Num4),
-                    ..
-                } => cpu.set_key(0xC, true),
-
-                Event::KeyDown {
-                    keycode: Some(Keycode::Q),
-                    ..
-                } => cpu.set_key(0x4, true),
                 Event::KeyDown {
-                    keycode: Some(Keycode::W),
+                    keycode: Some(keycode),
+                    repeat,
                     ..
-                } => cpu.set_key(0x5, true),
-                Event::KeyDown {
-                    keycode: Some(Keycode::E),
-                    ..
-                } => cpu.set_key(0x6, true),
-                Event::KeyDown {
-                    keycode: Some(Keycode::R),
+                } => {
+                    if controls
+                        .as_mut()
+                        .is_some_and(|controls| controls.key_down(keycode, repeat))
+                    {
+                        continue;
+                    }
+                    if let Some(key) = keypad_index(keycode, &key_bindings) {
+                        keys |= 1 << key;
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
                     ..
-                } => cpu.set_key(0xD, true),
+                } => {
+                    if controls
+                        .as_mut()
+                        .is_some_and(|controls| controls.key_up(keycode))
+                    {
+                        continue;
+                    }
+                    if let Some(key) = keypad_index(keycode, &key_bindings) {
+                        keys &= !(1 << key);
+                    }
+                }
+                _ => {}
+            }
+        }
 
-                Event::KeyDown {
-                    keycode: Some(Keycode::A),
-                    ..
-                } => cpu.set_key(0x7, true),
-                Event::KeyDown {
-                    keycode: Some(Keycode::S),
-                    ..
-                } => cpu.set_key(0x8, true),
-                Event::KeyDown {
-                    keycode: Some(Keycode::D),
-                    ..
-                } => cpu.set_key(0x9, true),
-                Event::KeyDown {
-                    keycode: Some(Keycode::F),
-                    ..
-                } => cpu.set_key(0xE, true),
+        if let Some(control) = control.as_mut() {
+            if let Some((rom, options)) = control.poll(&mut cpu, &mut instruments, cheats.as_mut())
+            {
+                if let Some(options) = &options {
+                    apply_cartridge(options, &mut cpu, &mut overrides);
+                    command_line.apply(&mut cpu, &mut overrides);
+                    overrides.apply(&mut config);
+                }
+                initial = cpu.clone();
+                reload_cheats(cheats.as_mut(), &rom, &mut cpu);
+            }
+        }
+        if rom_watcher
+            .as_mut()
+            .is_some_and(|watcher| watcher.changed())
+        {
+            match reload_rom(&executable, &loader, &cpu) {
+                Ok((reloaded, rom, options)) => {
+                    cpu = reloaded;
+                    if let Some(options) = &options {
+                        apply_cartridge(options, &mut cpu, &mut overrides);
+                        command_line.apply(&mut cpu, &mut overrides);
+                        overrides.apply(&mut config);
+                    }
+                    initial = cpu.clone();
+                    reload_cheats(cheats.as_mut(), &rom, &mut cpu);
+                    println!("Reloaded {} ({} bytes)", executable, cpu.rom_size());
+                }
+                Err(error) => eprintln!("Cannot reload {}: {}", executable, error),
+            }
+        }
+        if config_watcher
+            .as_mut()
+            .is_some_and(|watcher| watcher.changed())
+        {
+            match load_config(args.config.as_deref()) {
+                Ok(reloaded) => {
+                    let edited = overrides.edit(file, &reloaded);
+                    file = (reloaded.color, reloaded.cycles_per_frame);
+                    overrides.apply(&mut config);
+                    if edited.is_empty() {
+                        println!("Reloaded the configuration, the colours and speed are unchanged");
+                    } else {
+                        println!("Reloaded {} from the configuration", edited.join(", "));
+                    }
+                }
+                Err(error) => eprintln!("Cannot reload the configuration: {}", error),
+            }
+        }
+        if controls
+            .as_mut()
+            .is_some_and(|controls| controls.take_reset())
+        {
+            cpu = initial.clone();
+            if let Some(cheats) = &cheats {
+                cheats.apply_all(&mut cpu);
+            }
+        }
+        let paused = control.as_ref().is_some_and(|control| control.paused())
+            || controls.as_ref().is_some_and(|controls| controls.paused());
+        let local_keys = keys | control.as_ref().map_or(0, |control| control.keys());
 
-                Event::KeyDown {
-                    keycode: Some(Keycode::Z),
-                    ..
-                } => cpu.set_key(0xA, true),
-                Event::KeyDown {
-                    keycode: Some(Keycode::X),
-                    ..
-                } => cpu.set_key(0x0, true),
-                Event::KeyDown {
-                    keycode: Some(Keycode::C),
-                    ..
-                } => cpu.set_key(0xB, true),
-                Event::KeyDown {
-                    keycode: Some(Keycode::V),
-                    ..
-                } => cpu.set_key(0xF, true),
+        // Speed of the emulation against real time, `None` when uncapped
+        let speed = if paused {
+            Some(0.0)
+        } else {
+            controls
+                .as_ref()
+                .map_or(Some(1.0), |controls| controls.speed())
+        };
+        let frames = match speed {
+            Some(speed) => timestep.frames(speed),
+            None => {
+                timestep.frames(0.0);
+                let start = Instant::now();
+                while start.elapsed() < UNCAPPED_BUDGET {
+                    frame_keys = run_frame(
+                        &mut cpu,
+                        &mut instruments,
+                        session.as_mut(),
+                        cheats.as_ref(),
+                        local_keys,
+                        config.cycles_per_frame,
+                    )?;
+                }
+                0
+            }
+        };
+        let mut advances = 0;
+        if let Some(controls) = controls.as_mut() {
+            // The control server pauses without frame advance
+            advances = controls.take_frame_advances();
+            if control.as_ref().is_some_and(|control| control.paused()) {
+                advances = 0;
+            }
+            let steps = controls.take_steps();
+            if steps > 0 {
+                for key in 0..16 {
+                    cpu.set_key(key, local_keys & (1 << key) != 0);
+                }
+                frame_keys = local_keys;
+            }
+            for _ in 0..steps {
+                instruments.step(&mut cpu);
+            }
+        }
+        for _ in 0..frames + advances {
+            frame_keys = run_frame(
+                &mut cpu,
+                &mut instruments,
+                session.as_mut(),
+                cheats.as_ref(),
+                local_keys,
+                config.cycles_per_frame,
+            )?;
+        }
 
-                Event::KeyUp {
-                    keycode: Some(Keycode::Num1),
-                    ..
-                } => cpu.set_key(1, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::Num2),
-                    ..
-                } => cpu.set_key(2, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::Num3),
-                    ..
-                } => cpu.set_key(3, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::Num4),
-                    ..
-                } => cpu.set_key(0xC, false),
+        let frame_status = controls.as_ref().and_then(|controls| controls.status());
+        if frame_status != status {
+            status = frame_status;
+            let window_title = match &status {
+                Some(status) => format!("{} [{}]", title, status),
+                None => title.clone(),
+            };
+            canvas.window_mut().set_title(&window_title)?;
+        }
 
-                Event::KeyUp {
-                    keycode: Some(Keycode::Q),
-                    ..
-                } => cpu.set_key(0x4, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::W),
-                    ..
-                } => cpu.set_key(0x5, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::E),
-                    ..
-                } => cpu.set_key(0x6, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::R),
-                    ..
-                } => cpu.set_key(0xD, false),
+        rates.frame(&cpu);
+        canvas.clear();
+        let (width, height) = canvas.output_size()?;
+        let picture = if controls.as_ref().is_some_and(|controls| controls.overlay()) {
+            overlay_texture.with_lock(None, |buffer, pitch| {
+                let colors = (config.color.back, config.color.front);
+                overlay::draw(buffer, pitch, &cpu, frame_keys, &rates, colors)
+            })?;
+            // The overlay takes the right third of the window
+            let panel_width = width / 3;
+            let panel_height = panel_width * overlay::HEIGHT as u32 / overlay::WIDTH as u32;
+            let panel = Rect::new((width - panel_width) as i32, 0, panel_width, panel_height);
+            canvas.copy(&overlay_texture, None, panel)?;
+            Rect::new(0, 0, width - panel_width, height)
+        } else {
+            Rect::new(0, 0, width, height)
+        };
+        draw(&cpu, &mut canvas, &mut texture, &config, picture)?;
+        canvas.present();
+        if let (timestep::Present::Sleep, Some(speed)) = (config.present, speed) {
+            timestep.sleep(speed);
+        }
+        let sound = cpu.should_play_sound() && !paused;
+        if let Some(audio_device) = &audio_device {
+            if sound && !playing {
+                audio_device.resume();
+                playing = true;
+            }
+            if !sound && playing {
+                audio_device.pause();
+                playing = false;
+            }
+        }
+    }
 
-                Event::KeyUp {
-                    keycode: Some(Keycode::A),
-                    ..
-                } => cpu.set_key(0x7, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::S),
-                    ..
-                } => cpu.set_key(0x8, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::D),
-                    ..
-                } => cpu.set_key(0x9, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::F),
-                    ..
-                } => cpu.set_key(0xE, false),
+    if let (Some(profiler), Some(profile_config)) = (cpu.observer(), &config.profile) {
+        write_profile(&profiler.borrow(), profile_config)
+            .map_err(|error| format!("cannot write the profile: {}", error))?;
+    }
+    Ok(())
+}
 
-                Event::KeyUp {
-                    keycode: Some(Keycode::Z),
-                    ..
-                } => cpu.set_key(0xA, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::X),
-                    ..
-                } => cpu.set_key(0x0, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::C),
-                    ..
-                } => cpu.set_key(0xB, false),
-                Event::KeyUp {
-                    keycode: Some(Keycode::V),
-                    ..
-                } => cpu.set_key(0xF, false),
+/// The tracer, which sees every instruction run
+struct Instruments {
+    tracer: Option<Tracer<BufWriter<File>>>,
+    /// Locate faults
+    symbols: Symbols,
+}
 
-                _ => {}
+impl Instruments {
+    /// Runs the next instruction
+    fn step(&mut self, cpu: &mut Machine) {
+        // A faulted machine stays on the same instruction, it is traced once
+        if let (Some(trace), None) = (self.tracer.as_mut(), cpu.fault()) {
+            if let Err(error) = trace.record(cpu.view()) {
+                eprintln!("Cannot write the trace: {}", error);
+                self.tracer = None;
             }
         }
-
-        for _ in 0..config.cycles_per_frame {
-            cpu.next();
+        let faulted = cpu.fault().is_some();
+        cpu.next();
+        if let (false, Some(fault)) = (faulted, cpu.fault()) {
+            let location = self.symbols.describe(cpu.view().program_counter());
+            eprintln!("The program stopped at {}: {}", location, fault);
         }
+    }
 
-        canvas.clear();
-        draw(&mut cpu, &mut canvas, &mut texture, &config);
-        canvas.present();
-        cpu.decrease_timers();
-        if cpu.as_ref().should_play_sound() && !playing {
-            audio_device.resume();
-            playing = true;
+    /// Runs `cycles` instructions, then the timers tick
+    fn run_frame(&mut self, cpu: &mut Machine, cycles: i32) {
+        for _ in 0..cycles {
+            self.step(cpu);
         }
-        if !cpu.as_ref().should_play_sound() && playing {
-            audio_device.pause();
-            playing = false;
+        cpu.decrease_timers();
+    }
+}
+
+/// Runs one emulation frame with `keys` pressed and the frozen cheats
+/// written, in lockstep with the netplay peer when there is one. Returns the
+/// keys pressed on both sides.
+fn run_frame(
+    cpu: &mut Machine,
+    instruments: &mut Instruments,
+    mut session: Option<&mut netplay::Session>,
+    cheats: Option<&cheats::Cheats>,
+    keys: u16,
+    cycles: i32,
+) -> Result<u16, Error> {
+    let keys = match session.as_mut() {
+        Some(session) => session
+            .exchange_keys(keys)
+            .map_err(|error| format!("netplay: {}", error))?,
+        None => keys,
+    };
+    for key in 0..16 {
+        cpu.set_key(key, keys & (1 << key) != 0);
+    }
+    if let Some(cheats) = cheats {
+        cheats.apply_frozen(cpu);
+    }
+    instruments.run_frame(cpu, cycles);
+    if let Some(session) = session {
+        session
+            .end_frame(cpu.state_hash())
+            .map_err(|error| format!("netplay: {}", error))?;
+    }
+    Ok(keys)
+}
+
+/// A new machine running the program in `path` with the quirks and the
+/// profiler of `cpu`, the program and, for a cartridge, its options
+fn reload_rom(
+    path: &str,
+    loader: &RomLoader,
+    cpu: &Machine,
+) -> Result<(Machine, Vec<u8>, Option<cartridge::Options>), Error> {
+    let (rom, options) = read_rom(path)?;
+    let quirks = cpu.quirks();
+    let mut cpu = loader
+        .read(rom.as_slice())?
+        .with_observer(cpu.observer().clone());
+    cpu.set_quirks(quirks);
+    Ok((cpu, rom, options))
+}
+
+/// Sets the quirks of a cartridge on `cpu`, and its tickrate and colours
+fn apply_cartridge(options: &cartridge::Options, cpu: &mut Machine, overrides: &mut Overrides) {
+    let mut quirks = cpu.quirks();
+    options.apply_quirks(&mut quirks);
+    cpu.set_quirks(quirks);
+    overrides.extend(&Overrides::cartridge(options));
+}
+
+/// Switches `cheats` to the new program `rom` and applies them to `cpu`
+fn reload_cheats(cheats: Option<&mut cheats::Cheats>, rom: &[u8], cpu: &mut Machine) {
+    if let Some(cheats) = cheats {
+        if let Err(error) = cheats.reload(rom) {
+            eprintln!("Cannot reload the cheats: {}", error);
         }
+        cheats.apply_all(cpu);
+    }
+}
+
+/// Writes the profile to the files named in `config`
+fn write_profile(profiler: &Profiler, config: &ProfileConfig) -> std::io::Result<()> {
+    if let Some(path) = &config.report {
+        let mut file = BufWriter::new(std::fs::File::create(path)?);
+        profiler.write_report(&mut file)?;
+        file.flush()?;
     }
+    if let Some(path) = &config.collapsed {
+        let mut file = BufWriter::new(std::fs::File::create(path)?);
+        profiler.write_collapsed(&mut file)?;
+        file.flush()?;
+    }
+    Ok(())
+}
+
+fn load_symbols(path: &str) -> Result<Symbols, Error> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| format!("cannot read the symbols {}: {}", path, error))?;
+    Ok(text
+        .parse()
+        .map_err(|error| format!("cannot load the symbols {}: {}", path, error))?)
 }
 
-fn draw(cpu: &mut Box<Cpu>, canvas: &mut Canvas<Window>, texture: &mut Texture, config: &Config) {
-    texture
-        .with_lock(None, |buffer, _| {
-            let video_buffer = cpu.get_display().get_video_mem();
-            let front_color = &config.color.front;
-            let back_color = &config.color.back;
-            for (i, data) in video_buffer.iter().enumerate() {
-                let draw_pixel = *data != 0;
+fn format_color(color: [u8; 3]) -> String {
+    format!("#{:02X}{:02X}{:02X}", color[0], color[1], color[2])
+}
 
-                buffer[i * 3] = if draw_pixel { front_color[0] } else { back_color[0] };
-                buffer[i * 3 + 1] = if draw_pixel { front_color[1] } else { back_color[1] };
-                buffer[i * 3 + 2] = if draw_pixel { front_color[2] } else { back_color[2] };
+/// Built-in font set by name, or the glyphs stored in a file
+fn load_font(name: &str) -> std::io::Result<Font> {
+    let font = match name {
+        "vip" => Font::VIP,
+        "dream6800" => Font::DREAM_6800,
+        "eti660" => Font::ETI_660,
+        "schip" => Font::SCHIP,
+        path => {
+            let data = std::fs::read(path)?;
+            let mut font = Font::default();
+            if data.len() != FONT_SIZE {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("a font is {} bytes, {} has {}", FONT_SIZE, path, data.len()),
+                ));
             }
-        })
-        .expect("No se pudo copiar");
+            font.glyphs.copy_from_slice(&data);
+            font
+        }
+    };
+    Ok(font)
+}
+
+/// Keyboard to keypad mapping
+/// 1 2 3 4      1 2 3 C
+/// q w e r  ->  4 5 6 D
+/// a s d f      7 8 9 E
+/// z x c v      A 0 B F
+/// `key_bindings` take precedence over this layout.
+fn keypad_index(keycode: Keycode, key_bindings: &HashMap<Keycode, u8>) -> Option<u8> {
+    if let Some(key) = key_bindings.get(&keycode) {
+        return Some(*key);
+    }
+
+    let key = match keycode {
+        Keycode::Num1 => 0x1,
+        Keycode::Num2 => 0x2,
+        Keycode::Num3 => 0x3,
+        Keycode::Num4 => 0xC,
+        Keycode::Q => 0x4,
+        Keycode::W => 0x5,
+        Keycode::E => 0x6,
+        Keycode::R => 0xD,
+        Keycode::A => 0x7,
+        Keycode::S => 0x8,
+        Keycode::D => 0x9,
+        Keycode::F => 0xE,
+        Keycode::Z => 0xA,
+        Keycode::X => 0x0,
+        Keycode::C => 0xB,
+        Keycode::V => 0xF,
+        _ => return None,
+    };
+    Some(key)
+}
+
+fn draw(
+    cpu: &Machine,
+    canvas: &mut Canvas<Window>,
+    texture: &mut Texture,
+    config: &Config,
+    picture: Rect,
+) -> Result<(), Error> {
+    texture.with_lock(None, |buffer, _| {
+        let video_buffer = cpu.get_display().get_video_mem();
+        let front_color = &config.color.front;
+        let back_color = &config.color.back;
+        for (i, data) in video_buffer.iter().enumerate() {
+            let draw_pixel = *data != 0;
+
+            buffer[i * 3] = if draw_pixel {
+                front_color[0]
+            } else {
+                back_color[0]
+            };
+            buffer[i * 3 + 1] = if draw_pixel {
+                front_color[1]
+            } else {
+                back_color[1]
+            };
+            buffer[i * 3 + 2] = if draw_pixel {
+                front_color[2]
+            } else {
+                back_color[2]
+            };
+        }
+    })?;
 
-    canvas
-        .copy(&texture, None, None)
-        .expect("No se pudo copiar al render buffer");
+    canvas.copy(texture, None, picture)?;
+    Ok(())
 }
@@ -0,0 +1,385 @@
+//! Lockstep netplay between two interpreter instances.
+//!
+//! Both peers run the same program and exchange their keypad state every
+//! frame before executing it, so each machine sees exactly the same input.
+//! The host picks the seed for the random number generator, and both peers
+//! periodically compare a hash of their machine state to detect desyncs.
+//!
+//! Every message is a tag byte followed by big endian fields:
+//!
+//! | tag | message | payload                                   |
+//! |-----|---------|-------------------------------------------|
+//! | 0   | hello   | version u8, seed u64, state u64, keys u16 |
+//! | 1   | input   | frame u32, keys u16                       |
+//! | 2   | hash    | frame u32, state u64                      |
+//!
+//! The state of the hello also covers the quirks and the speed. The keys of
+//! the hello are those the sender controls. The peers must split the keypad
+//! between them, or both control every key.
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use chip_8::quirks::Quirks;
+
+const PROTOCOL_VERSION: u8 = 3;
+
+const TAG_HELLO: u8 = 0;
+const TAG_INPUT: u8 = 1;
+const TAG_HASH: u8 = 2;
+
+/// Keys on the left half of the keypad
+/// 1 2 . .
+/// 4 5 . .
+/// 7 8 . .
+/// A 0 . .
+const LEFT_KEYS: u16 =
+    1 << 0x1 | 1 << 0x2 | 1 << 0x4 | 1 << 0x5 | 1 << 0x7 | 1 << 0x8 | 1 << 0xA | 1 << 0x0;
+const ALL_KEYS: u16 = 0xFFFF;
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Host,
+    Join,
+}
+
+/// Part of the keypad a player controls.
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyOwnership {
+    /// Both players can press every key
+    All,
+    /// 1, 2, 4, 5, 7, 8, A and 0
+    Left,
+    /// 3, C, 6, D, 9, E, B and F
+    Right,
+}
+
+impl KeyOwnership {
+    fn mask(self) -> u16 {
+        match self {
+            KeyOwnership::All => ALL_KEYS,
+            KeyOwnership::Left => LEFT_KEYS,
+            KeyOwnership::Right => !LEFT_KEYS,
+        }
+    }
+}
+
+fn default_keys() -> KeyOwnership {
+    KeyOwnership::All
+}
+
+fn default_hash_interval() -> u32 {
+    60
+}
+
+#[derive(Deserialize)]
+pub struct NetplayConfig {
+    pub mode: Mode,
+    pub address: String,
+    /// Keys controlled by this player, the peer owns the rest
+    #[serde(default = "default_keys")]
+    pub keys: KeyOwnership,
+    /// Frames between state hash comparisons, 0 disables them
+    #[serde(default = "default_hash_interval")]
+    pub hash_interval: u32,
+}
+
+pub struct Session {
+    stream: TcpStream,
+    local_mask: u16,
+    remote_mask: u16,
+    hash_interval: u32,
+    frame: u32,
+    seed: u64,
+}
+
+impl Session {
+    /// Waits for the peer (host) or connects to it (join) and performs the
+    /// handshake. `state_hash` is the hash of the freshly loaded machine, it
+    /// is used with `quirks` and `cycles_per_frame` to check that both peers
+    /// run the same program the same way.
+    pub fn connect(
+        config: &NetplayConfig,
+        state_hash: u64,
+        quirks: Quirks,
+        cycles_per_frame: i32,
+    ) -> Result<Session> {
+        let stream = match config.mode {
+            Mode::Host => TcpListener::bind(config.address.as_str())?.accept()?.0,
+            Mode::Join => TcpStream::connect(config.address.as_str())?,
+        };
+        Session::start(
+            stream,
+            config,
+            setup_hash(state_hash, quirks, cycles_per_frame),
+        )
+    }
+
+    /// Performs the handshake over a connected `stream`.
+    fn start(mut stream: TcpStream, config: &NetplayConfig, state_hash: u64) -> Result<Session> {
+        let local_mask = config.keys.mask();
+        let (seed, remote_mask) = match config.mode {
+            Mode::Host => {
+                let seed = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_nanos() as u64)
+                    .unwrap_or_default();
+                send_hello(&mut stream, seed, state_hash, local_mask)?;
+                let (_, remote_mask) = expect_hello(&mut stream, state_hash)?;
+                (seed, remote_mask)
+            }
+            Mode::Join => {
+                let (seed, remote_mask) = expect_hello(&mut stream, state_hash)?;
+                send_hello(&mut stream, seed, state_hash, local_mask)?;
+                (seed, remote_mask)
+            }
+        };
+        stream.set_nodelay(true)?;
+
+        let shared = local_mask == ALL_KEYS && remote_mask == ALL_KEYS;
+        let split = local_mask & remote_mask == 0 && local_mask | remote_mask == ALL_KEYS;
+        if !shared && !split {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "the peer controls the keys {:04X} and this player {:04X}, they must \
+                     split the keypad or both control all of it",
+                    remote_mask, local_mask
+                ),
+            ));
+        }
+
+        Ok(Session {
+            stream,
+            local_mask,
+            remote_mask,
+            hash_interval: config.hash_interval,
+            frame: 0,
+            seed,
+        })
+    }
+
+    /// Seed agreed with the peer for the random number generator.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Sends the local keys for the current frame and blocks until the peer
+    /// sends theirs, returns the keys both machines must use for the frame.
+    pub fn exchange_keys(&mut self, local_keys: u16) -> Result<u16> {
+        let mut message = [TAG_INPUT, 0, 0, 0, 0, 0, 0];
+        message[1..5].copy_from_slice(&self.frame.to_be_bytes());
+        message[5..7].copy_from_slice(&(local_keys & self.local_mask).to_be_bytes());
+        self.stream.write_all(&message)?;
+
+        let mut reply = [0; 7];
+        self.read_message(TAG_INPUT, &mut reply)?;
+        self.check_frame(&reply[1..5])?;
+        let remote_keys = u16::from_be_bytes([reply[5], reply[6]]);
+
+        Ok((local_keys & self.local_mask) | (remote_keys & self.remote_mask))
+    }
+
+    /// Ends the current frame, every `hash_interval` frames the state hash
+    /// is compared with the peer's one.
+    pub fn end_frame(&mut self, state_hash: u64) -> Result<()> {
+        if self.hash_interval != 0 && self.frame.is_multiple_of(self.hash_interval) {
+            let mut message = [0; 13];
+            message[0] = TAG_HASH;
+            message[1..5].copy_from_slice(&self.frame.to_be_bytes());
+            message[5..13].copy_from_slice(&state_hash.to_be_bytes());
+            self.stream.write_all(&message)?;
+
+            let mut reply = [0; 13];
+            self.read_message(TAG_HASH, &mut reply)?;
+            self.check_frame(&reply[1..5])?;
+            let mut remote_hash = [0; 8];
+            remote_hash.copy_from_slice(&reply[5..13]);
+            if u64::from_be_bytes(remote_hash) != state_hash {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("desync detected at frame {}", self.frame),
+                ));
+            }
+        }
+        self.frame = self.frame.wrapping_add(1);
+        Ok(())
+    }
+
+    fn read_message(&mut self, tag: u8, buffer: &mut [u8]) -> Result<()> {
+        self.stream.read_exact(buffer)?;
+        if buffer[0] != tag {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unexpected message {}, expected {}", buffer[0], tag),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_frame(&self, frame: &[u8]) -> Result<()> {
+        let remote_frame = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]);
+        if remote_frame != self.frame {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("peer is at frame {}, expected {}", remote_frame, self.frame),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// FNV-1a of `state_hash`, `quirks` and `cycles_per_frame`
+fn setup_hash(state_hash: u64, quirks: Quirks, cycles_per_frame: i32) -> u64 {
+    let Quirks {
+        shift,
+        memory_increment_by_x,
+        memory_leave_i_unchanged,
+        wrap,
+        jump,
+        vblank,
+        logic,
+    } = quirks;
+    let mut bytes = state_hash.to_be_bytes().to_vec();
+    bytes.extend(
+        [
+            shift,
+            memory_increment_by_x,
+            memory_leave_i_unchanged,
+            wrap,
+            jump,
+            vblank,
+            logic,
+        ]
+        .iter()
+        .map(|quirk| *quirk as u8),
+    );
+    bytes.extend_from_slice(&cycles_per_frame.to_be_bytes());
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn send_hello(stream: &mut TcpStream, seed: u64, state_hash: u64, keys: u16) -> Result<()> {
+    let mut message = [0; 20];
+    message[0] = TAG_HELLO;
+    message[1] = PROTOCOL_VERSION;
+    message[2..10].copy_from_slice(&seed.to_be_bytes());
+    message[10..18].copy_from_slice(&state_hash.to_be_bytes());
+    message[18..20].copy_from_slice(&keys.to_be_bytes());
+    stream.write_all(&message)
+}
+
+/// Reads the peer's hello, returns the seed and the keys it sent.
+fn expect_hello(stream: &mut TcpStream, state_hash: u64) -> Result<(u64, u16)> {
+    let mut message = [0; 20];
+    stream.read_exact(&mut message)?;
+    if message[0] != TAG_HELLO || message[1] != PROTOCOL_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "the peer is not a compatible chip-8 interpreter",
+        ));
+    }
+
+    let mut seed = [0; 8];
+    seed.copy_from_slice(&message[2..10]);
+    let mut remote_hash = [0; 8];
+    remote_hash.copy_from_slice(&message[10..18]);
+    if u64::from_be_bytes(remote_hash) != state_hash {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "the peer loaded a different program, or runs it with other quirks or speed",
+        ));
+    }
+
+    Ok((
+        u64::from_be_bytes(seed),
+        u16::from_be_bytes([message[18], message[19]]),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Result;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use chip_8::quirks::Quirks;
+
+    use crate::netplay::{setup_hash, KeyOwnership, Mode, NetplayConfig, Session};
+
+    fn config(mode: Mode, keys: KeyOwnership) -> NetplayConfig {
+        NetplayConfig {
+            mode,
+            address: String::new(),
+            keys,
+            hash_interval: 2,
+        }
+    }
+
+    /// Host and join sessions over loopback, the host's result first
+    fn sessions(
+        host_keys: KeyOwnership,
+        join_keys: KeyOwnership,
+        join_hash: u64,
+    ) -> (Result<Session>, Result<Session>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let join = thread::spawn(move || {
+            let stream = TcpStream::connect(address).unwrap();
+            Session::start(stream, &config(Mode::Join, join_keys), join_hash)
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let host = Session::start(stream, &config(Mode::Host, host_keys), 7);
+        (host, join.join().unwrap())
+    }
+
+    #[test]
+    fn lockstep() {
+        let (host, join) = sessions(KeyOwnership::Left, KeyOwnership::Right, 7);
+        let (mut host, mut join) = (host.unwrap(), join.unwrap());
+        assert_eq!(host.seed(), join.seed());
+
+        let join = thread::spawn(move || {
+            let keys = join.exchange_keys(1 << 0x1 | 1 << 0xC).unwrap();
+            join.end_frame(42).unwrap();
+            keys
+        });
+        let keys = host.exchange_keys(1 << 0x2 | 1 << 0xD).unwrap();
+        host.end_frame(42).unwrap();
+        // Each player only presses the keys it owns
+        assert_eq!(keys, 1 << 0x2 | 1 << 0xC);
+        assert_eq!(join.join().unwrap(), keys);
+    }
+
+    #[test]
+    fn handshake_errors() {
+        let (host, join) = sessions(KeyOwnership::Left, KeyOwnership::Left, 7);
+        assert!(host.is_err() && join.is_err());
+        let (host, join) = sessions(KeyOwnership::All, KeyOwnership::Right, 7);
+        assert!(host.is_err() && join.is_err());
+        let (host, _) = sessions(KeyOwnership::All, KeyOwnership::All, 8);
+        assert!(host.is_err());
+        let (host, join) = sessions(KeyOwnership::All, KeyOwnership::All, 7);
+        assert!(host.is_ok() && join.is_ok());
+    }
+
+    #[test]
+    fn setup() {
+        let hash = setup_hash(7, Quirks::default(), 10);
+        assert_eq!(setup_hash(7, Quirks::default(), 10), hash);
+        assert_ne!(setup_hash(8, Quirks::default(), 10), hash);
+        assert_ne!(setup_hash(7, Quirks::VIP, 10), hash);
+        let quirks = Quirks {
+            logic: !Quirks::default().logic,
+            ..Quirks::default()
+        };
+        assert_ne!(setup_hash(7, quirks, 10), hash);
+        assert_ne!(setup_hash(7, Quirks::default(), 11), hash);
+    }
+}
@@ -0,0 +1,1320 @@
+//! Assembler for Octo, the language Octo cartridges carry their programs in.
+//!
+//! The whole language of Octo 1.2 is understood: the CHIP-8, SUPER-CHIP
+//! and XO-CHIP statements, `if`/`begin`/`else`/`end`, `loop`/`while`/`again`,
+//! the comparison pseudo-instructions using `vF`, labels, `:const`,
+//! `:alias`, `:unpack`, `:next`, `:org`, `:byte`, `:pointer`, `:call`,
+//! `:macro`, `:calc`, `:stringmode` and `:assert`. `:breakpoint`, `:monitor`
+//! and `:proto` only matter to Octo's debugger and are skipped.
+//!
+//! Octo reserves 0x200 for a jump to `main`. When the source starts with
+//! `: main` the jump would lead to the next instruction, so it is left out
+//! and the program starts right at 0x200. This keeps the ROMs of cartridges
+//! exported as byte literals where they were.
+//!
+//! `:calc` expressions have no precedence and are evaluated right to left,
+//! as in Octo: `{ 1 + 2 * 3 }` is 7 and `{ 2 * 3 + 1 }` is 8.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Where programs start
+const START: usize = 0x200;
+const MEMORY: usize = 0x10000;
+/// Macro expansions before a recursive macro is reported
+const MAX_EXPANSIONS: usize = 100_000;
+
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Assembles Octo `source` into the bytes loaded at 0x200
+pub fn assemble(source: &str) -> Result<Vec<u8>> {
+    Assembler::new(tokenize(source)).run()
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+impl Token {
+    fn is_string(&self) -> bool {
+        self.text.starts_with('"')
+    }
+}
+
+/// Splits on whitespace, drops `#` comments and keeps `"strings"` whole
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                break;
+            } else if c == '"' {
+                let mut text = String::from(chars.next().unwrap());
+                for c in chars.by_ref() {
+                    text.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+                tokens.push(Token {
+                    text,
+                    line: index + 1,
+                });
+            } else {
+                let mut text = String::new();
+                while let Some(&c) = chars.peek().filter(|c| !c.is_whitespace()) {
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push(Token {
+                    text,
+                    line: index + 1,
+                });
+            }
+        }
+    }
+    tokens
+}
+
+/// How an address not defined yet is written once it is
+#[derive(Clone, Copy)]
+enum Patch {
+    /// The 12 bits of `NNN` in the instruction at the address
+    Nnn,
+    /// Two bytes, big endian
+    Long,
+    /// `(value >> shift) | or` in the byte at the address
+    Byte { shift: u8, or: u8 },
+}
+
+enum Address {
+    Value(f64),
+    Label(String),
+}
+
+struct Proto {
+    name: String,
+    address: usize,
+    patch: Patch,
+    line: usize,
+}
+
+/// Open `begin`, `else` and `loop` blocks
+enum Block {
+    /// The jump to patch with the address of `else` or `end`
+    If(usize),
+    Else(usize),
+    /// The start of the loop and the jumps out of it from `while`
+    Loop(usize, Vec<usize>),
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+struct StringMode {
+    alphabet: Vec<char>,
+    body: Vec<Token>,
+}
+
+/// Comparison of an `if` or `while`
+enum Condition {
+    /// `==` or `!=` with a byte, `equal` for `==`
+    Byte {
+        x: u8,
+        value: u8,
+        equal: bool,
+    },
+    Register {
+        x: u8,
+        y: u8,
+        equal: bool,
+    },
+    /// `key`, or `-key` when not `pressed`
+    Key {
+        x: u8,
+        pressed: bool,
+    },
+    /// `<`, `>`, `<=` and `>=`, computed into `vF` which is `flag` when true
+    Ordered {
+        setup: [[u8; 2]; 2],
+        flag: u8,
+    },
+}
+
+struct Assembler {
+    tokens: Vec<Token>,
+    position: usize,
+    memory: Vec<u8>,
+    here: usize,
+    /// One past the highest address written
+    end: usize,
+    line: usize,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    string_modes: HashMap<String, StringMode>,
+    protos: Vec<Proto>,
+    blocks: Vec<Block>,
+    /// `:next` label, given the immediate of the next instruction
+    next: Option<String>,
+    expansions: usize,
+}
+
+impl Assembler {
+    fn new(tokens: Vec<Token>) -> Assembler {
+        Assembler {
+            tokens,
+            position: 0,
+            memory: vec![0; MEMORY],
+            here: START,
+            end: START,
+            line: 1,
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            string_modes: HashMap::new(),
+            protos: Vec::new(),
+            blocks: Vec::new(),
+            next: None,
+            expansions: 0,
+        }
+    }
+
+    fn run(mut self) -> Result<Vec<u8>> {
+        let main_first = matches!(&self.tokens[..], [colon, main, ..] if colon.text == ":" && main.text == "main");
+        if !main_first {
+            self.instruction(0x10, 0x00)?;
+        }
+
+        while self.position < self.tokens.len() {
+            self.statement()?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let what = match block {
+                Block::If(_) | Block::Else(_) => "`begin` without `end`",
+                Block::Loop(..) => "`loop` without `again`",
+            };
+            return Err(self.error(what));
+        }
+        if let Some(name) = &self.next {
+            return Err(self.error(format!("`:next {}` without an instruction", name)));
+        }
+        let main = self
+            .constants
+            .get("main")
+            .copied()
+            .ok_or_else(|| self.error("the program has no `main` label"))?;
+        if !main_first {
+            self.patch(START, Patch::Nnn, main as usize);
+        }
+        for proto in std::mem::take(&mut self.protos) {
+            let value = self.constants.get(&proto.name).copied().ok_or(Error {
+                line: proto.line,
+                message: format!("undefined name {}", proto.name),
+            })?;
+            self.patch(proto.address, proto.patch, value as usize);
+        }
+        Ok(self.memory[START..self.end.max(START)].to_vec())
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> Error {
+        Error {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| self.error("unexpected end of the program"))?;
+        self.position += 1;
+        self.line = token.line;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens
+            .get(self.position)
+            .map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let token = self.next_token()?;
+        if token.text != text {
+            return Err(self.error(format!("expected `{}`, found `{}`", text, token.text)));
+        }
+        Ok(())
+    }
+
+    /// A name for a label, constant, alias or macro
+    fn name(&mut self) -> Result<String> {
+        let token = self.next_token()?;
+        if parse_number(&token.text).is_some()
+            || register(&token.text).is_some()
+            || token.is_string()
+            || KEYWORDS.contains(&token.text.as_str())
+        {
+            return Err(self.error(format!("`{}` can't be a name", token.text)));
+        }
+        Ok(token.text)
+    }
+
+    /// The tokens between `{` and the matching `}`
+    fn braces(&mut self) -> Result<Vec<Token>> {
+        self.expect("{")?;
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn statement(&mut self) -> Result<()> {
+        let token = self.next_token()?;
+        let text = token.text.as_str();
+        if let Some(x) = self.register(text) {
+            return self.assignment(x);
+        }
+        match text {
+            ":" => {
+                let name = self.name()?;
+                self.define(name, self.here as f64)
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.define(name, value)
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let expression = self.braces()?;
+                let value = self.calculate(&expression)?;
+                self.define(name, value)
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let x = if self.peek() == Some("{") {
+                    let expression = self.braces()?;
+                    let value = self.calculate(&expression)?;
+                    if !(0.0..16.0).contains(&value) {
+                        return Err(self.error(format!("{} is not a register", value)));
+                    }
+                    value as u8
+                } else {
+                    self.register_operand()?
+                };
+                self.aliases.insert(name, x);
+                Ok(())
+            }
+            ":unpack" => {
+                let token = self.next_token()?;
+                let or = if token.text == "long" {
+                    0
+                } else {
+                    self.number(&token.text, 0.0, 15.0)? << 4
+                };
+                let address = self.address()?;
+                self.emit_address(0x6000, Patch::Byte { shift: 8, or }, &address)?;
+                self.emit_address(0x6100, Patch::Byte { shift: 0, or: 0 }, &address)
+            }
+            ":next" => {
+                let name = self.name()?;
+                self.next = Some(name);
+                Ok(())
+            }
+            ":org" => {
+                let address = self.value()?;
+                if !(0.0..MEMORY as f64).contains(&address) {
+                    return Err(self.error(format!("address {} is outside memory", address)));
+                }
+                self.here = address as usize;
+                Ok(())
+            }
+            ":byte" => {
+                let value = self.value()?;
+                let byte = self.byte_value(value)?;
+                self.emit(&[byte])
+            }
+            ":pointer" => self.address_operand(0x0000, Patch::Long),
+            ":call" => self.address_operand(0x2000, Patch::Nnn),
+            ":macro" => {
+                let name = self.name()?;
+                let mut parameters = Vec::new();
+                while self.peek().is_some_and(|text| text != "{") {
+                    parameters.push(self.name()?);
+                }
+                let body = self.braces()?;
+                self.macros.insert(
+                    name,
+                    Macro {
+                        parameters,
+                        body,
+                        calls: 0,
+                    },
+                );
+                Ok(())
+            }
+            ":stringmode" => {
+                let name = self.name()?;
+                let alphabet = self.string()?.chars().collect();
+                let body = self.braces()?;
+                self.string_modes
+                    .insert(name, StringMode { alphabet, body });
+                Ok(())
+            }
+            ":assert" => {
+                let message = match self.peek() {
+                    Some(text) if text.starts_with('"') => self.string()?,
+                    _ => String::from("assertion failed"),
+                };
+                let expression = self.braces()?;
+                if self.calculate(&expression)? == 0.0 {
+                    return Err(self.error(message));
+                }
+                Ok(())
+            }
+            ":breakpoint" | ":proto" => self.next_token().map(|_| ()),
+            ":monitor" => {
+                self.next_token()?;
+                self.next_token().map(|_| ())
+            }
+            "return" | ";" => self.instruction(0x00, 0xEE),
+            "clear" => self.instruction(0x00, 0xE0),
+            "scroll-right" => self.instruction(0x00, 0xFB),
+            "scroll-left" => self.instruction(0x00, 0xFC),
+            "exit" => self.instruction(0x00, 0xFD),
+            "lores" => self.instruction(0x00, 0xFE),
+            "hires" => self.instruction(0x00, 0xFF),
+            "audio" => self.instruction(0xF0, 0x02),
+            "scroll-down" | "scroll-up" => {
+                let n = self.nibble()?;
+                let base = if text == "scroll-down" { 0xC0 } else { 0xD0 };
+                self.instruction(0x00, base | n)
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.instruction(0xF0 | n, 0x01)
+            }
+            "bcd" => self.register_instruction(0x33),
+            "saveflags" => self.register_instruction(0x75),
+            "loadflags" => self.register_instruction(0x85),
+            "save" | "load" => {
+                let x = self.register_operand()?;
+                if self.peek() == Some("-") {
+                    self.next_token()?;
+                    let y = self.register_operand()?;
+                    let n = if text == "save" { 0x2 } else { 0x3 };
+                    self.instruction(0x50 | x, y << 4 | n)
+                } else {
+                    let n = if text == "save" { 0x55 } else { 0x65 };
+                    self.instruction(0xF0 | x, n)
+                }
+            }
+            "sprite" => {
+                let x = self.register_operand()?;
+                let y = self.register_operand()?;
+                let n = self.nibble()?;
+                self.instruction(0xD0 | x, y << 4 | n)
+            }
+            "jump" => self.address_operand(0x1000, Patch::Nnn),
+            "jump0" => self.address_operand(0xB000, Patch::Nnn),
+            "native" => self.address_operand(0x0000, Patch::Nnn),
+            "i" => self.index(),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let n = match text {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.register_instruction(n)
+            }
+            "if" => {
+                let condition = self.condition()?;
+                let token = self.next_token()?;
+                match token.text.as_str() {
+                    "then" => self.skip(&condition, false),
+                    "begin" => {
+                        self.skip(&condition, true)?;
+                        self.blocks.push(Block::If(self.here));
+                        self.instruction(0x10, 0x00)
+                    }
+                    other => {
+                        Err(self.error(format!("expected `then` or `begin`, found `{}`", other)))
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If(jump)) => {
+                    let end = self.here;
+                    self.instruction(0x10, 0x00)?;
+                    self.patch(jump, Patch::Nnn, self.here);
+                    self.blocks.push(Block::Else(end));
+                    Ok(())
+                }
+                _ => Err(self.error("`else` without `begin`")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If(jump)) | Some(Block::Else(jump)) => {
+                    self.patch(jump, Patch::Nnn, self.here);
+                    Ok(())
+                }
+                _ => Err(self.error("`end` without `begin`")),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop(self.here, Vec::new()));
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition()?;
+                self.skip(&condition, true)?;
+                let jump = self.here;
+                match self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop(_, exits) => Some(exits),
+                    _ => None,
+                }) {
+                    Some(exits) => exits.push(jump),
+                    None => return Err(self.error("`while` outside a loop")),
+                }
+                self.instruction(0x10, 0x00)
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop(start, exits)) => {
+                    self.instruction(0x10 | (start >> 8) as u8 & 0xF, start as u8)?;
+                    for exit in exits {
+                        self.patch(exit, Patch::Nnn, self.here);
+                    }
+                    Ok(())
+                }
+                _ => Err(self.error("`again` without `loop`")),
+            },
+            _ if self.macros.contains_key(text) => self.expand(text),
+            _ if self.string_modes.contains_key(text) => self.expand_string(text),
+            _ if text == "{" || parse_number(text).is_some() => {
+                self.position -= 1;
+                let value = self.value()?;
+                let byte = self.byte_value(value)?;
+                self.emit(&[byte])
+            }
+            _ if KEYWORDS.contains(&text) || token.is_string() => {
+                Err(self.error(format!("unexpected `{}`", text)))
+            }
+            // Any other name calls a subroutine
+            _ => {
+                self.position -= 1;
+                self.address_operand(0x2000, Patch::Nnn)
+            }
+        }
+    }
+
+    /// `vX := ...`, `vX += ...` and the other operators
+    fn assignment(&mut self, x: u8) -> Result<()> {
+        let operator = self.next_token()?.text;
+        let operand = self.peek().unwrap_or_default().to_string();
+        if let Some(y) = self.register(&operand) {
+            self.next_token()?;
+            let n = match operator.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return Err(self.error(format!("unknown operator `{}`", operator))),
+            };
+            return self.instruction(0x80 | x, y << 4 | n);
+        }
+
+        match (operator.as_str(), operand.as_str()) {
+            (":=", "key") => {
+                self.next_token()?;
+                self.instruction(0xF0 | x, 0x0A)
+            }
+            (":=", "delay") => {
+                self.next_token()?;
+                self.instruction(0xF0 | x, 0x07)
+            }
+            (":=", "random") => {
+                self.next_token()?;
+                let mask = self.byte()?;
+                self.instruction(0xC0 | x, mask)
+            }
+            (":=", _) => {
+                let value = self.byte()?;
+                self.instruction(0x60 | x, value)
+            }
+            ("+=", _) => {
+                let value = self.byte()?;
+                self.instruction(0x70 | x, value)
+            }
+            ("-=", _) => {
+                let value = self.byte()?;
+                self.instruction(0x70 | x, value.wrapping_neg())
+            }
+            _ => Err(self.error(format!(
+                "`{}` needs a register, found `{}`",
+                operator, operand
+            ))),
+        }
+    }
+
+    /// `i := ...` and `i += vX`
+    fn index(&mut self) -> Result<()> {
+        let operator = self.next_token()?.text;
+        match operator.as_str() {
+            "+=" => self.register_instruction(0x1E),
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next_token()?;
+                    self.register_instruction(0x29)
+                }
+                Some("bighex") => {
+                    self.next_token()?;
+                    self.register_instruction(0x30)
+                }
+                Some("long") => {
+                    self.next_token()?;
+                    self.instruction(0xF0, 0x00)?;
+                    self.address_operand(0x0000, Patch::Long)
+                }
+                _ => self.address_operand(0xA000, Patch::Nnn),
+            },
+            _ => Err(self.error(format!("unknown operator `i {}`", operator))),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition> {
+        let x = self.register_operand()?;
+        let operator = self.next_token()?.text;
+        match operator.as_str() {
+            "key" => return Ok(Condition::Key { x, pressed: true }),
+            "-key" => return Ok(Condition::Key { x, pressed: false }),
+            _ => {}
+        }
+
+        let operand = self.peek().unwrap_or_default().to_string();
+        let y = self.register(&operand);
+        let load = match y {
+            Some(y) => {
+                self.next_token()?;
+                [0x8F, y << 4]
+            }
+            None => [0x6F, self.byte()?],
+        };
+        match (operator.as_str(), y) {
+            ("==", Some(y)) | ("!=", Some(y)) => Ok(Condition::Register {
+                x,
+                y,
+                equal: operator == "==",
+            }),
+            ("==", None) | ("!=", None) => Ok(Condition::Byte {
+                x,
+                value: load[1],
+                equal: operator == "==",
+            }),
+            // vF := operand, vF =- vX: vF is 1 when vX >= operand
+            (">=", _) | ("<", _) => Ok(Condition::Ordered {
+                setup: [load, [0x8F, x << 4 | 0x7]],
+                flag: (operator == ">=") as u8,
+            }),
+            // vF := operand, vF -= vX: vF is 1 when vX <= operand
+            ("<=", _) | (">", _) => Ok(Condition::Ordered {
+                setup: [load, [0x8F, x << 4 | 0x5]],
+                flag: (operator == "<=") as u8,
+            }),
+            _ => Err(self.error(format!("unknown comparison `{}`", operator))),
+        }
+    }
+
+    /// Emits what skips the next instruction when `condition` is `when`
+    fn skip(&mut self, condition: &Condition, when: bool) -> Result<()> {
+        match *condition {
+            Condition::Byte { x, value, equal } => {
+                let opcode = if equal == when { 0x30 } else { 0x40 };
+                self.instruction(opcode | x, value)
+            }
+            Condition::Register { x, y, equal } => {
+                let opcode = if equal == when { 0x50 } else { 0x90 };
+                self.instruction(opcode | x, y << 4)
+            }
+            Condition::Key { x, pressed } => {
+                let n = if pressed == when { 0x9E } else { 0xA1 };
+                self.instruction(0xE0 | x, n)
+            }
+            Condition::Ordered { setup, flag } => {
+                for [high, low] in setup {
+                    self.instruction(high, low)?;
+                }
+                let opcode = if when { 0x3F } else { 0x4F };
+                self.instruction(opcode, flag)
+            }
+        }
+    }
+
+    fn expand(&mut self, name: &str) -> Result<()> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error(format!("the macro {} never stops expanding", name)));
+        }
+        let count = self.macros[name].parameters.len();
+        let mut arguments = HashMap::new();
+        for index in 0..count {
+            let argument = self.next_token()?;
+            arguments.insert(self.macros[name].parameters[index].clone(), argument);
+        }
+
+        let definition = self.macros.get_mut(name).unwrap();
+        let calls = definition.calls;
+        definition.calls += 1;
+        let line = self.line;
+        let body: Vec<Token> = definition
+            .body
+            .iter()
+            .map(|token| match arguments.get(&token.text) {
+                Some(argument) => argument.clone(),
+                None if token.text == "CALLS" => Token {
+                    text: calls.to_string(),
+                    line,
+                },
+                None => token.clone(),
+            })
+            .collect();
+        self.tokens.splice(self.position..self.position, body);
+        Ok(())
+    }
+
+    fn expand_string(&mut self, name: &str) -> Result<()> {
+        let text = self.string()?;
+        let mode = &self.string_modes[name];
+        let mut expansion = Vec::new();
+        for (index, c) in text.chars().enumerate() {
+            let value = mode
+                .alphabet
+                .iter()
+                .position(|letter| *letter == c)
+                .ok_or_else(|| self.error(format!("`{}` is not in the string mode {}", c, name)))?;
+            for token in &mode.body {
+                let text = match token.text.as_str() {
+                    "CHAR" => (c as u32).to_string(),
+                    "INDEX" => index.to_string(),
+                    "VALUE" => value.to_string(),
+                    _ => token.text.clone(),
+                };
+                expansion.push(Token {
+                    text,
+                    line: self.line,
+                });
+            }
+        }
+        self.tokens.splice(self.position..self.position, expansion);
+        Ok(())
+    }
+
+    /// The text of a `"string"`, escapes `\n`, `\t`, `\"` and `\\` resolved
+    fn string(&mut self) -> Result<String> {
+        let token = self.next_token()?;
+        let inner = token
+            .text
+            .strip_prefix('"')
+            .and_then(|text| text.strip_suffix('"'))
+            .ok_or_else(|| self.error(format!("expected a string, found `{}`", token.text)))?;
+        let mut text = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some(c) => text.push(c),
+                    None => {}
+                }
+            } else {
+                text.push(c);
+            }
+        }
+        Ok(text)
+    }
+
+    fn define(&mut self, name: String, value: f64) -> Result<()> {
+        if self.constants.contains_key(&name) {
+            return Err(self.error(format!("{} is already defined", name)));
+        }
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    fn register(&self, text: &str) -> Option<u8> {
+        register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn register_operand(&mut self) -> Result<u8> {
+        let token = self.next_token()?;
+        self.register(&token.text)
+            .ok_or_else(|| self.error(format!("expected a register, found `{}`", token.text)))
+    }
+
+    /// `FX` instructions taking a register
+    fn register_instruction(&mut self, n: u8) -> Result<()> {
+        let x = self.register_operand()?;
+        self.instruction(0xF0 | x, n)
+    }
+
+    /// A number, a constant or a `{ calc }` expression
+    fn value(&mut self) -> Result<f64> {
+        if self.peek() == Some("{") {
+            let expression = self.braces()?;
+            return self.calculate(&expression);
+        }
+        let token = self.next_token()?;
+        self.constant(&token.text)
+            .ok_or_else(|| self.error(format!("undefined name {}", token.text)))
+    }
+
+    fn constant(&self, text: &str) -> Option<f64> {
+        parse_number(text).or_else(|| self.constants.get(text).copied())
+    }
+
+    fn number(&self, text: &str, min: f64, max: f64) -> Result<u8> {
+        let value = self
+            .constant(text)
+            .ok_or_else(|| self.error(format!("undefined name {}", text)))?;
+        if !(min..=max).contains(&value.floor()) {
+            return Err(self.error(format!("{} is out of range", value)));
+        }
+        Ok(value.floor() as i64 as u8)
+    }
+
+    fn byte_value(&self, value: f64) -> Result<u8> {
+        let value = value.floor();
+        if !(-128.0..=255.0).contains(&value) {
+            return Err(self.error(format!("{} doesn't fit in a byte", value)));
+        }
+        Ok(value as i64 as u8)
+    }
+
+    /// A byte operand, -128 to 255
+    fn byte(&mut self) -> Result<u8> {
+        let value = self.value()?;
+        self.byte_value(value)
+    }
+
+    fn nibble(&mut self) -> Result<u8> {
+        let value = self.value()?.floor();
+        if !(0.0..=15.0).contains(&value) {
+            return Err(self.error(format!("{} doesn't fit in 4 bits", value)));
+        }
+        Ok(value as u8)
+    }
+
+    /// Emits `opcode` with an address, which may be defined later
+    fn address_operand(&mut self, opcode: u16, patch: Patch) -> Result<()> {
+        let address = self.address()?;
+        self.emit_address(opcode, patch, &address)
+    }
+
+    /// An address operand, or the name of a label defined later
+    fn address(&mut self) -> Result<Address> {
+        if self.peek() == Some("{") {
+            return Ok(Address::Value(self.value()?));
+        }
+        let token = self.next_token()?;
+        match self.constant(&token.text) {
+            Some(value) => Ok(Address::Value(value)),
+            None if parse_number(&token.text).is_none()
+                && register(&token.text).is_none()
+                && !KEYWORDS.contains(&token.text.as_str()) =>
+            {
+                Ok(Address::Label(token.text))
+            }
+            None => Err(self.error(format!("expected an address, found `{}`", token.text))),
+        }
+    }
+
+    fn emit_address(&mut self, opcode: u16, patch: Patch, address: &Address) -> Result<()> {
+        let here = self.here;
+        let [high, low] = opcode.to_be_bytes();
+        match patch {
+            Patch::Long => {
+                // The immediate of `i := long` is the instruction's second
+                // half, its first was emitted already
+                self.emit(&[0, 0])?;
+            }
+            _ => self.instruction(high, low)?,
+        }
+        match address {
+            Address::Value(value) => {
+                let limit = match patch {
+                    Patch::Nnn => 0xFFF,
+                    _ => 0xFFFF,
+                };
+                if !(0.0..=limit as f64).contains(&value.floor()) {
+                    return Err(self.error(format!("address {} is out of range", value)));
+                }
+                self.patch(here, patch, value.floor() as usize);
+            }
+            Address::Label(name) => self.protos.push(Proto {
+                name: name.clone(),
+                address: here,
+                patch,
+                line: self.line,
+            }),
+        }
+        Ok(())
+    }
+
+    fn patch(&mut self, address: usize, patch: Patch, value: usize) {
+        match patch {
+            Patch::Nnn => {
+                self.memory[address] = self.memory[address] & 0xF0 | (value >> 8) as u8 & 0xF;
+                self.memory[address + 1] = value as u8;
+            }
+            Patch::Long => {
+                self.memory[address] = (value >> 8) as u8;
+                self.memory[address + 1] = value as u8;
+            }
+            Patch::Byte { shift, or } => {
+                self.memory[address + 1] = (value >> shift) as u8 | or;
+            }
+        }
+    }
+
+    fn instruction(&mut self, high: u8, low: u8) -> Result<()> {
+        if let Some(name) = self.next.take() {
+            self.define(name, (self.here + 1) as f64)?;
+        }
+        self.emit(&[high, low])
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.here + bytes.len() > MEMORY {
+            return Err(self.error("the program is larger than 64 KiB"));
+        }
+        self.memory[self.here..self.here + bytes.len()].copy_from_slice(bytes);
+        self.here += bytes.len();
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    /// Evaluates `{ calc }` tokens, right to left
+    fn calculate(&self, tokens: &[Token]) -> Result<f64> {
+        let mut position = 0;
+        let value = self.expression(tokens, &mut position)?;
+        if position != tokens.len() {
+            return Err(self.error(format!("unexpected `{}`", tokens[position].text)));
+        }
+        Ok(value)
+    }
+
+    fn expression(&self, tokens: &[Token], position: &mut usize) -> Result<f64> {
+        let left = self.term(tokens, position)?;
+        let operator = match tokens.get(*position) {
+            Some(token) if token.text != ")" => token.text.as_str(),
+            _ => return Ok(left),
+        };
+        *position += 1;
+        let right = self.expression(tokens, position)?;
+        let truth = |condition: bool| condition as u8 as f64;
+        let value = match operator {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (left as i64 & right as i64) as f64,
+            "|" => (left as i64 | right as i64) as f64,
+            "^" => (left as i64 ^ right as i64) as f64,
+            "<<" => (left as i64).wrapping_shl(right as u32) as f64,
+            ">>" => (left as i64).wrapping_shr(right as u32) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => truth(left < right),
+            ">" => truth(left > right),
+            "<=" => truth(left <= right),
+            ">=" => truth(left >= right),
+            "==" => truth(left == right),
+            "!=" => truth(left != right),
+            _ => return Err(self.error(format!("unknown operator `{}`", operator))),
+        };
+        Ok(value)
+    }
+
+    fn term(&self, tokens: &[Token], position: &mut usize) -> Result<f64> {
+        let token = tokens
+            .get(*position)
+            .ok_or_else(|| self.error("incomplete expression"))?;
+        *position += 1;
+        let text = token.text.as_str();
+        if text == "(" {
+            let value = self.expression(tokens, position)?;
+            match tokens.get(*position) {
+                Some(token) if token.text == ")" => {
+                    *position += 1;
+                    return Ok(value);
+                }
+                _ => return Err(self.error("missing `)`")),
+            }
+        }
+        if text == "strlen" {
+            let token = tokens
+                .get(*position)
+                .ok_or_else(|| self.error("incomplete expression"))?;
+            *position += 1;
+            let length = token.text.trim_matches('"').chars().count();
+            return Ok(length as f64);
+        }
+        let unary: Option<fn(f64) -> f64> = match text {
+            "-" => Some(|value| -value),
+            "~" => Some(|value| !(value as i64) as f64),
+            "!" => Some(|value| (value == 0.0) as u8 as f64),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(unary) = unary {
+            return Ok(unary(self.term(tokens, position)?));
+        }
+        if text == "@" {
+            let address = self.term(tokens, position)?;
+            return Ok(self.memory.get(address as usize).copied().unwrap_or(0) as f64);
+        }
+        match text {
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => self
+                .constant(text)
+                .or_else(|| self.register(text).map(f64::from))
+                .ok_or_else(|| self.error(format!("undefined name {}", text))),
+        }
+    }
+}
+
+/// Words that can't name labels or constants
+const KEYWORDS: &[&str] = &[
+    ":=",
+    "|=",
+    "&=",
+    "^=",
+    "-=",
+    "=-",
+    "+=",
+    ">>=",
+    "<<=",
+    "==",
+    "!=",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "key",
+    "-key",
+    "hex",
+    "bighex",
+    "random",
+    "delay",
+    ":",
+    ":next",
+    ":unpack",
+    ":breakpoint",
+    ":proto",
+    ":alias",
+    ":const",
+    ":org",
+    ";",
+    "return",
+    "clear",
+    "bcd",
+    "save",
+    "load",
+    "buzzer",
+    "if",
+    "then",
+    "begin",
+    "else",
+    "end",
+    "jump",
+    "jump0",
+    "native",
+    "sprite",
+    "loop",
+    "while",
+    "again",
+    "scroll-down",
+    "scroll-right",
+    "scroll-left",
+    "lores",
+    "hires",
+    "loadflags",
+    "saveflags",
+    "i",
+    "audio",
+    "plane",
+    ":macro",
+    ":calc",
+    ":byte",
+    ":call",
+    ":stringmode",
+    ":assert",
+    ":monitor",
+    ":pointer",
+    "pitch",
+    "scroll-up",
+    "exit",
+    "long",
+    "{",
+    "}",
+];
+
+/// `v0` to `vF`, either case
+fn register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/// Decimal, `0x` hexadecimal or `0b` binary, maybe negative
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::octo::assemble;
+
+    #[test]
+    fn statements() {
+        let rom = assemble(
+            ": main
+            clear  v3 := 0x12  v3 += v4  v3 -= 1  i := shape  sprite v0 v1 5
+            v2 := random 0xF0  v2 := key  delay := v2  i := hex v2  bcd v2
+            save v5  load v5 - v7  scroll-down 3  hires  i := long shape
+            : shape 0xFF 0b10000001",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            [
+                0x00, 0xE0, 0x63, 0x12, 0x83, 0x44, 0x73, 0xFF, 0xA2, 0x22, 0xD0, 0x15, //
+                0xC2, 0xF0, 0xF2, 0x0A, 0xF2, 0x15, 0xF2, 0x29, 0xF2, 0x33, //
+                0xF5, 0x55, 0x55, 0x73, 0x00, 0xC3, 0x00, 0xFF, 0xF0, 0x00, 0x02, 0x22, //
+                0xFF, 0x81,
+            ]
+        );
+    }
+
+    #[test]
+    fn jump_to_main() {
+        // A call to a subroutine defined first, then main
+        let rom = assemble(": sub return : main sub").unwrap();
+        assert_eq!(rom, [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+    }
+
+    #[test]
+    fn control_flow() {
+        let rom = assemble(
+            ": main
+            loop
+                if v0 == 3 then v1 := 1
+                if v0 > v2 begin v1 := 2 else v1 := 3 end
+                while v0 key
+            again",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            [
+                0x40, 0x03, 0x61, 0x01, // if then, skipped when v0 != 3
+                0x8F, 0x20, 0x8F, 0x05, 0x3F, 0x00, // vF = v0 <= v2, skip when false
+                0x12, 0x10, 0x61, 0x02, 0x12, 0x12, // jump else, v1 := 2, jump end
+                0x61, 0x03, // else
+                0xE0, 0x9E, 0x12, 0x18, // while: skip when pressed, exit
+                0x12, 0x00, // again
+            ]
+        );
+    }
+
+    #[test]
+    fn definitions() {
+        let rom = assemble(
+            ":const SIZE 3
+            :alias x v4
+            :macro increment register { register += 1 }
+            :calc double { SIZE * 2 }
+            : main
+            x := double
+            increment x increment x
+            :unpack 0xA data
+            :next target v0 := SIZE
+            i := target
+            :byte { 2 * 3 + 1 }
+            :org 0x240 : data",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            [
+                0x12, 0x02, 0x64, 0x06, 0x74, 0x01, 0x74, 0x01, 0x60, 0xA2, 0x61, 0x40, 0x60, 0x03,
+                0xA2, 0x0D, 0x08,
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            assemble("v0 := 1").unwrap_err().message,
+            "the program has no `main` label"
+        );
+        let error = assemble(": main\n jump nowhere").unwrap_err();
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (2, "undefined name nowhere")
+        );
+        assert!(assemble(": main v0 := 256").is_err());
+        assert!(assemble(": main loop").is_err());
+        assert!(assemble(": main : main").is_err());
+    }
+
+    #[test]
+    fn forward_references() {
+        // Every patch, with the label defined after its uses and before
+        let uses = "jump0 target  i := long target  :pointer target
+            :unpack 0xB target  :unpack long target";
+        let rom = assemble(&format!(": main {}\n:org 0x345 : target {}", uses, uses)).unwrap();
+        let expected = [
+            0xB3, 0x45, 0xF0, 0x00, 0x03, 0x45, 0x03, 0x45, //
+            0x60, 0xB3, 0x61, 0x45, 0x60, 0x03, 0x61, 0x45,
+        ];
+        assert_eq!(rom[..16], expected);
+        assert_eq!(rom[0x145..], expected);
+        assert!(rom[16..0x145].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn org() {
+        let rom = assemble(": main jump tail :org 0x208 : tail 0xAA :org 0x204 0xBB").unwrap();
+        assert_eq!(rom, [0x12, 0x08, 0x00, 0x00, 0xBB, 0x00, 0x00, 0x00, 0xAA]);
+        assert!(assemble(": main :org 0x10000").is_err());
+    }
+
+    #[test]
+    fn calc() {
+        let value = |expression: &str| {
+            let rom = assemble(&format!(": main :calc x {{ {} }} :byte x", expression));
+            rom.map(|rom| rom[0])
+        };
+        assert_eq!(value("2 * 3 + 1"), Ok(8));
+        assert_eq!(value("( 2 * 3 ) + 1"), Ok(7));
+        assert_eq!(value("- 3 + 5"), Ok(2));
+        assert_eq!(value("( 0xF0 >> 4 ) | 1 << 7"), Ok(0x8F));
+        assert_eq!(value("1 << 65"), Ok(2));
+        assert_eq!(value("7 min 3 max 5"), Ok(5));
+        assert_eq!(value("floor 7 / 2"), Ok(3));
+        assert_eq!(value("strlen \"abc\" == 3"), Ok(1));
+        assert_eq!(value("HERE - 0x200"), Ok(0));
+        assert!(value("1 +").is_err());
+        assert!(value("undefined + 1").is_err());
+
+        // `@` reads what was assembled so far, `HERE` is the next address
+        let rom = assemble(": main 0x12 :calc x { @ ( HERE - 1 ) } :byte { x + 1 }").unwrap();
+        assert_eq!(rom, [0x12, 0x13]);
+    }
+
+    #[test]
+    fn macros() {
+        let rom = assemble(
+            ":macro count { :byte CALLS }
+            :macro pair a b { a := b b := a }
+            : main count count pair v1 v2",
+        )
+        .unwrap();
+        assert_eq!(rom, [0x12, 0x02, 0x00, 0x01, 0x81, 0x20, 0x82, 0x10]);
+
+        let error = assemble(":macro forever { forever }\n: main forever").unwrap_err();
+        assert_eq!(error.message, "the macro forever never stops expanding");
+        assert!(assemble(": main :macro two a b { a b } two v0").is_err());
+    }
+
+    #[test]
+    fn strings() {
+        let rom = assemble(
+            ":stringmode text \"abc\" { :byte { VALUE + 1 } }
+            : main text \"cab\"
+            :assert \"the text is 3 bytes\" { HERE == 0x205 }",
+        )
+        .unwrap();
+        assert_eq!(rom, [0x12, 0x02, 3, 1, 2]);
+        let error = assemble(": main :assert \"too long\" { HERE < 0x200 }").unwrap_err();
+        assert_eq!(error.message, "too long");
+    }
+}
@@ -0,0 +1,249 @@
+//! Debug overlay shown next to the picture: registers, stack, timers,
+//! pressed keys and speed, written with a built-in 3x5 pixel font.
+
+use std::time::{Duration, Instant};
+
+use crate::Machine;
+
+/// Size of the overlay in its own pixels
+pub const WIDTH: usize = 100;
+pub const HEIGHT: usize = 58;
+
+/// A glyph and the space after it
+const CELL_WIDTH: usize = 4;
+const CELL_HEIGHT: usize = 6;
+const MARGIN: usize = 2;
+/// Stack entries shown, the innermost ones
+const STACK_ENTRIES: usize = 3;
+
+/// Frames and instructions per second, measured over the last second
+pub struct Rates {
+    start: Instant,
+    frames: u32,
+    cycles: u64,
+    fps: u32,
+    ips: u64,
+}
+
+impl Rates {
+    pub fn new(cpu: &Machine) -> Rates {
+        Rates {
+            start: Instant::now(),
+            frames: 0,
+            cycles: cpu.view().cycles(),
+            fps: 0,
+            ips: 0,
+        }
+    }
+
+    /// Counts a picture shown
+    pub fn frame(&mut self, cpu: &Machine) {
+        self.frames += 1;
+        if self.start.elapsed() >= Duration::from_secs(1) {
+            let cycles = cpu.view().cycles();
+            self.fps = self.frames;
+            // The counter starts over when the program is reset
+            self.ips = cycles.saturating_sub(self.cycles);
+            self.start = Instant::now();
+            self.frames = 0;
+            self.cycles = cycles;
+        }
+    }
+}
+
+/// Writes the state of `cpu` on `buffer`, RGB24 rows of `pitch` bytes
+pub fn draw(
+    buffer: &mut [u8],
+    pitch: usize,
+    cpu: &Machine,
+    keys: u16,
+    rates: &Rates,
+    colors: ([u8; 3], [u8; 3]),
+) {
+    let (back, front) = colors;
+    for row in buffer.chunks_mut(pitch) {
+        for pixel in row[..WIDTH * 3].chunks_mut(3) {
+            pixel.copy_from_slice(&back);
+        }
+    }
+
+    write(buffer, pitch, &lines(cpu, keys, rates), &front);
+}
+
+/// The text of the overlay, one string per line
+fn lines(cpu: &Machine, keys: u16, rates: &Rates) -> Vec<String> {
+    let view = cpu.view();
+    let v = view.registers();
+    let stack = view.call_stack();
+    let mut lines = vec![
+        format!(
+            "PC {:04X}  I {:04X}",
+            view.program_counter(),
+            view.index_register()
+        ),
+        format!(
+            "SP {:02X} DT {:02X} ST {:02X}",
+            stack.len(),
+            view.delay_timer(),
+            view.sound_timer()
+        ),
+    ];
+    for first in (0..16).step_by(4) {
+        let registers: Vec<String> = (first..first + 4)
+            .map(|x| format!("V{:X} {:02X}", x, v[x]))
+            .collect();
+        lines.push(registers.join(" "));
+    }
+    let entries: Vec<String> = stack
+        .iter()
+        .rev()
+        .take(STACK_ENTRIES)
+        .map(|address| format!("{:04X}", address))
+        .collect();
+    lines.push(format!("STACK {}", entries.join(" ")));
+    let pressed: String = (0..16)
+        .filter(|key| keys & 1 << key != 0)
+        .map(|key| format!("{:X}", key))
+        .collect();
+    lines.push(format!("KEYS {}", pressed));
+    lines.push(format!("FPS {} IPS {}", rates.fps, rates.ips));
+    lines
+}
+
+/// Writes `lines` with the pixels `front`, clipping what doesn't fit
+fn write(buffer: &mut [u8], pitch: usize, lines: &[String], front: &[u8; 3]) {
+    for (line, text) in lines.iter().enumerate() {
+        let y = MARGIN + line * CELL_HEIGHT;
+        for (column, character) in text.chars().enumerate() {
+            let x = MARGIN + column * CELL_WIDTH;
+            if x + CELL_WIDTH > WIDTH || y + CELL_HEIGHT > HEIGHT {
+                continue;
+            }
+            for (dy, bits) in glyph(character).iter().enumerate() {
+                for dx in 0..3 {
+                    if bits & 0b100 >> dx != 0 {
+                        let offset = (y + dy) * pitch + (x + dx) * 3;
+                        buffer[offset..offset + 3].copy_from_slice(front);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rows of a character, the three low bits of each, left to right. Unknown
+/// characters are blank.
+fn glyph(character: char) -> [u8; 5] {
+    match character.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        _ => [0; 5],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use chip_8::cpu::Cpu;
+
+    use crate::overlay::{glyph, lines, write, Rates, HEIGHT, MARGIN, WIDTH};
+
+    const BACK: [u8; 3] = [0, 0, 0];
+    const FRONT: [u8; 3] = [0xFF, 0xFF, 0xFF];
+
+    /// The colour at `x`, `y` of `buffer`
+    fn pixel(buffer: &[u8], pitch: usize, x: usize, y: usize) -> [u8; 3] {
+        let offset = y * pitch + x * 3;
+        [buffer[offset], buffer[offset + 1], buffer[offset + 2]]
+    }
+
+    #[test]
+    fn text() {
+        // LD V0, 0x05 then LD I, 0x345
+        let mut cpu = Cpu::new(Cursor::new([0x60, 0x05, 0xA3, 0x45]))
+            .unwrap()
+            .with_observer(None);
+        cpu.next();
+        cpu.next();
+        let rates = Rates::new(&cpu);
+
+        let lines = lines(&cpu, 0b1000_0000_0000_0001, &rates);
+        assert_eq!(lines[0], "PC 0204  I 0345");
+        assert_eq!(lines[1], "SP 00 DT 00 ST 00");
+        assert_eq!(lines[2], "V0 05 V1 00 V2 00 V3 00");
+        assert_eq!(lines[5], "VC 00 VD 00 VE 00 VF 00");
+        assert_eq!(lines[6], "STACK ");
+        assert_eq!(lines[7], "KEYS 0F");
+        assert_eq!(lines[8], "FPS 0 IPS 0");
+    }
+
+    #[test]
+    fn glyphs() {
+        let mut buffer = vec![0; WIDTH * 3 * HEIGHT];
+        write(&mut buffer, WIDTH * 3, &[String::from("P")], &FRONT);
+
+        // The top row of P is two pixels wide
+        assert_eq!(pixel(&buffer, WIDTH * 3, MARGIN, MARGIN), FRONT);
+        assert_eq!(pixel(&buffer, WIDTH * 3, MARGIN + 1, MARGIN), FRONT);
+        assert_eq!(pixel(&buffer, WIDTH * 3, MARGIN + 2, MARGIN), BACK);
+        assert_eq!(pixel(&buffer, WIDTH * 3, MARGIN, MARGIN + 4), FRONT);
+        assert_eq!(pixel(&buffer, WIDTH * 3, MARGIN + 1, MARGIN + 4), BACK);
+
+        assert_eq!(glyph('p'), glyph('P'));
+        assert_eq!(glyph('?'), [0; 5]);
+    }
+
+    #[test]
+    fn clipping() {
+        // Rows wider than the overlay keep what's past it
+        let pitch = (WIDTH + 10) * 3;
+        let mut buffer = vec![0x80; pitch * HEIGHT];
+        let lines = vec!["8".repeat(100); 20];
+        write(&mut buffer, pitch, &lines, &FRONT);
+
+        for y in 0..HEIGHT {
+            for x in WIDTH..WIDTH + 10 {
+                assert_eq!(pixel(&buffer, pitch, x, y), [0x80; 3]);
+            }
+        }
+        assert_eq!(pixel(&buffer, pitch, MARGIN, MARGIN), FRONT);
+    }
+}
@@ -0,0 +1,114 @@
+//! Emulation runs at 60 frames per second of real time, whatever the rate at
+//! which pictures are shown.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+pub const FRAME_RATE: f64 = 60.0;
+/// Time after which a late machine stops catching up and slows down instead
+const MAX_LAG: f64 = 0.1;
+
+/// How the main loop waits between two pictures
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Present {
+    /// Waits for the vertical blank of the display
+    #[default]
+    Vsync,
+    /// Sleeps until the next emulation frame is due
+    Sleep,
+    /// Shows pictures as fast as possible
+    Uncapped,
+}
+
+/// Turns real time into emulation frames
+pub struct Timestep {
+    last: Instant,
+    /// Frames due and not run yet, fractions of a frame included
+    owed: f64,
+}
+
+impl Timestep {
+    pub fn new() -> Timestep {
+        Timestep {
+            last: Instant::now(),
+            owed: 0.0,
+        }
+    }
+
+    /// Frames due since the last call, at `speed` times real time. Time
+    /// beyond `MAX_LAG` is dropped instead of caught up.
+    pub fn frames(&mut self, speed: f32) -> u32 {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last);
+        self.last = now;
+        self.advance(elapsed, speed)
+    }
+
+    /// Frames due after `elapsed` more at `speed`
+    fn advance(&mut self, elapsed: Duration, speed: f32) -> u32 {
+        let rate = FRAME_RATE * speed as f64;
+        self.owed += elapsed.as_secs_f64() * rate;
+        self.owed = self.owed.min((MAX_LAG * rate).max(1.0));
+
+        let frames = self.owed.floor();
+        self.owed -= frames;
+        frames as u32
+    }
+
+    /// Sleeps until the next frame is due at `speed`
+    pub fn sleep(&self, speed: f32) {
+        if speed <= 0.0 {
+            thread::sleep(Duration::from_secs_f64(1.0 / FRAME_RATE));
+            return;
+        }
+        let due = (1.0 - self.owed) / (FRAME_RATE * speed as f64);
+        let elapsed = self.last.elapsed().as_secs_f64();
+        if due > elapsed {
+            thread::sleep(Duration::from_secs_f64(due - elapsed));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::timestep::Timestep;
+
+    fn milliseconds(milliseconds: u64) -> Duration {
+        Duration::from_millis(milliseconds)
+    }
+
+    #[test]
+    fn real_time() {
+        let mut timestep = Timestep::new();
+        assert_eq!(timestep.advance(milliseconds(50), 1.0), 3);
+        // Half frames add up
+        assert_eq!(timestep.advance(milliseconds(25), 1.0), 1);
+        assert_eq!(timestep.advance(milliseconds(25), 1.0), 2);
+        assert_eq!(timestep.advance(milliseconds(5), 1.0), 0);
+    }
+
+    #[test]
+    fn lag() {
+        let mut timestep = Timestep::new();
+        // At most 100 ms are caught up
+        assert_eq!(timestep.advance(milliseconds(1000), 1.0), 6);
+        assert_eq!(timestep.advance(milliseconds(1000), 4.0), 24);
+        // A slow machine still gets a frame
+        assert_eq!(timestep.advance(milliseconds(1000), 0.1), 1);
+    }
+
+    #[test]
+    fn speed() {
+        let mut timestep = Timestep::new();
+        assert_eq!(timestep.advance(milliseconds(50), 2.0), 6);
+        assert_eq!(timestep.advance(milliseconds(50), 0.5), 1);
+        // The half frame left over is run at the new speed
+        assert_eq!(timestep.advance(milliseconds(25), 1.0), 2);
+        assert_eq!(timestep.advance(milliseconds(50), 0.0), 0);
+    }
+}
@@ -0,0 +1,164 @@
+//! Runs a program on an emulated COSMAC VIP, with its original interpreter.
+
+use std::collections::HashMap;
+
+use sdl2::pixels::PixelFormatEnum;
+
+use chip_8::vip::{Vip, LINES, WIDTH};
+
+use crate::{audio, keypad_index, parse_color, read_rom, timestep, Error};
+
+/// Lines of the picture per CHIP-8 row
+const LINES_PER_ROW: usize = 4;
+
+#[derive(clap::Args)]
+pub struct VipArgs {
+    rom: String,
+    /// Dump of the 512 byte monitor ROM
+    #[arg(long)]
+    monitor: String,
+    /// Dump of the CHIP-8 interpreter, loaded at 0
+    #[arg(long)]
+    interpreter: String,
+    /// Background colour, #RRGGBB
+    #[arg(long, value_parser = parse_color, default_value = "#000000")]
+    back: [u8; 3],
+    /// Foreground colour, #RRGGBB
+    #[arg(long, value_parser = parse_color, default_value = "#FFFFFF")]
+    front: [u8; 3],
+    /// Window pixels per CHIP-8 pixel
+    #[arg(long, default_value_t = 10)]
+    scale: u32,
+    /// Disables the sound
+    #[arg(long)]
+    mute: bool,
+    /// Runs this many frames without a window and prints the screen
+    #[arg(long)]
+    frames: Option<u32>,
+}
+
+pub fn run(args: &VipArgs) -> Result<(), Error> {
+    let read = |path: &str| {
+        std::fs::read(path).map_err(|error| format!("cannot read {}: {}", path, error))
+    };
+    let monitor = read(&args.monitor)?;
+    let interpreter = read(&args.interpreter)?;
+    let (rom, _) = read_rom(&args.rom)?;
+    let mut vip = Vip::chip_8(&monitor, &interpreter, &rom)
+        .map_err(|error| format!("cannot load {}: {}", args.rom, error))?;
+
+    if let Some(frames) = args.frames {
+        for _ in 0..frames {
+            vip.run_frame();
+        }
+        print_screen(&vip);
+        return Ok(());
+    }
+
+    let sdl_context = sdl2::init().map_err(|error| format!("cannot initialize SDL: {}", error))?;
+    let sdl_video = sdl_context
+        .video()
+        .map_err(|error| format!("cannot initialize the video: {}", error))?;
+    let audio_device = if args.mute {
+        None
+    } else {
+        Some(
+            audio::initialize(&sdl_context)
+                .map_err(|error| format!("cannot initialize the audio: {}", error))?,
+        )
+    };
+    let mut playing = false;
+
+    let window = sdl_video
+        .window(
+            &format!("COSMAC VIP - {}", args.rom),
+            WIDTH as u32 * args.scale,
+            (LINES / LINES_PER_ROW) as u32 * args.scale,
+        )
+        .resizable()
+        .build()
+        .map_err(|error| format!("cannot create the window: {}", error))?;
+    let mut event_pump = sdl_context.event_pump()?;
+    let mut canvas = window
+        .into_canvas()
+        .accelerated()
+        .present_vsync()
+        .build()
+        .map_err(|error| format!("cannot create the renderer: {}", error))?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, LINES as u32)
+        .map_err(|error| format!("cannot create the texture: {}", error))?;
+    let mut timestep = timestep::Timestep::new();
+    let key_bindings = HashMap::new();
+
+    'running: loop {
+        use sdl2::event::Event;
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(key) = keypad_index(keycode, &key_bindings) {
+                        vip.set_key(key, true);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(key) = keypad_index(keycode, &key_bindings) {
+                        vip.set_key(key, false);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for _ in 0..timestep.frames(1.0) {
+            vip.run_frame();
+        }
+
+        if let Some(audio_device) = &audio_device {
+            let sound = vip.should_play_sound();
+            if sound && !playing {
+                audio_device.resume();
+                playing = true;
+            }
+            if !sound && playing {
+                audio_device.pause();
+                playing = false;
+            }
+        }
+
+        texture.with_lock(None, |buffer, pitch| {
+            for line in 0..LINES {
+                for x in 0..WIDTH {
+                    let color = if vip.is_pixel_set(x, line) {
+                        args.front
+                    } else {
+                        args.back
+                    };
+                    let offset = line * pitch + x * 3;
+                    buffer[offset..offset + 3].copy_from_slice(&color);
+                }
+            }
+        })?;
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+    }
+    Ok(())
+}
+
+/// The picture as CHIP-8 shows it, the first of every 4 lines
+fn print_screen(vip: &Vip) {
+    for line in (0..LINES).step_by(LINES_PER_ROW) {
+        let row: String = (0..WIDTH)
+            .map(|x| if vip.is_pixel_set(x, line) { '#' } else { '.' })
+            .collect();
+        println!("{}", row);
+    }
+}
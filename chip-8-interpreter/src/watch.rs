@@ -0,0 +1,103 @@
+//! Notices when a file changes on disk by polling its modification time.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Time between two checks of the modification time
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Watcher {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Watcher {
+        let path = path.into();
+        Watcher {
+            modified: modified(&path),
+            path,
+            checked: Instant::now(),
+        }
+    }
+
+    /// Whether the file was modified since the last time this returned true.
+    /// A file that can't be read, e.g. while it is being replaced, isn't
+    /// reported until it is back.
+    pub fn changed(&mut self) -> bool {
+        if self.checked.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.checked = Instant::now();
+        match modified(&self.path) {
+            Some(time) if Some(time) != self.modified => {
+                self.modified = Some(time);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    use crate::watch::{Watcher, POLL_INTERVAL};
+
+    /// Sets the modification time of `file` to `seconds` after the epoch
+    fn touch(file: &File, seconds: u64) {
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    /// Checks `watcher` as if the poll interval had passed
+    fn changed(watcher: &mut Watcher) -> bool {
+        watcher.checked -= POLL_INTERVAL;
+        watcher.changed()
+    }
+
+    #[test]
+    fn changes() {
+        let path = std::env::temp_dir().join("chip-8-watch-changes");
+        let file = File::create(&path).unwrap();
+        touch(&file, 1);
+        let mut watcher = Watcher::new(&path);
+        assert!(!changed(&mut watcher));
+
+        touch(&file, 2);
+        // Too early
+        assert!(!watcher.changed());
+        assert!(changed(&mut watcher));
+        assert!(!changed(&mut watcher));
+
+        // Being replaced, then back
+        std::fs::remove_file(&path).unwrap();
+        assert!(!changed(&mut watcher));
+        let file = File::create(&path).unwrap();
+        touch(&file, 3);
+        assert!(changed(&mut watcher));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_file() {
+        let path = std::env::temp_dir().join("chip-8-watch-missing");
+        let _ = std::fs::remove_file(&path);
+        let mut watcher = Watcher::new(&path);
+        assert!(!changed(&mut watcher));
+
+        let file = File::create(&path).unwrap();
+        touch(&file, 1);
+        assert!(changed(&mut watcher));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+//! Runs arbitrary programs on machines from the builder, with a bus that
+//! decodes ports right after the RAM. The first two bytes pick the memory
+//! size, the third one the number of ports and the fourth one the platform.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rand::rngs::mock::StepRng;
+
+use chip_8::bus::Bus;
+use chip_8::cpu::MEMORY_SIZE;
+use chip_8::machine::{MachineBuilder, Platform};
+
+/// Ports from `start`, where the RAM ends
+struct Ports {
+    start: u16,
+    ports: Vec<u8>,
+}
+
+impl Ports {
+    fn port(&self, address: u16) -> Option<usize> {
+        let index = address.checked_sub(self.start)? as usize;
+        if index < self.ports.len() {
+            Some(index)
+        } else {
+            None
+        }
+    }
+}
+
+impl Bus for Ports {
+    fn decodes(&self, address: u16) -> bool {
+        self.port(address).is_some()
+    }
+
+    fn read(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+        match self.port(address) {
+            Some(index) => self.ports[index],
+            None => memory[address as usize],
+        }
+    }
+
+    fn peek(&self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+        match self.port(address) {
+            Some(index) => self.ports[index],
+            None => memory.get(address as usize).copied().unwrap_or(0),
+        }
+    }
+
+    fn write(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) -> bool {
+        match self.port(address) {
+            Some(index) => self.ports[index] = value,
+            None => memory[address as usize] = value,
+        }
+        true
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let (settings, program) = data.split_at(4);
+    // From the end of the interpreter area to 4 KiB
+    let memory_size =
+        0x200 + u16::from_le_bytes([settings[0], settings[1]]) as usize % (MEMORY_SIZE - 0x1FF);
+    let platform = match settings[3] % 3 {
+        0 => Platform::Vip,
+        1 => Platform::Schip,
+        _ => Platform::XoChip,
+    };
+    let cpu = match MachineBuilder::platform(platform)
+        .memory_size(memory_size)
+        .load(program, StepRng::new(0, 0x9E37_79B9_7F4A_7C15))
+    {
+        Ok(cpu) => cpu,
+        Err(_) => return,
+    };
+    let mut cpu = cpu.with_bus(Ports {
+        start: memory_size as u16,
+        ports: vec![0; settings[2] as usize],
+    });
+
+    for _ in 0..100 {
+        for _ in 0..100 {
+            cpu.next();
+        }
+        cpu.decrease_timers();
+    }
+});
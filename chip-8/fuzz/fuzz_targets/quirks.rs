@@ -0,0 +1,39 @@
+//! Runs arbitrary programs with arbitrary quirks and keys, the first byte
+//! picks the quirks and the second one the pressed key.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rand::rngs::mock::StepRng;
+
+use chip_8::cpu::Cpu;
+use chip_8::quirks::Quirks;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let (settings, program) = data.split_at(2);
+    let mut cpu = match Cpu::from_bytes(program, StepRng::new(0, 0x9E37_79B9_7F4A_7C15)) {
+        Ok(cpu) => cpu,
+        Err(_) => return,
+    };
+
+    let bit = |n: u8| settings[0] & (1 << n) != 0;
+    cpu.set_quirks(Quirks {
+        shift: bit(0),
+        memory_increment_by_x: bit(1),
+        memory_leave_i_unchanged: bit(2),
+        wrap: bit(3),
+        jump: bit(4),
+        vblank: bit(5),
+        logic: bit(6),
+    });
+    cpu.set_key(settings[1] & 0xF, true);
+
+    for _ in 0..100 {
+        for _ in 0..100 {
+            cpu.next();
+        }
+        cpu.decrease_timers();
+    }
+});
@@ -0,0 +1,20 @@
+//! Runs arbitrary programs with the default quirks.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rand::rngs::mock::StepRng;
+
+use chip_8::cpu::Cpu;
+
+fuzz_target!(|program: &[u8]| {
+    let mut cpu = match Cpu::from_bytes(program, StepRng::new(0, 0x9E37_79B9_7F4A_7C15)) {
+        Ok(cpu) => cpu,
+        Err(_) => return,
+    };
+    for _ in 0..100 {
+        for _ in 0..100 {
+            cpu.next();
+        }
+        cpu.decrease_timers();
+    }
+});
@@ -0,0 +1,203 @@
+//! Memory access of the interpreter.
+//!
+//! Every read and write the program makes (instruction fetch, sprites,
+//! `FX33`, `FX55` and `FX65`) goes through the [`Bus`] of the machine, which
+//! can serve it from the RAM or from somewhere else: memory-mapped
+//! peripherals, extra banks or an access log. The bus also decides which
+//! addresses past the RAM exist, see [`Bus::decodes`]. Loading a ROM and the
+//! [`crate::view`] API use the RAM directly, only the opcode of a view goes
+//! through [`Bus::peek`].
+
+use core::ops::Range;
+
+use crate::cpu::MEMORY_SIZE;
+
+/// The machine passes the addresses inside its memory size and the ones past
+/// it that [`Bus::decodes`], any other access faults before reaching the
+/// bus.
+pub trait Bus {
+    /// Whether `address`, past the memory size of the machine, is served by
+    /// the bus, such as a peripheral above the RAM. Nothing is by default.
+    /// [`Bus::read`] and [`Bus::write`] must handle the decoded addresses
+    /// past [`MEMORY_SIZE`] themselves.
+    fn decodes(&self, _address: u16) -> bool {
+        false
+    }
+
+    /// Reads `address`, `memory` is the RAM of the machine.
+    fn read(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+        memory[address as usize]
+    }
+
+    /// Reads `address` without the side effects of [`Bus::read`], for
+    /// traces and debuggers. Serves the RAM by default, a bus that decodes
+    /// addresses past it serves those too.
+    fn peek(&self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+        memory.get(address as usize).copied().unwrap_or(0)
+    }
+
+    /// Writes `value` to `address`, `memory` is the RAM of the machine.
+    /// Returns whether the byte was stored, a dropped write is `false`.
+    fn write(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) -> bool {
+        memory[address as usize] = value;
+        true
+    }
+}
+
+/// Plain RAM, the default bus.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DirectBus;
+
+impl Bus for DirectBus {}
+
+/// Drops the writes to `range` and passes everything else to `bus`.
+///
+/// `ReadOnly::new(0..0x200, DirectBus)` protects the interpreter area.
+#[derive(Clone, Debug)]
+pub struct ReadOnly<B = DirectBus> {
+    range: Range<u16>,
+    bus: B,
+}
+
+impl<B: Bus> ReadOnly<B> {
+    pub fn new(range: Range<u16>, bus: B) -> ReadOnly<B> {
+        ReadOnly { range, bus }
+    }
+}
+
+impl<B: Bus> Bus for ReadOnly<B> {
+    fn decodes(&self, address: u16) -> bool {
+        self.bus.decodes(address)
+    }
+    fn read(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+        self.bus.read(memory, address)
+    }
+
+    fn peek(&self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+        self.bus.peek(memory, address)
+    }
+
+    fn write(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) -> bool {
+        !self.range.contains(&address) && self.bus.write(memory, address, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use crate::bus::{Bus, DirectBus, ReadOnly};
+    use crate::cpu::{Cpu, Fault, MEMORY_SIZE};
+    use crate::quirks::Quirks;
+
+    /// Counts the reads and writes
+    #[derive(Default)]
+    struct Counter {
+        reads: usize,
+        writes: usize,
+    }
+
+    impl Bus for Counter {
+        fn read(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+            self.reads += 1;
+            DirectBus.read(memory, address)
+        }
+
+        fn write(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) -> bool {
+            self.writes += 1;
+            DirectBus.write(memory, address, value)
+        }
+    }
+
+    /// 16 ports above the 4 KiB of RAM, from 0x1000
+    #[derive(Default)]
+    struct Ports {
+        ports: [u8; 16],
+    }
+
+    impl Bus for Ports {
+        fn decodes(&self, address: u16) -> bool {
+            (0x1000..0x1010).contains(&address)
+        }
+
+        fn read(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+            self.peek(memory, address)
+        }
+
+        fn peek(&self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+            match address {
+                0x1000..=0x100F => self.ports[address as usize - 0x1000],
+                _ => DirectBus.peek(memory, address),
+            }
+        }
+
+        fn write(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) -> bool {
+            match address {
+                0x1000..=0x100F => {
+                    self.ports[address as usize - 0x1000] = value;
+                    true
+                }
+                _ => DirectBus.write(memory, address, value),
+            }
+        }
+    }
+
+    #[test]
+    fn accesses_go_through_the_bus() {
+        // I = 0x300, V0..V2 to [I], draw 3 rows from [I]
+        let rom = [0xA3, 0x00, 0xF2, 0x55, 0xD0, 0x03];
+        let mut cpu = Cpu::from_bytes(&rom, StepRng::new(0, 1))
+            .unwrap()
+            .with_bus(Counter::default());
+        for _ in 0..3 {
+            cpu.next();
+        }
+
+        assert_eq!(cpu.bus().reads, 3 * 2 + 3);
+        assert_eq!(cpu.bus().writes, 3);
+    }
+
+    #[test]
+    fn read_only_region() {
+        // V0 = 0xAB, V1 = 0xCD, I = 0x1FF, V0..V1 to [I]
+        let rom = [0x60, 0xAB, 0x61, 0xCD, 0xA1, 0xFF, 0xF1, 0x55];
+        let mut cpu = Cpu::from_bytes(&rom, StepRng::new(0, 1))
+            .unwrap()
+            .with_bus(ReadOnly::new(0..0x200, DirectBus));
+        let below = cpu.view().memory()[0x1FF];
+        for _ in 0..4 {
+            cpu.next();
+        }
+
+        assert_eq!(cpu.view().memory()[0x1FF], below);
+        assert_eq!(cpu.view().memory()[0x200], 0xCD);
+    }
+
+    #[test]
+    fn addresses_past_memory() {
+        // I = 0xFFF, V0 = 1, I += V0, V0 = 0x42, V0 to [I], V0 = 0, V0 from
+        // [I], V0 = 0x10, I += V0, V0 to [I]
+        let rom = [
+            0xAF, 0xFF, 0x60, 0x01, 0xF0, 0x1E, 0x60, 0x42, 0xF0, 0x55, 0x60, 0x00, 0xF0, 0x65,
+            0x60, 0x10, 0xF0, 0x1E, 0xF0, 0x55,
+        ];
+        let mut cpu = Cpu::from_bytes(&rom, StepRng::new(0, 1))
+            .unwrap()
+            .with_bus(Ports::default());
+        cpu.set_quirks(Quirks {
+            memory_leave_i_unchanged: true,
+            ..Quirks::default()
+        });
+        for _ in 0..7 {
+            cpu.next();
+        }
+        assert_eq!(cpu.fault(), None);
+        assert_eq!(cpu.bus().ports[0], 0x42);
+        assert_eq!(cpu.view().registers()[0], 0x42);
+
+        for _ in 0..3 {
+            cpu.next();
+        }
+        assert_eq!(cpu.fault(), Some(Fault::InvalidAddress(0x1010)));
+    }
+}
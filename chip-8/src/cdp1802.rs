@@ -0,0 +1,483 @@
+//! The RCA CDP1802 microprocessor of the COSMAC VIP.
+//!
+//! Sixteen 16-bit registers, any of which can be the program counter (`P`)
+//! or the data pointer (`X`), an 8-bit accumulator `D` with its carry `DF`,
+//! the `Q` output and four `EF` inputs. Memory, the I/O ports and the flags
+//! belong to the board, which the processor reaches through [`Io`].
+//!
+//! Time is counted in machine cycles of 8 clock pulses: most instructions
+//! take 2, the long branches and skips 3.
+
+/// What the processor is wired to.
+pub trait Io {
+    fn read(&mut self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, value: u8);
+
+    /// `6N` with N from 1 to 7 puts `value` on the bus for port N
+    fn output(&mut self, _port: u8, _value: u8) {}
+
+    /// `6N` with N from 9 to F reads port N - 8
+    fn input(&mut self, _port: u8) -> u8 {
+        0
+    }
+
+    /// Level of the `EF1` to `EF4` input, `flag` from 1 to 4, true when the
+    /// pin is pulled low
+    fn flag(&mut self, _flag: u8) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Cdp1802 {
+    r: [u16; 16],
+    d: u8,
+    df: bool,
+    p: u8,
+    x: u8,
+    /// `X` and `P` saved by an interrupt
+    t: u8,
+    ie: bool,
+    q: bool,
+    /// After `00`, until a DMA or an interrupt
+    idle: bool,
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cdp1802 {
+    /// The state after a reset: `P`, `X` and `R0` at zero, interrupts on
+    pub fn new() -> Cdp1802 {
+        Cdp1802 {
+            r: [0; 16],
+            d: 0,
+            df: false,
+            p: 0,
+            x: 0,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.p = 0;
+        self.x = 0;
+        self.r[0] = 0;
+        self.ie = true;
+        self.q = false;
+        self.idle = false;
+    }
+
+    pub fn register(&self, n: u8) -> u16 {
+        self.r[n as usize & 0xF]
+    }
+
+    pub fn set_register(&mut self, n: u8, value: u16) {
+        self.r[n as usize & 0xF] = value;
+    }
+
+    pub fn d(&self) -> u8 {
+        self.d
+    }
+
+    pub fn df(&self) -> bool {
+        self.df
+    }
+
+    pub fn p(&self) -> u8 {
+        self.p
+    }
+
+    pub fn x(&self) -> u8 {
+        self.x
+    }
+
+    pub fn q(&self) -> bool {
+        self.q
+    }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        self.ie
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Address of the next instruction
+    pub fn program_counter(&self) -> u16 {
+        self.r[self.p as usize]
+    }
+
+    /// Answers an interrupt request, when enabled: saves `X` and `P` in `T`
+    /// and runs the routine at `R1` with `X` at 2. Returns the cycles taken.
+    pub fn interrupt(&mut self) -> u8 {
+        if !self.ie {
+            return 0;
+        }
+        self.t = self.x << 4 | self.p;
+        self.x = 2;
+        self.p = 1;
+        self.ie = false;
+        self.idle = false;
+        1
+    }
+
+    /// One DMA output cycle: the byte at `R0` for the device, `R0` moves on
+    pub fn dma_out<I: Io>(&mut self, io: &mut I) -> u8 {
+        let value = io.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+
+    /// Runs one instruction and returns the machine cycles it took. An idle
+    /// processor only waits a cycle.
+    pub fn step<I: Io>(&mut self, io: &mut I) -> u8 {
+        if self.idle {
+            return 1;
+        }
+
+        let opcode = self.fetch(io);
+        let n = opcode & 0xF;
+        match opcode >> 4 {
+            0x0 if n == 0 => self.idle = true,
+            // LDN
+            0x0 => self.d = io.read(self.r[n as usize]),
+            // INC, DEC
+            0x1 => self.r[n as usize] = self.r[n as usize].wrapping_add(1),
+            0x2 => self.r[n as usize] = self.r[n as usize].wrapping_sub(1),
+            // Short branches, 38 is SKP
+            0x3 => {
+                let taken = n != 0x8 && self.condition(io, n & 0x7) != (n & 0x8 != 0);
+                if taken {
+                    let low = io.read(self.r[self.p as usize]);
+                    let pc = &mut self.r[self.p as usize];
+                    *pc = *pc & 0xFF00 | low as u16;
+                } else {
+                    self.advance(1);
+                }
+            }
+            // LDA, STR
+            0x4 => {
+                self.d = io.read(self.r[n as usize]);
+                self.r[n as usize] = self.r[n as usize].wrapping_add(1);
+            }
+            0x5 => io.write(self.r[n as usize], self.d),
+            // IRX
+            0x6 if n == 0 => self.increment_x(),
+            // OUT
+            0x6 if n < 8 => {
+                let value = io.read(self.rx());
+                io.output(n, value);
+                self.increment_x();
+            }
+            // 68 selects no port on the 1802 and does nothing here
+            0x6 if n == 8 => {}
+            // INP
+            0x6 => {
+                self.d = io.input(n - 8);
+                io.write(self.rx(), self.d);
+            }
+            0x7 => self.execute_7(io, n),
+            // GLO, GHI, PLO, PHI
+            0x8 => self.d = self.r[n as usize] as u8,
+            0x9 => self.d = (self.r[n as usize] >> 8) as u8,
+            0xA => self.r[n as usize] = self.r[n as usize] & 0xFF00 | self.d as u16,
+            0xB => self.r[n as usize] = self.r[n as usize] & 0x00FF | (self.d as u16) << 8,
+            0xC => {
+                self.execute_long(io, n);
+                return 3;
+            }
+            // SEP, SEX
+            0xD => self.p = n,
+            0xE => self.x = n,
+            // SHR, SHL
+            _ if n == 0x6 => self.shift_right(false),
+            _ if n == 0xE => self.shift_left(false),
+            _ => {
+                let operand = self.operand(io, n);
+                self.alu(n & 0x7, false, operand);
+            }
+        }
+        2
+    }
+
+    /// `70` to `7F`
+    fn execute_7<I: Io>(&mut self, io: &mut I, n: u8) {
+        match n {
+            // RET, DIS
+            0x0 | 0x1 => {
+                let value = io.read(self.rx());
+                self.increment_x();
+                self.x = value >> 4;
+                self.p = value & 0xF;
+                self.ie = n == 0;
+            }
+            // LDXA
+            0x2 => {
+                self.d = io.read(self.rx());
+                self.increment_x();
+            }
+            // STXD
+            0x3 => {
+                io.write(self.rx(), self.d);
+                self.r[self.x as usize] = self.rx().wrapping_sub(1);
+            }
+            // SAV
+            0x8 => io.write(self.rx(), self.t),
+            // MARK
+            0x9 => {
+                self.t = self.x << 4 | self.p;
+                io.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            // REQ, SEQ
+            0xA => self.q = false,
+            0xB => self.q = true,
+            // SHRC, SHLC
+            0x6 => self.shift_right(true),
+            0xE => self.shift_left(true),
+            // ADC, SDB, SMB and their immediate forms
+            _ => {
+                let operand = self.operand(io, n);
+                self.alu(n & 0x7, true, operand);
+            }
+        }
+    }
+
+    /// The operand of `Fx` and `7x`: after the opcode from 8 on, M(R(X))
+    /// below
+    fn operand<I: Io>(&mut self, io: &mut I, n: u8) -> u8 {
+        if n & 0x8 != 0 {
+            self.fetch(io)
+        } else {
+            io.read(self.rx())
+        }
+    }
+
+    /// The arithmetic and logic of `Fx` (and of `7x` with `carry`): 0 load,
+    /// 1 OR, 2 AND, 3 XOR, 4 add, 5 operand minus D, 7 D minus operand
+    fn alu(&mut self, operation: u8, carry: bool, operand: u8) {
+        let carry_in = carry && self.df;
+        match operation {
+            0x0 => self.d = operand,
+            0x1 => self.d |= operand,
+            0x2 => self.d &= operand,
+            0x3 => self.d ^= operand,
+            0x4 => {
+                let sum = self.d as u16 + operand as u16 + carry_in as u16;
+                self.d = sum as u8;
+                self.df = sum > 0xFF;
+            }
+            0x5 => self.subtract(operand, self.d, carry),
+            _ => self.subtract(self.d, operand, carry),
+        }
+    }
+
+    /// `F6`, or `76` shifting `DF` in with `carry`
+    fn shift_right(&mut self, carry: bool) {
+        let out = self.d & 1 != 0;
+        self.d = self.d >> 1 | ((carry && self.df) as u8) << 7;
+        self.df = out;
+    }
+
+    /// `FE`, or `7E` shifting `DF` in with `carry`
+    fn shift_left(&mut self, carry: bool) {
+        let out = self.d & 0x80 != 0;
+        self.d = self.d << 1 | (carry && self.df) as u8;
+        self.df = out;
+    }
+
+    /// `DF` is set when there is no borrow
+    fn subtract(&mut self, minuend: u8, subtrahend: u8, borrow: bool) {
+        let borrow_in = borrow && !self.df;
+        let difference = minuend as i16 - subtrahend as i16 - borrow_in as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+
+    /// `C0` to `CF`: long branches, long skips and `NOP`
+    fn execute_long<I: Io>(&mut self, io: &mut I, n: u8) {
+        let inverted = n & 0x8 != 0;
+        if n & 0x4 == 0 {
+            // C0 LBR, C1 LBQ, C2 LBZ, C3 LBDF and the inverted C8 to CB,
+            // C8 being LSKP
+            let taken = if n == 0x8 {
+                self.advance(2);
+                return;
+            } else {
+                self.condition(io, n & 0x3) != inverted
+            };
+            if taken {
+                let pc = self.r[self.p as usize];
+                let high = io.read(pc);
+                let low = io.read(pc.wrapping_add(1));
+                self.r[self.p as usize] = u16::from_be_bytes([high, low]);
+            } else {
+                self.advance(2);
+            }
+            return;
+        }
+
+        // C4 NOP, C5 LSNQ, C6 LSNZ, C7 LSNF, CC LSIE, CD LSQ, CE LSZ, CF LSDF
+        let skip = match n {
+            0x4 => false,
+            0xC => self.ie,
+            _ => self.condition(io, n & 0x3) == inverted,
+        };
+        if skip {
+            self.advance(2);
+        }
+    }
+
+    /// Condition `n` of the branches: 0 always, 1 Q, 2 D is zero, 3 DF, 4 to
+    /// 7 EF1 to EF4
+    fn condition<I: Io>(&mut self, io: &mut I, n: u8) -> bool {
+        match n {
+            0x0 => true,
+            0x1 => self.q,
+            0x2 => self.d == 0,
+            0x3 => self.df,
+            _ => io.flag(n - 3),
+        }
+    }
+
+    fn fetch<I: Io>(&mut self, io: &mut I) -> u8 {
+        let value = io.read(self.r[self.p as usize]);
+        self.advance(1);
+        value
+    }
+
+    fn advance(&mut self, bytes: u16) {
+        let pc = &mut self.r[self.p as usize];
+        *pc = pc.wrapping_add(bytes);
+    }
+
+    fn rx(&self) -> u16 {
+        self.r[self.x as usize]
+    }
+
+    fn increment_x(&mut self) {
+        self.r[self.x as usize] = self.rx().wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cdp1802::{Cdp1802, Io};
+
+    /// 256 bytes of RAM, mirrored everywhere, and the last output
+    struct Ram {
+        memory: [u8; 256],
+        output: Option<(u8, u8)>,
+    }
+
+    impl Io for Ram {
+        fn read(&mut self, address: u16) -> u8 {
+            self.memory[address as usize & 0xFF]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.memory[address as usize & 0xFF] = value;
+        }
+
+        fn output(&mut self, port: u8, value: u8) {
+            self.output = Some((port, value));
+        }
+    }
+
+    /// Runs `program` from 0 until it reaches `00`, returns the cycles taken
+    fn run(cpu: &mut Cdp1802, program: &[u8]) -> (Ram, u32) {
+        let mut ram = Ram {
+            memory: [0; 256],
+            output: None,
+        };
+        ram.memory[..program.len()].copy_from_slice(program);
+        let mut cycles = 0;
+        while !cpu.is_idle() {
+            cycles += cpu.step(&mut ram) as u32;
+        }
+        (ram, cycles)
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut cpu = Cdp1802::new();
+        // 0xF0 + 0x20, then 0x10 - 0x30 and 0x12 - 0x02 with the borrow
+        let (_, cycles) = run(&mut cpu, &[0xF8, 0xF0, 0xFC, 0x20, 0x00]);
+        assert_eq!((cpu.d(), cpu.df(), cycles), (0x10, true, 6));
+
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &[0xF8, 0x10, 0xFF, 0x30, 0x00]);
+        assert_eq!((cpu.d(), cpu.df()), (0xE0, false));
+        let mut cpu = Cdp1802::new();
+        run(
+            &mut cpu,
+            &[0xF8, 0x10, 0xFF, 0x30, 0xF8, 0x12, 0x7F, 0x02, 0x00],
+        );
+        assert_eq!((cpu.d(), cpu.df()), (0x0F, true));
+
+        // 0x01 shifted right through DF, then left back in
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &[0xF8, 0x01, 0x76, 0x7E, 0x00]);
+        assert_eq!((cpu.d(), cpu.df()), (0x01, false));
+        // R(X) - D with SD
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &[0xE5, 0xF8, 0x03, 0xF5, 0x00]);
+        assert_eq!((cpu.d(), cpu.df()), (0xE5 - 0x03, true));
+    }
+
+    #[test]
+    fn branches() {
+        let mut cpu = Cdp1802::new();
+        // D = 0, BZ over two SEQ, LBNZ not taken, LSZ over two SEQ, SKP
+        // over one
+        let program = [
+            0xF8, 0x00, 0x32, 0x06, 0x7B, 0x7B, // 00
+            0xCA, 0x00, 0x00, 0xCE, 0x7B, 0x7B, // 06
+            0x38, 0x7B, 0x00, // 0C
+        ];
+        let (_, cycles) = run(&mut cpu, &program);
+        assert!(!cpu.q());
+        assert_eq!(cpu.program_counter(), 0x0F);
+        assert_eq!(cycles, 2 * 2 + 3 + 3 + 2 + 2);
+    }
+
+    #[test]
+    fn interrupt() {
+        let mut cpu = Cdp1802::new();
+        cpu.set_register(1, 0x10);
+        cpu.set_register(2, 0xFF);
+        let mut ram = Ram {
+            memory: [0; 256],
+            output: None,
+        };
+        // SEX 2, then M(R2) = 0x42, OUT 1
+        ram.memory[..6].copy_from_slice(&[0xE2, 0xF8, 0x42, 0x52, 0x61, 0x00]);
+        // DEC 2, SAV, SEQ, RET
+        ram.memory[0x10..0x14].copy_from_slice(&[0x22, 0x78, 0x7B, 0x70]);
+
+        cpu.step(&mut ram);
+        assert_eq!(cpu.interrupt(), 1);
+        assert_eq!((cpu.p(), cpu.x(), cpu.interrupts_enabled()), (1, 2, false));
+        assert_eq!(cpu.interrupt(), 0);
+        while !cpu.is_idle() {
+            cpu.step(&mut ram);
+        }
+
+        assert_eq!(ram.memory[0xFE], 0x20);
+        assert!(cpu.q());
+        assert_eq!((cpu.p(), cpu.x(), cpu.interrupts_enabled()), (0, 2, true));
+        assert_eq!(ram.output, Some((1, 0x42)));
+        assert_eq!(cpu.register(2), 0x100);
+    }
+}
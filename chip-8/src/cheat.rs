@@ -0,0 +1,169 @@
+//! Cheat finder and cheat codes.
+//!
+//! A [`Search`] starts with every byte of memory as a candidate and narrows
+//! them down between snapshots: lose a life, keep the addresses that
+//! decreased, lose another, and so on until the lives counter is left. A
+//! [`Cheat`] then writes a value to it, once or every frame.
+//!
+//! Everything here lives on the stack, one bit per candidate address.
+
+use crate::cpu::MEMORY_SIZE;
+use crate::view::{MachineView, Poke};
+
+const WORDS: usize = MEMORY_SIZE / 64;
+
+/// How a byte must compare to its value in the previous snapshot to stay a
+/// candidate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// Equals this value, whatever it was before
+    Value(u8),
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+}
+
+impl Comparison {
+    fn matches(self, before: u8, after: u8) -> bool {
+        match self {
+            Comparison::Value(value) => after == value,
+            Comparison::Equal => after == before,
+            Comparison::Changed => after != before,
+            Comparison::Increased => after > before,
+            Comparison::Decreased => after < before,
+        }
+    }
+}
+
+/// Addresses that may hold the value being looked for.
+#[derive(Clone)]
+pub struct Search {
+    candidates: [u64; WORDS],
+    snapshot: [u8; MEMORY_SIZE],
+}
+
+impl Search {
+    /// Starts a search with every address in memory as a candidate
+    pub fn new(view: MachineView<'_>) -> Search {
+        let memory = view.memory();
+        let mut search = Search {
+            candidates: [0; WORDS],
+            snapshot: [0; MEMORY_SIZE],
+        };
+        for address in 0..memory.len() {
+            search.candidates[address / 64] |= 1 << (address % 64);
+        }
+        search.snapshot[..memory.len()].copy_from_slice(memory);
+        search
+    }
+
+    /// Keeps the candidates whose byte in `view` compares to the previous
+    /// snapshot as `comparison` says, then takes a new snapshot
+    pub fn narrow(&mut self, view: MachineView<'_>, comparison: Comparison) {
+        let memory = view.memory();
+        for (address, (before, after)) in self.snapshot.iter().zip(memory).enumerate() {
+            if !comparison.matches(*before, *after) {
+                self.candidates[address / 64] &= !(1 << (address % 64));
+            }
+        }
+        self.snapshot[..memory.len()].copy_from_slice(memory);
+    }
+
+    pub fn is_candidate(&self, address: u16) -> bool {
+        let address = address as usize;
+        address < MEMORY_SIZE && self.candidates[address / 64] & 1 << (address % 64) != 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.candidates
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// The candidates, lowest address first
+    pub fn candidates(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..MEMORY_SIZE as u16).filter(move |address| self.is_candidate(*address))
+    }
+
+    /// Value of `address` in the last snapshot
+    pub fn snapshot(&self, address: u16) -> Option<u8> {
+        self.snapshot.get(address as usize).copied()
+    }
+}
+
+/// Writes `value` to `address`, every frame when `frozen`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    pub frozen: bool,
+}
+
+impl Cheat {
+    /// Writes the value, nothing happens when the address is outside memory
+    pub fn apply(&self, poke: &mut Poke<'_>) {
+        if let Some(byte) = poke.memory().get_mut(self.address as usize) {
+            *byte = self.value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use crate::cheat::{Cheat, Comparison, Search};
+    use crate::cpu::{Cpu, MEMORY_SIZE};
+
+    fn set(cpu: &mut Cpu<StepRng>, address: usize, value: u8) {
+        cpu.poke().memory()[address] = value;
+    }
+
+    #[test]
+    fn narrow() {
+        let mut cpu = Cpu::from_bytes(&[0x00, 0xE0], StepRng::new(0, 1)).unwrap();
+        set(&mut cpu, 0x300, 3);
+        set(&mut cpu, 0x301, 3);
+        let mut search = Search::new(cpu.view());
+        assert_eq!(search.count(), MEMORY_SIZE);
+
+        search.narrow(cpu.view(), Comparison::Value(3));
+        assert_eq!(search.candidates().collect::<Vec<_>>(), [0x300, 0x301]);
+
+        set(&mut cpu, 0x300, 2);
+        set(&mut cpu, 0x301, 4);
+        let mut decreased = search.clone();
+        decreased.narrow(cpu.view(), Comparison::Decreased);
+        assert_eq!(decreased.candidates().collect::<Vec<_>>(), [0x300]);
+        search.narrow(cpu.view(), Comparison::Increased);
+        assert_eq!(search.candidates().collect::<Vec<_>>(), [0x301]);
+        assert_eq!(search.snapshot(0x301), Some(4));
+
+        search.narrow(cpu.view(), Comparison::Equal);
+        assert!(search.is_candidate(0x301));
+        set(&mut cpu, 0x301, 0);
+        search.narrow(cpu.view(), Comparison::Equal);
+        assert_eq!(search.count(), 0);
+        assert!(!search.is_candidate(0xFFFF));
+    }
+
+    #[test]
+    fn apply() {
+        let mut cpu = Cpu::from_bytes(&[0x00, 0xE0], StepRng::new(0, 1)).unwrap();
+        let cheat = Cheat {
+            address: 0x300,
+            value: 9,
+            frozen: true,
+        };
+        cheat.apply(&mut cpu.poke());
+        assert_eq!(cpu.view().memory()[0x300], 9);
+
+        let outside = Cheat {
+            address: 0xFFFF,
+            ..cheat
+        };
+        outside.apply(&mut cpu.poke());
+    }
+}
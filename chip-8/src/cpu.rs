@@ -1,33 +1,147 @@
+use core::fmt;
+#[cfg(feature = "std")]
 use std::io::Read;
 
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
 
-use crate::display::{DEFAULT_FONTS, Display};
+use crate::bus::{Bus, DirectBus};
+use crate::display::Display;
+use crate::font::{Font, FONT_SIZE, GLYPH_SIZE};
 use crate::keypad::KeyPad;
-
-pub struct Cpu {
+use crate::machine::StackOverflow;
+use crate::observer::{NoObserver, Observer};
+use crate::quirks::Quirks;
+use crate::rom::{RomError, RomLoader};
+use crate::view::{MachineView, Poke};
+
+/// CHIP-8 machine, `R` is the random number generator used by `CXNN`, `B`
+/// the [`Bus`] memory is accessed through and `O` the [`Observer`] told
+/// about what the program does.
+#[derive(Clone)]
+pub struct Cpu<R = StdRng, B = DirectBus, O = NoObserver> {
     v: [u8; 16],
-    memory: [u8; 4096],
+    memory: [u8; MEMORY_SIZE],
+    /// Bytes of `memory` the program can reach
+    memory_size: usize,
     i: u16,
-    stack: [u16; 24],
+    stack: [u16; STACK_CAPACITY],
+    stack_depth: u8,
+    stack_overflow: StackOverflow,
     program_counter: u16,
     stack_pointer: u8,
     delay_timer: u8,
     sound_timer: u8,
     keypad: KeyPad,
     display: Display,
+    rng: R,
+    quirks: Quirks,
+    waiting_vblank: bool,
+    cycles: u64,
+    rom_size: usize,
+    font_address: u16,
+    fault: Option<Fault>,
+    /// `FX0A` is waiting for a key
+    waiting_key: bool,
+    bus: B,
+    observer: O,
 }
 
-const START_ADDRESS: u16 = 0x200;
+/// Where programs are loaded unless a [`RomLoader`] says otherwise.
+pub const START_ADDRESS: u16 = 0x200;
+pub const MEMORY_SIZE: usize = 4096;
+/// Deepest call stack a machine can have
+pub const STACK_CAPACITY: usize = 128;
+/// Call stack depth unless a [`crate::machine::MachineBuilder`] says otherwise
+pub const STACK_DEPTH: u8 = 24;
+
+/// Why a machine stopped, programs can't make the interpreter panic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// `2NNN` with the call stack full
+    StackOverflow,
+    /// `00EE` with the call stack empty
+    StackUnderflow,
+    /// An access from this address runs past the end of memory
+    InvalidAddress(u16),
+}
 
-impl Cpu {
-    fn execute_opcode(&mut self, opcode: u16) {
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::StackOverflow => write!(f, "call stack overflow"),
+            Fault::StackUnderflow => write!(f, "return with an empty call stack"),
+            Fault::InvalidAddress(address) => {
+                write!(f, "access from {:#05X} past the end of memory", address)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Fault {}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+impl<R: RngCore, B: Bus, O: Observer> Cpu<R, B, O> {
+    fn read(&mut self, address: u16) -> u8 {
+        self.bus.read(&self.memory, address)
+    }
+
+    /// Checks that `length` bytes from `address` are inside memory or
+    /// decoded by the bus
+    fn check_range(&self, address: u16, length: usize) -> Result<(), Fault> {
+        let end = address as usize + length;
+        let decoded = |address: usize| {
+            address < self.memory_size
+                || address <= u16::MAX as usize && self.bus.decodes(address as u16)
+        };
+        // An empty access still needs its first address
+        let last = end.max(address as usize + 1);
+        if end > self.memory_size && !(address as usize..last).all(decoded) {
+            return Err(Fault::InvalidAddress(address));
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if self.bus.write(&mut self.memory, address, value) {
+            self.observer.memory_written(address, value);
+        }
+    }
+
+    /// Moves the program counter past an instruction, faulting at the end
+    /// of the address space
+    fn advance(&mut self) -> Result<(), Fault> {
+        self.program_counter = self
+            .program_counter
+            .checked_add(2)
+            .ok_or(Fault::InvalidAddress(self.program_counter))?;
+        Ok(())
+    }
+
+    /// Skips the next instruction when `condition` holds
+    fn skip(&mut self, condition: bool) -> Result<(), Fault> {
+        if condition {
+            self.advance()?;
+        }
+        Ok(())
+    }
+
+    fn execute_opcode(&mut self, opcode: u16) -> Result<(), Fault> {
         let i_1 = (opcode & 0xF000) >> 12;
         let i_2 = (opcode & 0x0F00) >> 8;
         let i_3 = (opcode & 0x00F0) >> 4;
         let i_4 = opcode & 0x000F;
 
-        self.program_counter += 2;
+        self.advance()?;
 
         let x = i_2 as u8;
         let y = i_3 as u8;
@@ -36,9 +150,15 @@ impl Cpu {
         match (i_1, i_2, i_3, i_4) {
             (0x0, 0x0, 0x0, 0x0) => self.program_counter -= 2,
             // Clear screen
-            (0x0, 0x0, 0xE, 0x0) => self.display.clear_screen(),
+            (0x0, 0x0, 0xE, 0x0) => {
+                self.display.clear_screen();
+                self.observer.screen_cleared();
+            }
             // Ret
             (0x0, 0x0, 0xE, 0xE) => {
+                if self.stack_pointer == 0 {
+                    return Err(Fault::StackUnderflow);
+                }
                 self.stack_pointer -= 1;
                 self.program_counter = self.stack[self.stack_pointer as usize];
             }
@@ -46,23 +166,29 @@ impl Cpu {
             (0x1, _, _, _) => self.program_counter = opcode & 0x0FFF,
             // Call
             (0x2, _, _, _) => {
+                let depth = match self.stack_overflow {
+                    StackOverflow::Capacity => STACK_CAPACITY,
+                    _ => self.stack_depth as usize,
+                };
+                if self.stack_pointer as usize >= depth {
+                    if self.stack_overflow != StackOverflow::Wrap || depth == 0 {
+                        return Err(Fault::StackOverflow);
+                    }
+                    // The oldest return address makes room, as in a ring
+                    self.stack.copy_within(1..depth, 0);
+                    self.stack_pointer = depth as u8 - 1;
+                }
                 self.stack[self.stack_pointer as usize] = self.program_counter;
                 self.stack_pointer += 1;
                 self.program_counter = opcode & 0x0FFF;
             }
             // Skip if Vx = NN
-            (0x3, _, _, _) => self.program_counter += if self.v[x as usize] == nn { 2 } else { 0 },
+            (0x3, _, _, _) => self.skip(self.v[x as usize] == nn)?,
 
             // Skip if Vx != NN
-            (0x4, _, _, _) => self.program_counter += if self.v[x as usize] != nn { 2 } else { 0 },
+            (0x4, _, _, _) => self.skip(self.v[x as usize] != nn)?,
             // Skip if Vx == Vy
-            (0x5, _, _, 0x0) => {
-                self.program_counter += if self.v[x as usize] == self.v[y as usize] {
-                    2
-                } else {
-                    0
-                }
-            }
+            (0x5, _, _, 0x0) => self.skip(self.v[x as usize] == self.v[y as usize])?,
             // Store NN in Vx
             (0x6, _, _, _) => self.v[x as usize] = nn,
             // Add nn to Vx
@@ -73,75 +199,93 @@ impl Cpu {
             // Set Vy = Vx
             (0x8, _, _, 0x0) => self.v[x as usize] = self.v[y as usize],
             // Set vX to Vx | Vy
-            (0x8, _, _, 0x1) => self.v[x as usize] |= self.v[y as usize],
+            (0x8, _, _, 0x1) => {
+                self.v[x as usize] |= self.v[y as usize];
+                self.reset_vf_after_logic();
+            }
             // Set vX to Vx & Vy
-            (0x8, _, _, 0x2) => self.v[x as usize] &= self.v[y as usize],
+            (0x8, _, _, 0x2) => {
+                self.v[x as usize] &= self.v[y as usize];
+                self.reset_vf_after_logic();
+            }
             // Set vX to Vx ^ Vy
-            (0x8, _, _, 0x3) => self.v[x as usize] ^= self.v[y as usize],
+            (0x8, _, _, 0x3) => {
+                self.v[x as usize] ^= self.v[y as usize];
+                self.reset_vf_after_logic();
+            }
             // Add Vx + Vy in Vx, set VF to 1 if overflow
             (0x8, _, _, 0x4) => {
                 let (res, overflow) = self.v[x as usize].overflowing_add(self.v[y as usize]);
-                self.v[0x0F] = if overflow { 1 } else { 0 };
                 self.v[x as usize] = res;
+                self.v[0x0F] = if overflow { 1 } else { 0 };
             }
             // Vx - Vy, set VF to 0 if borrow
             (0x8, _, _, 0x5) => {
                 let (res, overflow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
-                self.v[0x0F] = if overflow { 0 } else { 1 };
                 self.v[x as usize] = res;
+                self.v[0x0F] = if overflow { 0 } else { 1 };
             }
             // Vx = Vy >> 1, VF = LSB from Vy before op
             (0x8, _, _, 0x6) => {
-                self.v[0xF] = self.v[y as usize] & 0x1;
-                self.v[x as usize] = self.v[y as usize] >> 1;
+                let source = self.shift_source(x, y);
+                self.v[x as usize] = source >> 1;
+                self.v[0xF] = source & 0x1;
             }
             // Set Vx to Vy - Vx, VF=1 if borrow
             (0x8, _, _, 0x7) => {
                 let (res, overflow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
-                self.v[0x0F] = if overflow { 0 } else { 1 };
                 self.v[x as usize] = res;
+                self.v[0x0F] = if overflow { 0 } else { 1 };
             }
             // Vx = Vy << 1, VF = MSB from Vy before op
             (0x8, _, _, 0xE) => {
-                self.v[0xF] = self.v[y as usize] & 0x80;
-                self.v[x as usize] = self.v[y as usize] << 1;
+                let source = self.shift_source(x, y);
+                self.v[x as usize] = source << 1;
+                self.v[0xF] = source >> 7;
             }
             // Skip instruction if Vx != Vy
-            (0x9, _, _, 0x0) => {
-                self.program_counter += if self.v[x as usize] != self.v[y as usize] {
-                    2
-                } else {
-                    0
-                }
-            }
+            (0x9, _, _, 0x0) => self.skip(self.v[x as usize] != self.v[y as usize])?,
             // Store NNN in register I
             (0xA, _, _, _) => self.i = opcode & 0x0FFF,
             // Store NNN in register I
-            (0xB, _, _, _) => self.program_counter = (opcode & 0x0FFF) + self.v[0] as u16,
+            (0xB, _, _, _) => {
+                let offset = if self.quirks.jump { x } else { 0 };
+                self.program_counter = (opcode & 0x0FFF) + self.v[offset as usize] as u16;
+            }
             // Set Vx to random number with mask nn
             (0xC, _, _, _) => {
-                self.v[x as usize] = rand::thread_rng().gen_range(0x0..0xFF) & nn;
+                self.v[x as usize] = self.rng.gen::<u8>() & nn;
             }
             // DRAW!!!
             (0xD, _, _, _) => {
-                let n = opcode & 0x000F;
-                let sprite_collision = self.display.draw(
-                    self.v[x as usize] as usize,
-                    self.v[y as usize] as usize,
-                    &self.memory[self.i as usize..(self.i + n) as usize],
-                );
+                let n = (opcode & 0x000F) as usize;
+                self.check_range(self.i, n)?;
+                let mut sprite = [0; 15];
+                for (row, offset) in sprite[..n].iter_mut().zip(0..) {
+                    *row = self.read(self.i + offset);
+                }
+                let sprite = &sprite[..n];
+                let (vx, vy) = (self.v[x as usize] as usize, self.v[y as usize] as usize);
+                let sprite_collision = if self.quirks.wrap {
+                    self.display.draw(vx, vy, sprite)
+                } else {
+                    self.display.draw_clipped(vx, vy, sprite)
+                };
 
                 self.v[0xF] = if sprite_collision { 1 } else { 0 };
+                self.waiting_vblank = self.quirks.vblank;
+                self.observer
+                    .sprite_drawn(vx as u8, vy as u8, n as u8, sprite_collision);
             }
             // Skip if key Vx is pressed
             (0xE, _, 0x9, 0xE) => {
-                let vx = self.v[x as usize] as usize;
-                self.program_counter += if self.keypad.is_key_down(vx) { 2 } else { 0 }
+                let vx = (self.v[x as usize] & 0xF) as usize;
+                self.skip(self.keypad.is_key_down(vx))?
             }
             // Skip if key Vx is not pressed
             (0xE, _, 0xA, 0x1) => {
-                let vx = self.v[x as usize] as usize;
-                self.program_counter += if self.keypad.is_key_down(vx) { 0 } else { 2 }
+                let vx = (self.v[x as usize] & 0xF) as usize;
+                self.skip(!self.keypad.is_key_down(vx))?
             }
             // Set Vx value to delay timer
             (0xF, _, 0x0, 0x7) => self.v[x as usize] = self.delay_timer,
@@ -151,69 +295,174 @@ impl Cpu {
                 if let Some(pressed) = self.keypad.any_key_pressed() {
                     self.program_counter += 2;
                     self.v[x as usize] = pressed;
+                    self.waiting_key = false;
+                } else if !self.waiting_key {
+                    self.waiting_key = true;
+                    self.observer.key_wait(x);
                 }
             }
             // Set the delay timer to the value stored in Vx
             (0xF, _, 0x1, 0x5) => self.delay_timer = self.v[x as usize],
             // Set the sound timer to the value stored in Vx
-            (0xF, _, 0x1, 0x8) => self.sound_timer = self.v[x as usize],
+            (0xF, _, 0x1, 0x8) => {
+                let playing = self.sound_timer > 0;
+                self.sound_timer = self.v[x as usize];
+                match (playing, self.sound_timer > 0) {
+                    (false, true) => self.observer.sound_started(),
+                    (true, false) => self.observer.sound_stopped(),
+                    _ => {}
+                }
+            }
             // Set I = I + Vx
-            (0xF, _, 0x1, 0xE) => self.i += self.v[x as usize] as u16,
-            // Set I to the sprite stored in Vx
-            (0xF, _, 0x2, 0x9) => self.i = self.v[x as usize] as u16 * 5,
+            (0xF, _, 0x1, 0xE) => self.i = self.i.wrapping_add(self.v[x as usize] as u16),
+            // Set I to the sprite of the digit stored in Vx
+            (0xF, _, 0x2, 0x9) => {
+                self.i = self.font_address + (self.v[x as usize] & 0xF) as u16 * GLYPH_SIZE
+            }
             // Store the BCD of Vx in address I, I+1, I+2
             (0xF, _, 0x3, 0x3) => {
                 let vx = self.v[x as usize];
-                self.memory[self.i as usize] = vx / 100;
-                self.memory[self.i as usize + 1] = (vx / 10) % 10;
-                self.memory[self.i as usize + 2] = (vx % 100) % 10;
+                self.check_range(self.i, 3)?;
+                self.write(self.i, vx / 100);
+                self.write(self.i + 1, (vx / 10) % 10);
+                self.write(self.i + 2, (vx % 100) % 10);
             }
             // Set [I, I+X]
             // Store values V0 to Vx to address I to I + X, set I = I + X +1
             (0xF, _, 0x5, 0x5) => {
-                self.memory[(self.i as usize)..(self.i as usize + x as usize + 1)]
-                    .copy_from_slice(&self.v[0..(x + 1) as usize]);
-                self.i += x as u16 + 1;
+                self.check_range(self.i, x as usize + 1)?;
+                for register in 0..=x {
+                    self.write(self.i + register as u16, self.v[register as usize]);
+                }
+                self.increment_i_after_load_store(x);
             }
             // Store values V0 to Vx to address I to I + X, set I = I + X +1
             (0xF, _, 0x6, 0x5) => {
-                self.v[0..(x + 1) as usize].copy_from_slice(
-                    &self.memory[(self.i as usize)..(self.i as usize + x as usize + 1)],
-                );
-                self.i += x as u16 + 1;
+                self.check_range(self.i, x as usize + 1)?;
+                for register in 0..=x {
+                    self.v[register as usize] = self.read(self.i + register as u16);
+                }
+                self.increment_i_after_load_store(x);
             }
 
             _ => {}
         }
+        Ok(())
     }
 
-    pub fn new<Reader: Read>(mut file: Reader) -> std::io::Result<Box<Cpu>> {
-        let mut cpu = Box::new(Cpu {
-            v: [0; 16],
-            memory: [0; 4096],
-            i: 0,
-            stack: [0; 24],
-            program_counter: START_ADDRESS,
-            stack_pointer: 0,
-            delay_timer: 0,
-            sound_timer: 0,
-            keypad: KeyPad::new(),
-            display: Display::new(),
-        });
-        for (i, item) in DEFAULT_FONTS.iter().enumerate() {
-            cpu.memory[i] = *item;
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic {
+            self.v[0xF] = 0;
+        }
+    }
+
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift {
+            self.v[x as usize]
+        } else {
+            self.v[y as usize]
+        }
+    }
+
+    fn increment_i_after_load_store(&mut self, x: u8) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
+        }
+        let increment = if self.quirks.memory_increment_by_x {
+            x as u16
+        } else {
+            x as u16 + 1
+        };
+        self.i = self.i.wrapping_add(increment);
+    }
+
+    pub(crate) fn load_font(&mut self, font: &Font) -> Result<(), RomError> {
+        let start = font.address as usize;
+        if start + FONT_SIZE > self.memory_size {
+            return Err(RomError::InvalidAddress(font.address));
+        }
+
+        self.memory[start..start + FONT_SIZE].copy_from_slice(&font.glyphs);
+        self.font_address = font.address;
+        Ok(())
+    }
+
+    pub(crate) fn load_program(
+        &mut self,
+        rom: &[u8],
+        load_address: u16,
+        entry_point: u16,
+    ) -> Result<(), RomError> {
+        for address in [load_address, entry_point] {
+            if address as usize >= self.memory_size {
+                return Err(RomError::InvalidAddress(address));
+            }
         }
 
-        let _ = file.read(&mut cpu.memory[(START_ADDRESS as usize)..])?;
+        let program = &mut self.memory[(load_address as usize)..self.memory_size];
+        if rom.len() > program.len() {
+            return Err(RomError::TooLarge {
+                size: rom.len(),
+                available: program.len(),
+            });
+        }
+
+        program[..rom.len()].copy_from_slice(rom);
+        self.program_counter = entry_point;
+        self.rom_size = rom.len();
+        Ok(())
+    }
 
-        Ok(cpu)
+    /// Sets the memory and call stack limits, checked by the builder
+    pub(crate) fn configure(
+        &mut self,
+        memory_size: usize,
+        stack_depth: u8,
+        stack_overflow: StackOverflow,
+    ) {
+        self.memory_size = memory_size;
+        self.stack_depth = stack_depth;
+        self.stack_overflow = stack_overflow;
     }
 
+    /// Size in bytes of the loaded program
+    pub fn rom_size(&self) -> usize {
+        self.rom_size
+    }
+
+    /// What stopped the machine, if anything
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    /// Where the font was loaded, `FX29` points I inside it
+    pub fn font_address(&self) -> u16 {
+        self.font_address
+    }
+
+    /// Runs the next instruction, nothing happens once the machine faulted.
     pub fn next(&mut self) {
-        let instruction = (self.memory[self.program_counter as usize] as u16) << 8
-            | (self.memory[(self.program_counter + 1) as usize] as u16);
+        if self.waiting_vblank || self.fault.is_some() {
+            return;
+        }
 
-        self.execute_opcode(instruction);
+        let program_counter = self.program_counter;
+        let result = self.check_range(program_counter, 2).and_then(|()| {
+            let instruction = (self.read(program_counter) as u16) << 8
+                | self.read(program_counter + 1) as u16;
+            self.execute_opcode(instruction).map(|()| instruction)
+        });
+        match result {
+            Ok(opcode) => {
+                self.cycles += 1;
+                self.observer.instruction(program_counter, opcode);
+            }
+            Err(fault) => {
+                // Leave the program counter at the faulting instruction
+                self.program_counter = program_counter;
+                self.fault = Some(fault);
+            }
+        }
     }
 
     pub fn set_key(&mut self, key_index: u8, status: bool) {
@@ -221,12 +470,17 @@ impl Cpu {
     }
 
     pub fn decrease_timers(&mut self) {
+        self.waiting_vblank = false;
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
+            if self.sound_timer == 0 {
+                self.observer.sound_stopped();
+            }
         }
+        self.observer.timers_ticked(self.delay_timer, self.sound_timer);
     }
 
     pub fn should_play_sound(&self) -> bool {
@@ -236,14 +490,208 @@ impl Cpu {
     pub fn get_display(&self) -> &Display {
         &self.display
     }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Read-only view of the registers, memory, call stack and timers
+    pub fn view(&self) -> MachineView<'_> {
+        MachineView {
+            memory: &self.memory[..self.memory_size],
+            v: &self.v,
+            i: self.i,
+            program_counter: self.program_counter,
+            opcode: self.check_range(self.program_counter, 2).ok().map(|()| {
+                let program_counter = self.program_counter;
+                (self.bus.peek(&self.memory, program_counter) as u16) << 8
+                    | self.bus.peek(&self.memory, program_counter + 1) as u16
+            }),
+            stack: &self.stack[..self.stack_pointer as usize],
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            display: &self.display,
+            cycles: self.cycles,
+            waiting_vblank: self.waiting_vblank,
+        }
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Changes the registers, memory and timers of a running machine
+    pub fn poke(&mut self) -> Poke<'_> {
+        Poke {
+            memory: &mut self.memory[..self.memory_size],
+            v: &mut self.v,
+            i: &mut self.i,
+            program_counter: &mut self.program_counter,
+            delay_timer: &mut self.delay_timer,
+            sound_timer: &mut self.sound_timer,
+        }
+    }
+
+    /// FNV-1a hash of the whole machine state (registers, memory, stack,
+    /// timers and screen), used to detect when two machines diverge.
+    pub fn state_hash(&self) -> u64 {
+        let mut hash = FNV_OFFSET_BASIS;
+        hash = fnv1a(hash, &self.v);
+        hash = fnv1a(hash, &self.memory);
+        hash = fnv1a(hash, &self.i.to_be_bytes());
+        for address in self.stack.iter() {
+            hash = fnv1a(hash, &address.to_be_bytes());
+        }
+        hash = fnv1a(hash, &self.program_counter.to_be_bytes());
+        hash = fnv1a(
+            hash,
+            &[self.stack_pointer, self.delay_timer, self.sound_timer],
+        );
+        fnv1a(hash, self.display.get_video_mem())
+    }
+}
+
+impl<R: RngCore> Cpu<R> {
+    /// Creates a machine with `rom` loaded at the start address, use a
+    /// [`RomLoader`] to load it somewhere else.
+    pub fn from_bytes(rom: &[u8], rng: R) -> Result<Cpu<R>, RomError> {
+        RomLoader::new().load(rom, rng)
+    }
+
+    /// Machine with empty memory
+    pub(crate) fn blank(rng: R) -> Cpu<R> {
+        Cpu {
+            v: [0; 16],
+            memory: [0; MEMORY_SIZE],
+            memory_size: MEMORY_SIZE,
+            i: 0,
+            stack: [0; STACK_CAPACITY],
+            stack_depth: STACK_DEPTH,
+            stack_overflow: StackOverflow::Fault,
+            program_counter: START_ADDRESS,
+            stack_pointer: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            keypad: KeyPad::new(),
+            display: Display::new(),
+            rng,
+            quirks: Quirks::default(),
+            waiting_vblank: false,
+            cycles: 0,
+            rom_size: 0,
+            font_address: 0,
+            fault: None,
+            waiting_key: false,
+            bus: DirectBus,
+            observer: NoObserver,
+        }
+    }
+
+    /// Routes the memory accesses of the program through `bus`
+    pub fn with_bus<B: Bus>(self, bus: B) -> Cpu<R, B> {
+        Cpu {
+            v: self.v,
+            memory: self.memory,
+            memory_size: self.memory_size,
+            i: self.i,
+            stack: self.stack,
+            stack_depth: self.stack_depth,
+            stack_overflow: self.stack_overflow,
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            keypad: self.keypad,
+            display: self.display,
+            rng: self.rng,
+            quirks: self.quirks,
+            waiting_vblank: self.waiting_vblank,
+            cycles: self.cycles,
+            rom_size: self.rom_size,
+            font_address: self.font_address,
+            fault: self.fault,
+            waiting_key: self.waiting_key,
+            bus,
+            observer: self.observer,
+        }
+    }
+}
+
+impl<R, B> Cpu<R, B> {
+    /// Tells `observer` about everything the program does
+    pub fn with_observer<O: Observer>(self, observer: O) -> Cpu<R, B, O> {
+        Cpu {
+            v: self.v,
+            memory: self.memory,
+            memory_size: self.memory_size,
+            i: self.i,
+            stack: self.stack,
+            stack_depth: self.stack_depth,
+            stack_overflow: self.stack_overflow,
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            keypad: self.keypad,
+            display: self.display,
+            rng: self.rng,
+            quirks: self.quirks,
+            waiting_vblank: self.waiting_vblank,
+            cycles: self.cycles,
+            rom_size: self.rom_size,
+            font_address: self.font_address,
+            fault: self.fault,
+            waiting_key: self.waiting_key,
+            bus: self.bus,
+            observer,
+        }
+    }
+}
+
+impl<R: RngCore + SeedableRng, B, O> Cpu<R, B, O> {
+    /// Reseeds the random number generator used by `CXNN`, two machines
+    /// running the same program with the same seed produce the same values.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = R::seed_from_u64(seed);
+    }
+}
+
+#[cfg(feature = "std")]
+impl Cpu {
+    /// Reads the whole program from `file` and loads it at the start
+    /// address, ROMs that don't fit in memory are rejected.
+    pub fn new<Reader: Read>(file: Reader) -> std::io::Result<Cpu> {
+        RomLoader::new().read(file)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
 
-    use crate::cpu::{Cpu, START_ADDRESS};
+    use rand::rngs::mock::StepRng;
+
+    use crate::cpu::{Cpu, Fault, STACK_CAPACITY, START_ADDRESS};
     use crate::display::Pixel;
+    use crate::font::Font;
+    use crate::quirks::Quirks;
+    use crate::rom::RomLoader;
 
     #[test]
     fn default_initialized() -> std::io::Result<()> {
@@ -251,7 +699,7 @@ mod tests {
         let cpu = Cpu::new(Cursor::new(data))?;
         assert_eq!(&cpu.memory[0x200..0x400], &[0; 0x200]);
         assert_eq!(&cpu.v, &[0; 16]);
-        assert_eq!(&cpu.stack, &[0; 24]);
+        assert_eq!(&cpu.stack[..], &[0; STACK_CAPACITY][..]);
 
         assert_eq!(cpu.sound_timer, 0);
         assert_eq!(cpu.delay_timer, 0);
@@ -262,6 +710,15 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn from_bytes_with_rng() {
+        let mut cpu = Cpu::from_bytes(&[0xC0, 0xF0], StepRng::new(u64::MAX, 0)).unwrap();
+        assert_eq!(&cpu.memory[0x200..0x202], &[0xC0, 0xF0]);
+
+        cpu.next();
+        assert_eq!(cpu.v[0], 0xF0, "the injected generator is used");
+    }
+
     #[test]
     fn clear_display() -> std::io::Result<()> {
         let data = [0x00, 0xE0];
@@ -405,6 +862,127 @@ mod tests {
         );
     }
 
+    #[test]
+    fn seeded_rng_is_deterministic() {
+        let data = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF];
+        let mut first = Cpu::new(Cursor::new(data)).unwrap();
+        let mut second = Cpu::new(Cursor::new(data)).unwrap();
+        first.seed_rng(42);
+        second.seed_rng(42);
+
+        for _ in 0..3 {
+            first.next();
+            second.next();
+        }
+
+        assert_eq!(first.v, second.v, "same seed, same random values");
+    }
+
+    #[test]
+    fn state_hash_tracks_changes() {
+        let data = [0x60, 0x01];
+        let mut cpu = Cpu::new(Cursor::new(data)).unwrap();
+        let other = Cpu::new(Cursor::new(data)).unwrap();
+        assert_eq!(cpu.state_hash(), other.state_hash());
+
+        cpu.next();
+        assert_ne!(cpu.state_hash(), other.state_hash(), "V0 changed");
+    }
+
+    #[test]
+    fn quirk_shift() {
+        let data = [0x81, 0x26, 0x81, 0x26];
+        let mut cpu = Cpu::new(Cursor::new(data)).unwrap();
+        cpu.v[1] = 0b100;
+        cpu.v[2] = 0b1000;
+
+        cpu.next();
+        assert_eq!(cpu.v[1], 0b100, "V1 = V2 >> 1");
+
+        cpu.set_quirks(Quirks {
+            shift: true,
+            ..Quirks::default()
+        });
+        cpu.next();
+        assert_eq!(cpu.v[1], 0b10, "V1 = V1 >> 1");
+    }
+
+    #[test]
+    fn quirk_load_store() {
+        let data = [0xF2, 0x55, 0xF2, 0x55, 0xF2, 0x55];
+        let mut cpu = Cpu::new(Cursor::new(data)).unwrap();
+
+        cpu.next();
+        assert_eq!(cpu.i, 3);
+
+        cpu.set_quirks(Quirks {
+            memory_increment_by_x: true,
+            ..Quirks::default()
+        });
+        cpu.next();
+        assert_eq!(cpu.i, 5);
+
+        cpu.set_quirks(Quirks::SCHIP);
+        cpu.next();
+        assert_eq!(cpu.i, 5);
+    }
+
+    #[test]
+    fn quirk_logic() {
+        let data = [0x80, 0x11, 0x80, 0x11];
+        let mut cpu = Cpu::new(Cursor::new(data)).unwrap();
+        cpu.v[0xF] = 1;
+
+        cpu.next();
+        assert_eq!(cpu.v[0xF], 1);
+
+        cpu.set_quirks(Quirks::VIP);
+        cpu.next();
+        assert_eq!(cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn flags() {
+        // VF = VF << 1, VF = V0 + V1
+        let data = [0x8F, 0xFE, 0x8F, 0x04];
+        let mut cpu = Cpu::new(Cursor::new(data)).unwrap();
+        cpu.v[0xF] = 0x81;
+        cpu.next();
+        assert_eq!(cpu.v[0xF], 1, "the flag wins over the result");
+
+        cpu.v[0x0] = 0xFF;
+        cpu.v[0xF] = 0x01;
+        cpu.next();
+        assert_eq!(cpu.v[0xF], 1);
+    }
+
+    #[test]
+    fn quirk_jump() {
+        let data = [0xB3, 0x00];
+        let mut cpu = Cpu::new(Cursor::new(data)).unwrap();
+        cpu.set_quirks(Quirks::SCHIP);
+        cpu.v[0] = 1;
+        cpu.v[3] = 2;
+
+        cpu.next();
+        assert_eq!(cpu.program_counter, 0x302, "jumps to 0x300 + V3");
+    }
+
+    #[test]
+    fn quirk_vblank() {
+        let data = [0xD0, 0x01, 0xD0, 0x01];
+        let mut cpu = Cpu::new(Cursor::new(data)).unwrap();
+        cpu.set_quirks(Quirks::VIP);
+
+        cpu.next();
+        cpu.next();
+        assert_eq!(cpu.program_counter, 0x202, "waits for the frame to end");
+
+        cpu.decrease_timers();
+        cpu.next();
+        assert_eq!(cpu.program_counter, 0x204);
+    }
+
     #[test]
     fn opcode_ld_i_addr() {
         let data = [0x61, 0xAA, 0x62, 0x1A, 0x6A, 0x15];
@@ -432,4 +1010,69 @@ mod tests {
             "the program counter is advanced two bytes"
         );
     }
+
+    #[test]
+    fn font_location() {
+        // V0 = 0xA, I = sprite of V0
+        let data = [0x60, 0x0A, 0xF0, 0x29];
+
+        let mut cpu = Cpu::new(Cursor::new(data)).unwrap();
+        assert_eq!(&cpu.memory[0x50..0xA0], &Font::SCHIP.glyphs[..]);
+        cpu.next();
+        cpu.next();
+        assert_eq!(cpu.i, 0x50 + 0xA * 5);
+
+        let mut cpu = RomLoader::new()
+            .font(Font::VIP.at(0x000))
+            .load(&data, StepRng::new(0, 1))
+            .unwrap();
+        assert_eq!(&cpu.memory[..0x50], &Font::VIP.glyphs[..]);
+        cpu.next();
+        cpu.next();
+        assert_eq!(cpu.i, 0xA * 5);
+    }
+
+    #[test]
+    fn view_and_poke() {
+        // Call 0x206, call 0x208, V0 = delay timer
+        let data = [0x22, 0x04, 0x00, 0x00, 0x22, 0x06, 0xF0, 0x07];
+
+        let mut cpu = Cpu::new(Cursor::new(data)).unwrap();
+        cpu.next();
+        cpu.next();
+        assert_eq!(cpu.view().call_stack(), &[0x202, 0x206]);
+        assert_eq!(cpu.view().memory_range(0x200..0x202), Some(&[0x22, 0x04][..]));
+        assert_eq!(cpu.view().memory_range(0xFFF..0x1001), None);
+
+        let mut poke = cpu.poke();
+        poke.set_delay_timer(0x42);
+        poke.memory()[0x300] = 0xAB;
+        cpu.next();
+        assert_eq!(cpu.view().registers()[0], 0x42);
+        assert_eq!(cpu.view().delay_timer(), 0x42);
+        assert_eq!(cpu.view().memory()[0x300], 0xAB);
+    }
+
+    #[test]
+    fn faults() {
+        let cases: [(&[u8], Fault); 4] = [
+            (&[0x22, 0x00], Fault::StackOverflow),
+            (&[0x00, 0xEE], Fault::StackUnderflow),
+            (&[0xAF, 0xFF, 0xD0, 0x02], Fault::InvalidAddress(0xFFF)),
+            (&[0x60, 0xFF, 0xBF, 0xFF], Fault::InvalidAddress(0x10FE)),
+        ];
+        for (program, fault) in cases.iter() {
+            let mut cpu = Cpu::new(Cursor::new(program)).unwrap();
+            for _ in 0..100 {
+                cpu.next();
+            }
+            assert_eq!(cpu.fault(), Some(*fault));
+        }
+
+        // The program counter stays at the faulting instruction
+        let mut cpu = Cpu::new(Cursor::new([0x00, 0xEE])).unwrap();
+        cpu.next();
+        assert_eq!(cpu.program_counter, 0x200);
+        assert_eq!(cpu.view().cycles(), 0);
+    }
 }
@@ -0,0 +1,247 @@
+//! Disassembler, with the mnemonics of Cowgod's Chip-8 technical reference.
+//!
+//! ```
+//! use chip_8::disasm::Instruction;
+//!
+//! assert_eq!(Instruction::decode(0xD125).to_string(), "DRW V1, V2, 5");
+//! ```
+
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// `0NNN`, a machine code routine on the original interpreter
+    Sys(u16),
+    /// `00E0`
+    Cls,
+    /// `00EE`
+    Ret,
+    /// `1NNN`
+    Jump(u16),
+    /// `2NNN`
+    Call(u16),
+    /// `3XNN`
+    SkipEqualByte(u8, u8),
+    /// `4XNN`
+    SkipNotEqualByte(u8, u8),
+    /// `5XY0`
+    SkipEqual(u8, u8),
+    /// `6XNN`
+    LoadByte(u8, u8),
+    /// `7XNN`
+    AddByte(u8, u8),
+    /// `8XY0`
+    Load(u8, u8),
+    /// `8XY1`
+    Or(u8, u8),
+    /// `8XY2`
+    And(u8, u8),
+    /// `8XY3`
+    Xor(u8, u8),
+    /// `8XY4`
+    Add(u8, u8),
+    /// `8XY5`
+    Sub(u8, u8),
+    /// `8XY6`
+    ShiftRight(u8, u8),
+    /// `8XY7`
+    SubNegated(u8, u8),
+    /// `8XYE`
+    ShiftLeft(u8, u8),
+    /// `9XY0`
+    SkipNotEqual(u8, u8),
+    /// `ANNN`
+    LoadI(u16),
+    /// `BNNN`
+    JumpOffset(u16),
+    /// `CXNN`
+    Random(u8, u8),
+    /// `DXYN`
+    Draw(u8, u8, u8),
+    /// `EX9E`
+    SkipKey(u8),
+    /// `EXA1`
+    SkipNotKey(u8),
+    /// `FX07`
+    LoadDelay(u8),
+    /// `FX0A`
+    WaitKey(u8),
+    /// `FX15`
+    SetDelay(u8),
+    /// `FX18`
+    SetSound(u8),
+    /// `FX1E`
+    AddI(u8),
+    /// `FX29`
+    LoadFont(u8),
+    /// `FX33`
+    Bcd(u8),
+    /// `FX55`
+    Store(u8),
+    /// `FX65`
+    Restore(u8),
+    /// Anything else, shown as data
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Instruction {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        match (opcode >> 12, x, y, n) {
+            (0x0, 0x0, 0xE, 0x0) => Instruction::Cls,
+            (0x0, 0x0, 0xE, 0xE) => Instruction::Ret,
+            (0x0, _, _, _) => Instruction::Sys(nnn),
+            (0x1, _, _, _) => Instruction::Jump(nnn),
+            (0x2, _, _, _) => Instruction::Call(nnn),
+            (0x3, _, _, _) => Instruction::SkipEqualByte(x, nn),
+            (0x4, _, _, _) => Instruction::SkipNotEqualByte(x, nn),
+            (0x5, _, _, 0x0) => Instruction::SkipEqual(x, y),
+            (0x6, _, _, _) => Instruction::LoadByte(x, nn),
+            (0x7, _, _, _) => Instruction::AddByte(x, nn),
+            (0x8, _, _, 0x0) => Instruction::Load(x, y),
+            (0x8, _, _, 0x1) => Instruction::Or(x, y),
+            (0x8, _, _, 0x2) => Instruction::And(x, y),
+            (0x8, _, _, 0x3) => Instruction::Xor(x, y),
+            (0x8, _, _, 0x4) => Instruction::Add(x, y),
+            (0x8, _, _, 0x5) => Instruction::Sub(x, y),
+            (0x8, _, _, 0x6) => Instruction::ShiftRight(x, y),
+            (0x8, _, _, 0x7) => Instruction::SubNegated(x, y),
+            (0x8, _, _, 0xE) => Instruction::ShiftLeft(x, y),
+            (0x9, _, _, 0x0) => Instruction::SkipNotEqual(x, y),
+            (0xA, _, _, _) => Instruction::LoadI(nnn),
+            (0xB, _, _, _) => Instruction::JumpOffset(nnn),
+            (0xC, _, _, _) => Instruction::Random(x, nn),
+            (0xD, _, _, _) => Instruction::Draw(x, y, n),
+            (0xE, _, 0x9, 0xE) => Instruction::SkipKey(x),
+            (0xE, _, 0xA, 0x1) => Instruction::SkipNotKey(x),
+            (0xF, _, 0x0, 0x7) => Instruction::LoadDelay(x),
+            (0xF, _, 0x0, 0xA) => Instruction::WaitKey(x),
+            (0xF, _, 0x1, 0x5) => Instruction::SetDelay(x),
+            (0xF, _, 0x1, 0x8) => Instruction::SetSound(x),
+            (0xF, _, 0x1, 0xE) => Instruction::AddI(x),
+            (0xF, _, 0x2, 0x9) => Instruction::LoadFont(x),
+            (0xF, _, 0x3, 0x3) => Instruction::Bcd(x),
+            (0xF, _, 0x5, 0x5) => Instruction::Store(x),
+            (0xF, _, 0x6, 0x5) => Instruction::Restore(x),
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+
+    /// The opcode pattern of the instruction, such as `8XY4`
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Instruction::Sys(_) => "0NNN",
+            Instruction::Cls => "00E0",
+            Instruction::Ret => "00EE",
+            Instruction::Jump(_) => "1NNN",
+            Instruction::Call(_) => "2NNN",
+            Instruction::SkipEqualByte(..) => "3XNN",
+            Instruction::SkipNotEqualByte(..) => "4XNN",
+            Instruction::SkipEqual(..) => "5XY0",
+            Instruction::LoadByte(..) => "6XNN",
+            Instruction::AddByte(..) => "7XNN",
+            Instruction::Load(..) => "8XY0",
+            Instruction::Or(..) => "8XY1",
+            Instruction::And(..) => "8XY2",
+            Instruction::Xor(..) => "8XY3",
+            Instruction::Add(..) => "8XY4",
+            Instruction::Sub(..) => "8XY5",
+            Instruction::ShiftRight(..) => "8XY6",
+            Instruction::SubNegated(..) => "8XY7",
+            Instruction::ShiftLeft(..) => "8XYE",
+            Instruction::SkipNotEqual(..) => "9XY0",
+            Instruction::LoadI(_) => "ANNN",
+            Instruction::JumpOffset(_) => "BNNN",
+            Instruction::Random(..) => "CXNN",
+            Instruction::Draw(..) => "DXYN",
+            Instruction::SkipKey(_) => "EX9E",
+            Instruction::SkipNotKey(_) => "EXA1",
+            Instruction::LoadDelay(_) => "FX07",
+            Instruction::WaitKey(_) => "FX0A",
+            Instruction::SetDelay(_) => "FX15",
+            Instruction::SetSound(_) => "FX18",
+            Instruction::AddI(_) => "FX1E",
+            Instruction::LoadFont(_) => "FX29",
+            Instruction::Bcd(_) => "FX33",
+            Instruction::Store(_) => "FX55",
+            Instruction::Restore(_) => "FX65",
+            Instruction::Unknown(_) => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Sys(address) => write!(f, "SYS {:#05X}", address),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jump(address) => write!(f, "JP {:#05X}", address),
+            Instruction::Call(address) => write!(f, "CALL {:#05X}", address),
+            Instruction::SkipEqualByte(x, nn) => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipNotEqualByte(x, nn) => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipEqual(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LoadByte(x, nn) => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Instruction::AddByte(x, nn) => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Instruction::Load(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::Add(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubNegated(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNotEqual(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI(address) => write!(f, "LD I, {:#05X}", address),
+            Instruction::JumpOffset(address) => write!(f, "JP V0, {:#05X}", address),
+            Instruction::Random(x, nn) => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKey(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::Store(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::Restore(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm::Instruction;
+
+    #[test]
+    fn mnemonics() {
+        let cases = [
+            (0x00E0, "CLS"),
+            (0x00EE, "RET"),
+            (0x0123, "SYS 0x123"),
+            (0x1ABC, "JP 0xABC"),
+            (0x2200, "CALL 0x200"),
+            (0x3A0F, "SE VA, 0x0F"),
+            (0x5120, "SE V1, V2"),
+            (0x8AB6, "SHR VA, VB"),
+            (0xA22A, "LD I, 0x22A"),
+            (0xB300, "JP V0, 0x300"),
+            (0xF155, "LD [I], V1"),
+            (0xF265, "LD V2, [I]"),
+            (0x5121, "DW 0x5121"),
+            (0xFFFF, "DW 0xFFFF"),
+        ];
+        for (opcode, text) in cases.iter() {
+            assert_eq!(Instruction::decode(*opcode).to_string(), *text);
+        }
+    }
+}
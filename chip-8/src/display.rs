@@ -21,7 +21,10 @@ pub const DEFAULT_FONTS: [u8; 80] = [
 
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
+/// Size of the screen packed one bit per pixel.
+pub const BITMAP_SIZE: usize = WIDTH * HEIGHT / 8;
 
+#[derive(Clone)]
 pub struct Display {
     memory: [u8; WIDTH * HEIGHT],
 }
@@ -92,14 +95,47 @@ impl Display {
         collision
     }
 
+    /// Draws like [`Display::draw`] but the pixels past the edges of the
+    /// screen are clipped, only the starting position wraps around.
+    pub fn draw_clipped(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let (x, y) = (x % WIDTH, y % HEIGHT);
+        let mut collision = false;
+        for (j, row) in sprite.iter().enumerate().take(HEIGHT - y) {
+            for i in 0..8.min(WIDTH - x) {
+                if row >> (7 - i) & 0x01 == 1 {
+                    let old_value = self.get_pixel(x + i, y + j) as u8;
+                    if old_value == 1 {
+                        collision = true;
+                    }
+                    self.set_pixel(x + i, y + j, Pixel::from(old_value ^ 1));
+                }
+            }
+        }
+
+        collision
+    }
+
     pub fn get_video_mem(&self) -> &[u8; WIDTH * HEIGHT] {
         &self.memory
     }
+
+    /// Packs the screen one bit per pixel, row by row, the most significant
+    /// bit of each byte is the leftmost pixel.
+    pub fn to_bitmap(&self) -> [u8; BITMAP_SIZE] {
+        let mut bitmap = [0; BITMAP_SIZE];
+        for (i, pixel) in self.memory.iter().enumerate() {
+            if *pixel != 0 {
+                bitmap[i / 8] |= 0x80 >> (i % 8);
+            }
+        }
+        bitmap
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
-    use crate::display::{Display, Pixel};
+    use crate::display::{Display, Pixel, BITMAP_SIZE};
 
     #[test]
     fn clear() {
@@ -146,6 +182,33 @@ mod tests {
         assert_eq!(Pixel::Off, display.get_pixel(7, 1));
     }
 
+    #[test]
+    fn draw_clipped() {
+        let mut display = Display::new();
+
+        display.draw_clipped(62, 31, &[0xFF, 0xFF]);
+        assert_eq!(Pixel::On, display.get_pixel(63, 31));
+        assert_eq!(Pixel::Off, display.get_pixel(0, 31), "not wrapped");
+        assert_eq!(Pixel::Off, display.get_pixel(62, 0), "not wrapped");
+
+        display.draw_clipped(64 + 2, 0, &[0x80]);
+        assert_eq!(Pixel::On, display.get_pixel(2, 0), "the position wraps");
+    }
+
+    #[test]
+    fn bitmap() {
+        let mut display = Display::new();
+
+        display.set_pixel(0, 0, Pixel::On);
+        display.set_pixel(9, 0, Pixel::On);
+        display.set_pixel(63, 31, Pixel::On);
+
+        let bitmap = display.to_bitmap();
+        assert_eq!(bitmap[0], 0b1000_0000);
+        assert_eq!(bitmap[1], 0b0100_0000);
+        assert_eq!(bitmap[BITMAP_SIZE - 1], 0b0000_0001);
+    }
+
     #[test]
     fn draw_detects_collisions() {
         let mut display = Display::new();
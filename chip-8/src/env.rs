@@ -0,0 +1,331 @@
+//! Reinforcement learning environment.
+//!
+//! An [`Env`] runs a program frame by frame without any frontend, rewards
+//! and episode termination are read from memory with the probes declared in
+//! a small TOML file:
+//!
+//! ```toml
+//! cycles_per_frame = 10
+//! # vip, schip or xo-chip, and where the program is loaded
+//! quirks = "vip"
+//! load_address = 0x200
+//!
+//! # The reward of a step is how much the score increased
+//! [[reward]]
+//! address = 0x2F0
+//! length = 2
+//!
+//! # The episode ends when there are no lives left
+//! [[done]]
+//! address = 0x2F2
+//! when = "equals"
+//! value = 0
+//! ```
+//!
+//! Probes read big endian values of 1 to 4 bytes.
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+use crate::cpu::Cpu;
+use crate::display::BITMAP_SIZE;
+use crate::machine::MachineBuilder;
+use crate::quirks::Quirks;
+use crate::rom::RomError;
+
+/// The screen packed one bit per pixel, see [`crate::display::Display::to_bitmap`].
+pub type Observation = [u8; BITMAP_SIZE];
+
+fn default_cycles_per_frame() -> u32 {
+    10
+}
+
+fn default_load_address() -> u16 {
+    0x200
+}
+
+fn default_length() -> usize {
+    1
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EnvConfig {
+    #[serde(default = "default_cycles_per_frame")]
+    pub cycles_per_frame: u32,
+    #[serde(default, deserialize_with = "quirks_name")]
+    pub quirks: Quirks,
+    #[serde(default = "default_load_address")]
+    pub load_address: u16,
+    #[serde(default)]
+    pub reward: Vec<RewardProbe>,
+    #[serde(default)]
+    pub done: Vec<DoneProbe>,
+}
+
+/// Rewards the change of a big endian value stored at `address`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RewardProbe {
+    pub address: usize,
+    #[serde(default = "default_length")]
+    pub length: usize,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+/// Ends the episode when the big endian value at `address` meets a condition.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DoneProbe {
+    pub address: usize,
+    #[serde(default = "default_length")]
+    pub length: usize,
+    pub when: Condition,
+    pub value: u32,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    Equals,
+    NotEquals,
+    Below,
+    Above,
+}
+
+/// Quirks given by name, see [`Quirks::from_name`]
+fn quirks_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Quirks, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Quirks::from_name(&name).ok_or_else(|| {
+        D::Error::custom(format!(
+            "unknown quirks {}, use vip, schip or xo-chip",
+            name
+        ))
+    })
+}
+
+impl EnvConfig {
+    /// Parses `config`, the probes must read 1 to 4 bytes
+    pub fn from_toml(config: &str) -> Result<EnvConfig, toml::de::Error> {
+        let config: EnvConfig = toml::from_str(config)?;
+        let probes = config
+            .reward
+            .iter()
+            .map(|probe| (probe.address, probe.length));
+        let probes = probes.chain(
+            config
+                .done
+                .iter()
+                .map(|probe| (probe.address, probe.length)),
+        );
+        for (address, length) in probes {
+            if !(1..=4).contains(&length) {
+                return Err(toml::de::Error::custom(format!(
+                    "the probe at {:#X} reads {} bytes, from 1 to 4 fit a value",
+                    address, length
+                )));
+            }
+        }
+        Ok(config)
+    }
+
+    /// The machine the program runs on
+    fn machine(&self) -> MachineBuilder {
+        MachineBuilder::new()
+            .quirks(self.quirks)
+            .load_address(self.load_address)
+    }
+}
+
+impl DoneProbe {
+    fn is_met(&self, cpu: &Cpu) -> bool {
+        let value = read_value(cpu, self.address, self.length);
+        match self.when {
+            Condition::Equals => value == self.value,
+            Condition::NotEquals => value != self.value,
+            Condition::Below => value < self.value,
+            Condition::Above => value > self.value,
+        }
+    }
+}
+
+/// Reads `length` bytes at `address` as a big endian number, bytes outside
+/// memory read as zero.
+fn read_value(cpu: &Cpu, address: usize, length: usize) -> u32 {
+    (address..address.saturating_add(length)).fold(0, |value, address| {
+        value << 8 | *cpu.view().memory().get(address).unwrap_or(&0) as u32
+    })
+}
+
+pub struct Env {
+    rom: Vec<u8>,
+    config: EnvConfig,
+    cpu: Cpu,
+    scores: Vec<u32>,
+}
+
+impl Env {
+    pub fn new(rom: Vec<u8>, config: EnvConfig) -> Result<Env, RomError> {
+        let cpu = config.machine().load(&rom, StdRng::seed_from_u64(0))?;
+        let mut env = Env {
+            rom,
+            config,
+            cpu,
+            scores: Vec::new(),
+        };
+        env.update_scores();
+        Ok(env)
+    }
+
+    /// Restarts the program, `seed` makes the episode reproducible.
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.cpu = self
+            .config
+            .machine()
+            .load(&self.rom, StdRng::seed_from_u64(seed))
+            .expect("the ROM was loaded by Env::new");
+        self.update_scores();
+        self.cpu.get_display().to_bitmap()
+    }
+
+    /// Holds `action_keys` down, releasing every other key, and runs up to
+    /// `frames` frames. Stops early when the episode is done, or the program
+    /// faulted.
+    pub fn step(&mut self, action_keys: &[u8], frames: u32) -> (Observation, f64, bool) {
+        for key in 0..16 {
+            self.cpu.set_key(key, action_keys.contains(&key));
+        }
+
+        let mut done = false;
+        for _ in 0..frames {
+            for _ in 0..self.config.cycles_per_frame {
+                self.cpu.next();
+            }
+            self.cpu.decrease_timers();
+
+            done = self.cpu.fault().is_some()
+                || self.config.done.iter().any(|probe| probe.is_met(&self.cpu));
+            if done {
+                break;
+            }
+        }
+
+        let previous_scores = std::mem::take(&mut self.scores);
+        self.update_scores();
+        let reward = self
+            .config
+            .reward
+            .iter()
+            .zip(self.scores.iter().zip(previous_scores.iter()))
+            .map(|(probe, (score, previous))| probe.scale * (*score as f64 - *previous as f64))
+            .sum();
+
+        (self.cpu.get_display().to_bitmap(), reward, done)
+    }
+
+    /// Copy of the machine, it can be restored with [`Env::restore_state`].
+    pub fn clone_state(&self) -> Cpu {
+        self.cpu.clone()
+    }
+
+    pub fn restore_state(&mut self, state: Cpu) {
+        self.cpu = state;
+        self.update_scores();
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    fn update_scores(&mut self) {
+        let cpu = &self.cpu;
+        self.scores = self
+            .config
+            .reward
+            .iter()
+            .map(|probe| read_value(cpu, probe.address, probe.length))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::{Env, EnvConfig};
+    use crate::quirks::Quirks;
+
+    const CONFIG: &str = r#"
+        cycles_per_frame = 1
+
+        [[reward]]
+        address = 0x300
+
+        [[done]]
+        address = 0x300
+        when = "above"
+        value = 2
+    "#;
+
+    // V0 += 1, [0x300] = V0, loop
+    const ROM: [u8; 10] = [0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn parse_config() {
+        let config = EnvConfig::from_toml(CONFIG).unwrap();
+        assert_eq!(config.cycles_per_frame, 1);
+        assert_eq!(config.reward[0].address, 0x300);
+        assert_eq!(config.reward[0].length, 1);
+        assert_eq!(config.done[0].value, 2);
+        assert_eq!(config.quirks, Quirks::XO_CHIP);
+        assert_eq!(config.load_address, 0x200);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let long = "[[reward]]\naddress = 0x300\nlength = 5";
+        assert!(EnvConfig::from_toml(long).is_err());
+        let empty = "[[done]]\naddress = 0x300\nlength = 0\nwhen = \"equals\"\nvalue = 0";
+        assert!(EnvConfig::from_toml(empty).is_err());
+        assert!(EnvConfig::from_toml("quirks = \"octo\"").is_err());
+        assert!(EnvConfig::from_toml("[[reward]]\naddress = 0x300\nlength = 4").is_ok());
+    }
+
+    #[test]
+    fn machine() {
+        let config = EnvConfig::from_toml("quirks = \"schip\"\nload_address = 0x300").unwrap();
+        let env = Env::new(ROM.to_vec(), config).unwrap();
+        assert_eq!(env.cpu().quirks(), Quirks::SCHIP);
+        assert_eq!(env.cpu().view().program_counter(), 0x300);
+        assert_eq!(env.cpu().view().memory()[0x300], 0x70);
+    }
+
+    #[test]
+    fn reward_and_done() {
+        let mut env = Env::new(ROM.to_vec(), EnvConfig::from_toml(CONFIG).unwrap()).unwrap();
+        env.reset(0);
+
+        // One loop iteration increments the score
+        let (_, reward, done) = env.step(&[], 4);
+        assert_eq!(reward, 1.0);
+        assert!(!done);
+
+        let (_, reward, done) = env.step(&[], 100);
+        assert_eq!(reward, 2.0, "stops as soon as the score goes above 2");
+        assert!(done);
+    }
+
+    #[test]
+    fn clone_and_restore() {
+        let mut env = Env::new(ROM.to_vec(), EnvConfig::from_toml(CONFIG).unwrap()).unwrap();
+        env.step(&[], 4);
+        let state = env.clone_state();
+
+        env.step(&[5], 4);
+        env.restore_state(state);
+        assert_eq!(env.cpu().view().memory()[0x300], 1);
+        assert_eq!(env.step(&[], 4).1, 1.0);
+    }
+}
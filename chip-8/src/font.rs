@@ -0,0 +1,106 @@
+use crate::display::{DEFAULT_FONTS, DEFAULT_FONT_START_ADDRESS};
+
+/// Bytes of a font, sixteen glyphs of five rows
+pub const FONT_SIZE: usize = 80;
+/// Bytes of a single glyph
+pub const GLYPH_SIZE: u16 = 5;
+
+/// The hexadecimal digits `FX29` points I to, and where they live in memory.
+///
+/// Every glyph is 4 pixels wide, stored in the high nibble of its five rows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Font {
+    pub glyphs: [u8; FONT_SIZE],
+    pub address: u16,
+}
+
+impl Font {
+    /// COSMAC VIP
+    pub const VIP: Font = Font {
+        glyphs: [
+            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+            0x60, 0x20, 0x20, 0x20, 0x70, // 1
+            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+            0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+            0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+            0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+            0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+        ],
+        address: DEFAULT_FONT_START_ADDRESS,
+    };
+
+    /// DREAM 6800, 3 pixels wide
+    pub const DREAM_6800: Font = Font {
+        glyphs: [
+            0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+            0x40, 0x40, 0x40, 0x40, 0x40, // 1
+            0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+            0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+            0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+            0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+            0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+            0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+            0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+            0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+            0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+            0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+            0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+            0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+            0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+            0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+        ],
+        address: DEFAULT_FONT_START_ADDRESS,
+    };
+
+    /// ETI-660, 3 pixels wide
+    pub const ETI_660: Font = Font {
+        glyphs: [
+            0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+            0x20, 0x20, 0x20, 0x20, 0x20, // 1
+            0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+            0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+            0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+            0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+            0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+            0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+            0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+            0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+            0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+            0x80, 0x80, 0xE0, 0xA0, 0xE0, // B
+            0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+            0x20, 0x20, 0xE0, 0xA0, 0xE0, // D
+            0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+            0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+        ],
+        address: DEFAULT_FONT_START_ADDRESS,
+    };
+
+    /// SUPER-CHIP and Octo, the font this interpreter always used
+    pub const SCHIP: Font = Font {
+        glyphs: DEFAULT_FONTS,
+        address: DEFAULT_FONT_START_ADDRESS,
+    };
+
+    /// Same glyphs at another address.
+    pub const fn at(self, address: u16) -> Font {
+        Font {
+            glyphs: self.glyphs,
+            address,
+        }
+    }
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Font::SCHIP
+    }
+}
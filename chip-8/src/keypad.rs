@@ -1,5 +1,6 @@
 /// KeyPad
 /// |
+#[derive(Clone)]
 pub struct KeyPad {
     keypad: [bool; 16],
 }
@@ -21,8 +22,11 @@ impl KeyPad {
         pressed_key
     }
 
+    /// Keys past 0xF are ignored
     pub fn on_key(&mut self, key: u8, status: bool) {
-        self.keypad[key as usize] = status;
+        if let Some(key) = self.keypad.get_mut(key as usize) {
+            *key = status;
+        }
     }
 
     pub fn is_key_down(&self, index: usize) -> bool {
@@ -31,6 +35,7 @@ impl KeyPad {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use crate::keypad::KeyPad;
 
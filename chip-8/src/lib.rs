@@ -1,3 +1,25 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod bus;
+#[cfg(feature = "vip")]
+pub mod cdp1802;
+pub mod cheat;
 pub mod cpu;
+pub mod disasm;
 pub mod display;
+#[cfg(feature = "env")]
+pub mod env;
+pub mod font;
 mod keypad;
+pub mod machine;
+pub mod observer;
+#[cfg(feature = "std")]
+pub mod profile;
+pub mod quirks;
+pub mod rom;
+#[cfg(feature = "std")]
+pub mod symbols;
+pub mod trace;
+pub mod view;
+#[cfg(feature = "vip")]
+pub mod vip;
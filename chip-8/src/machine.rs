@@ -0,0 +1,320 @@
+//! Machines of historic platforms and test scenarios.
+//!
+//! A [`MachineBuilder`] starts from the defaults of this interpreter or from
+//! a [`Platform`] and overrides any of its settings:
+//!
+//! ```
+//! use chip_8::machine::{MachineBuilder, Platform, StackOverflow};
+//!
+//! let cpu = MachineBuilder::platform(Platform::Vip)
+//!     .memory_size(2048)
+//!     .stack_overflow(StackOverflow::Wrap)
+//!     .load(&[0x12, 0x00], rand::rngs::mock::StepRng::new(0, 1))
+//!     .unwrap();
+//! assert_eq!(cpu.view().memory().len(), 2048);
+//! ```
+
+#[cfg(feature = "std")]
+use std::io::Read;
+
+#[cfg(feature = "std")]
+use rand::rngs::StdRng;
+use rand::RngCore;
+#[cfg(feature = "std")]
+use rand::SeedableRng;
+
+use crate::cpu::{Cpu, MEMORY_SIZE, STACK_CAPACITY, STACK_DEPTH};
+use crate::font::Font;
+use crate::quirks::Quirks;
+use crate::rom::{RomError, RomLoader};
+
+/// What `2NNN` does with the call stack full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackOverflow {
+    /// The machine stops with [`crate::cpu::Fault::StackOverflow`]
+    Fault,
+    /// The call drops the oldest return address, like interpreters that
+    /// keep the stack in a ring, and returning past the remaining ones
+    /// faults
+    Wrap,
+    /// The stack depth is ignored, calls nest up to
+    /// [`crate::cpu::STACK_CAPACITY`] and fault beyond
+    Capacity,
+}
+
+/// Presets of the machines CHIP-8 ran on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    /// COSMAC VIP: 12 stack entries, the VIP font and quirks
+    Vip,
+    /// SUPER-CHIP 1.1: 16 stack entries, its font and quirks
+    Schip,
+    /// XO-CHIP as Octo runs it, limited to 4 KiB of memory
+    XoChip,
+}
+
+/// Memory size, call stack, program start, font and quirks of a new machine.
+#[derive(Clone, Copy, Debug)]
+pub struct MachineBuilder {
+    memory_size: usize,
+    stack_depth: usize,
+    stack_overflow: StackOverflow,
+    loader: RomLoader,
+    font: Font,
+    quirks: Quirks,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MachineBuilder {
+    /// The machine [`RomLoader`] builds: 4 KiB of memory, 24 stack entries
+    /// that fault when full, XO-CHIP quirks
+    pub fn new() -> MachineBuilder {
+        MachineBuilder {
+            memory_size: MEMORY_SIZE,
+            stack_depth: STACK_DEPTH as usize,
+            stack_overflow: StackOverflow::Fault,
+            loader: RomLoader::new(),
+            font: Font::default(),
+            quirks: Quirks::default(),
+        }
+    }
+
+    pub fn platform(platform: Platform) -> MachineBuilder {
+        let builder = MachineBuilder::new();
+        match platform {
+            Platform::Vip => builder.stack_depth(12).font(Font::VIP).quirks(Quirks::VIP),
+            Platform::Schip => builder
+                .stack_depth(16)
+                .font(Font::SCHIP)
+                .quirks(Quirks::SCHIP),
+            Platform::XoChip => builder
+                .stack_depth(16)
+                .font(Font::SCHIP)
+                .quirks(Quirks::XO_CHIP),
+        }
+    }
+
+    /// Bytes of RAM, at most [`MEMORY_SIZE`]. The bus can serve the
+    /// addresses past it, see [`crate::bus::Bus::decodes`]
+    pub fn memory_size(mut self, size: usize) -> MachineBuilder {
+        self.memory_size = size;
+        self
+    }
+
+    /// Nested calls before the stack is full, from 1 to [`STACK_CAPACITY`]
+    pub fn stack_depth(mut self, depth: usize) -> MachineBuilder {
+        self.stack_depth = depth;
+        self
+    }
+
+    pub fn stack_overflow(mut self, policy: StackOverflow) -> MachineBuilder {
+        self.stack_overflow = policy;
+        self
+    }
+
+    /// Address the ROM is copied to, 0x200 by default.
+    pub fn load_address(mut self, address: u16) -> MachineBuilder {
+        self.loader = self.loader.load_address(address);
+        self
+    }
+
+    /// First instruction executed, the load address by default.
+    pub fn entry_point(mut self, address: u16) -> MachineBuilder {
+        self.loader = self.loader.entry_point(address);
+        self
+    }
+
+    pub fn font(mut self, font: Font) -> MachineBuilder {
+        self.font = font;
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> MachineBuilder {
+        self.quirks = quirks;
+        self
+    }
+
+    pub fn load<R: RngCore>(&self, rom: &[u8], rng: R) -> Result<Cpu<R>, RomError> {
+        self.loader
+            .font(self.font)
+            .load_into(self.machine(rng)?, rom)
+    }
+
+    /// Like [`RomLoader::read`], on this machine
+    #[cfg(feature = "std")]
+    pub fn read<Reader: Read>(&self, reader: Reader) -> std::io::Result<Cpu> {
+        let cpu = self.machine(StdRng::from_entropy())?;
+        self.loader.font(self.font).read_into(cpu, reader)
+    }
+
+    /// The configured machine with empty memory
+    fn machine<R: RngCore>(&self, rng: R) -> Result<Cpu<R>, RomError> {
+        if self.memory_size > MEMORY_SIZE {
+            return Err(RomError::MemorySize(self.memory_size));
+        }
+        if !(1..=STACK_CAPACITY).contains(&self.stack_depth) {
+            return Err(RomError::StackDepth(self.stack_depth));
+        }
+
+        let mut cpu = Cpu::blank(rng);
+        cpu.configure(
+            self.memory_size,
+            self.stack_depth as u8,
+            self.stack_overflow,
+        );
+        cpu.set_quirks(self.quirks);
+        Ok(cpu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use crate::bus::{Bus, DirectBus};
+    use crate::cpu::{Fault, MEMORY_SIZE, STACK_CAPACITY};
+    use crate::font::Font;
+    use crate::machine::{MachineBuilder, Platform, StackOverflow};
+    use crate::quirks::Quirks;
+    use crate::rom::RomError;
+
+    /// Calls itself forever
+    const RECURSION: [u8; 2] = [0x22, 0x00];
+
+    /// Decodes the whole address space, `8000` (V0 = V0) past the RAM
+    struct Open;
+
+    impl Bus for Open {
+        fn decodes(&self, _address: u16) -> bool {
+            true
+        }
+
+        fn read(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+            match memory.get(address as usize) {
+                Some(byte) => *byte,
+                None if address.is_multiple_of(2) => 0x80,
+                None => 0x00,
+            }
+        }
+
+        fn write(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) -> bool {
+            (address as usize) < MEMORY_SIZE && DirectBus.write(memory, address, value)
+        }
+    }
+
+    #[test]
+    fn presets() {
+        let cpu = MachineBuilder::platform(Platform::Vip)
+            .load(&RECURSION, StepRng::new(0, 1))
+            .unwrap();
+        assert_eq!(cpu.quirks(), Quirks::VIP);
+        assert_eq!(&cpu.view().memory()[0x50..0x55], &Font::VIP.glyphs[..5]);
+
+        let mut cpu = MachineBuilder::platform(Platform::Schip)
+            .load(&RECURSION, StepRng::new(0, 1))
+            .unwrap();
+        for _ in 0..17 {
+            cpu.next();
+        }
+        assert_eq!(cpu.view().call_stack().len(), 16);
+        assert_eq!(cpu.fault(), Some(Fault::StackOverflow));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        // Calls from 200, 204 and 208, then returns three times
+        let rom = [
+            0x22, 0x04, 0x00, 0x00, 0x22, 0x08, 0x00, 0xEE, 0x22, 0x0C, 0x00, 0xEE, 0x00, 0xEE,
+        ];
+        let mut cpu = MachineBuilder::new()
+            .stack_depth(2)
+            .stack_overflow(StackOverflow::Wrap)
+            .load(&rom, StepRng::new(0, 1))
+            .unwrap();
+        for _ in 0..3 {
+            cpu.next();
+        }
+        assert_eq!(cpu.fault(), None);
+        assert_eq!(cpu.view().call_stack(), &[0x206, 0x20A]);
+        cpu.next();
+        cpu.next();
+        assert_eq!(cpu.fault(), None);
+        assert_eq!(cpu.view().program_counter(), 0x206);
+        cpu.next();
+        assert_eq!(cpu.fault(), Some(Fault::StackUnderflow));
+
+        let mut cpu = MachineBuilder::new()
+            .stack_depth(2)
+            .stack_overflow(StackOverflow::Capacity)
+            .load(&RECURSION, StepRng::new(0, 1))
+            .unwrap();
+        for _ in 0..STACK_CAPACITY {
+            cpu.next();
+        }
+        assert_eq!(cpu.fault(), None);
+        cpu.next();
+        assert_eq!(cpu.fault(), Some(Fault::StackOverflow));
+    }
+
+    #[test]
+    fn memory_size() {
+        let mut cpu = MachineBuilder::new()
+            .memory_size(0x800)
+            .load(&[0xA7, 0xFF, 0xF0, 0x55], StepRng::new(0, 1))
+            .unwrap();
+        cpu.next();
+        cpu.next();
+        assert_eq!(cpu.fault(), None);
+        cpu.poke().set_index_register(0x800);
+        cpu.poke().set_program_counter(0x202);
+        cpu.next();
+        assert_eq!(cpu.fault(), Some(Fault::InvalidAddress(0x800)));
+
+        let error = MachineBuilder::new()
+            .memory_size(0x200)
+            .load(&RECURSION, StepRng::new(0, 1))
+            .err();
+        assert_eq!(error, Some(RomError::InvalidAddress(0x200)));
+        let error = MachineBuilder::new()
+            .memory_size(MEMORY_SIZE + 1)
+            .load(&RECURSION, StepRng::new(0, 1))
+            .err();
+        assert_eq!(error, Some(RomError::MemorySize(MEMORY_SIZE + 1)));
+        let error = MachineBuilder::new()
+            .stack_depth(0)
+            .stack_overflow(StackOverflow::Wrap)
+            .load(&RECURSION, StepRng::new(0, 1))
+            .err();
+        assert_eq!(error, Some(RomError::StackDepth(0)));
+        let error = MachineBuilder::new()
+            .stack_depth(STACK_CAPACITY + 1)
+            .load(&RECURSION, StepRng::new(0, 1))
+            .err();
+        assert_eq!(error, Some(RomError::StackDepth(STACK_CAPACITY + 1)));
+    }
+
+    #[test]
+    fn end_of_address_space() {
+        // V0 = 0xFF, jump to 0xFFF + V0, then V0 = V0 up to the last address
+        let mut cpu = MachineBuilder::new()
+            .load(&[0x60, 0xFF, 0xBF, 0xFF], StepRng::new(0, 1))
+            .unwrap()
+            .with_bus(Open);
+        cpu.next();
+        cpu.next();
+        cpu.next();
+        assert_eq!(cpu.view().program_counter(), 0x1100);
+
+        cpu.poke().set_program_counter(0xFFFC);
+        cpu.next();
+        assert_eq!(cpu.fault(), None);
+        cpu.next();
+        assert_eq!(cpu.fault(), Some(Fault::InvalidAddress(0xFFFE)));
+        assert_eq!(cpu.view().program_counter(), 0xFFFE);
+    }
+}
@@ -0,0 +1,271 @@
+//! Hooks into the execution of the interpreter.
+//!
+//! The [`Observer`] of a machine hears about every instruction, memory
+//! write, drawing, sound and timer tick, so profilers, achievement checkers
+//! and recorders don't need their own copy of the interpreter. Attach one
+//! with [`crate::cpu::Cpu::with_observer`]. Every method does nothing by
+//! default, and the default [`NoObserver`] compiles away entirely.
+//!
+//! An `Option` of an observer listens only when it is `Some`, and an
+//! `Rc<RefCell<_>>` one is shared by the copies of a machine, such as save
+//! states.
+
+#[cfg(feature = "std")]
+use std::cell::RefCell;
+#[cfg(feature = "std")]
+use std::rc::Rc;
+
+/// Events of a running machine, all of them come after the change happened.
+pub trait Observer {
+    /// The instruction at `address` ran without a fault
+    fn instruction(&mut self, _address: u16, _opcode: u16) {}
+
+    /// The program wrote `value` to `address` with `FX33` or `FX55`, and the
+    /// bus stored it
+    fn memory_written(&mut self, _address: u16, _value: u8) {}
+
+    /// `00E0`
+    fn screen_cleared(&mut self) {}
+
+    /// `DXYN` drew `height` rows at `(x, y)`, `collision` when it erased a
+    /// pixel
+    fn sprite_drawn(&mut self, _x: u8, _y: u8, _height: u8, _collision: bool) {}
+
+    /// The sound timer went from zero to a value
+    fn sound_started(&mut self) {}
+
+    /// The sound timer reached zero
+    fn sound_stopped(&mut self) {}
+
+    /// `FX0A` started waiting for a key to store in `VX`
+    fn key_wait(&mut self, _register: u8) {}
+
+    /// The timers ticked, these are their new values
+    fn timers_ticked(&mut self, _delay: u8, _sound: u8) {}
+}
+
+/// Listens to nothing, the default observer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoObserver;
+
+impl Observer for NoObserver {}
+
+impl<O: Observer> Observer for Option<O> {
+    fn instruction(&mut self, address: u16, opcode: u16) {
+        if let Some(observer) = self {
+            observer.instruction(address, opcode);
+        }
+    }
+
+    fn memory_written(&mut self, address: u16, value: u8) {
+        if let Some(observer) = self {
+            observer.memory_written(address, value);
+        }
+    }
+
+    fn screen_cleared(&mut self) {
+        if let Some(observer) = self {
+            observer.screen_cleared();
+        }
+    }
+
+    fn sprite_drawn(&mut self, x: u8, y: u8, height: u8, collision: bool) {
+        if let Some(observer) = self {
+            observer.sprite_drawn(x, y, height, collision);
+        }
+    }
+
+    fn sound_started(&mut self) {
+        if let Some(observer) = self {
+            observer.sound_started();
+        }
+    }
+
+    fn sound_stopped(&mut self) {
+        if let Some(observer) = self {
+            observer.sound_stopped();
+        }
+    }
+
+    fn key_wait(&mut self, register: u8) {
+        if let Some(observer) = self {
+            observer.key_wait(register);
+        }
+    }
+
+    fn timers_ticked(&mut self, delay: u8, sound: u8) {
+        if let Some(observer) = self {
+            observer.timers_ticked(delay, sound);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<O: Observer> Observer for Rc<RefCell<O>> {
+    fn instruction(&mut self, address: u16, opcode: u16) {
+        self.borrow_mut().instruction(address, opcode);
+    }
+
+    fn memory_written(&mut self, address: u16, value: u8) {
+        self.borrow_mut().memory_written(address, value);
+    }
+
+    fn screen_cleared(&mut self) {
+        self.borrow_mut().screen_cleared();
+    }
+
+    fn sprite_drawn(&mut self, x: u8, y: u8, height: u8, collision: bool) {
+        self.borrow_mut().sprite_drawn(x, y, height, collision);
+    }
+
+    fn sound_started(&mut self) {
+        self.borrow_mut().sound_started();
+    }
+
+    fn sound_stopped(&mut self) {
+        self.borrow_mut().sound_stopped();
+    }
+
+    fn key_wait(&mut self, register: u8) {
+        self.borrow_mut().key_wait(register);
+    }
+
+    fn timers_ticked(&mut self, delay: u8, sound: u8) {
+        self.borrow_mut().timers_ticked(delay, sound);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use rand::rngs::mock::StepRng;
+
+    use crate::bus::{DirectBus, ReadOnly};
+    use crate::cpu::Cpu;
+    use crate::observer::Observer;
+
+    /// Writes down every event
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl Observer for Recorder {
+        fn instruction(&mut self, address: u16, opcode: u16) {
+            self.events.push(format!("{:03X} {:04X}", address, opcode));
+        }
+
+        fn memory_written(&mut self, address: u16, value: u8) {
+            self.events.push(format!("write {:03X} {}", address, value));
+        }
+
+        fn screen_cleared(&mut self) {
+            self.events.push(String::from("clear"));
+        }
+
+        fn sprite_drawn(&mut self, x: u8, y: u8, height: u8, collision: bool) {
+            self.events
+                .push(format!("draw {} {} {} {}", x, y, height, collision));
+        }
+
+        fn sound_started(&mut self) {
+            self.events.push(String::from("sound on"));
+        }
+
+        fn sound_stopped(&mut self) {
+            self.events.push(String::from("sound off"));
+        }
+
+        fn key_wait(&mut self, register: u8) {
+            self.events.push(format!("wait V{:X}", register));
+        }
+
+        fn timers_ticked(&mut self, delay: u8, sound: u8) {
+            self.events.push(format!("tick {} {}", delay, sound));
+        }
+    }
+
+    #[test]
+    fn events() {
+        // CLS, V0 = 1, I = 0x300, store V0, sound timer = V0, I = 0x300,
+        // draw at V0, V0 twice, wait for a key
+        let rom = [
+            0x00, 0xE0, 0x60, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0xF0, 0x18, 0xA3, 0x00, 0xD0, 0x01,
+            0xD0, 0x01, 0xF1, 0x0A,
+        ];
+        let mut cpu = Cpu::from_bytes(&rom, StepRng::new(0, 1))
+            .unwrap()
+            .with_observer(Recorder::default());
+        for _ in 0..10 {
+            cpu.next();
+        }
+        cpu.decrease_timers();
+
+        assert_eq!(
+            cpu.observer().events,
+            [
+                "clear",
+                "200 00E0",
+                "202 6001",
+                "204 A300",
+                "write 300 1",
+                "206 F055",
+                "sound on",
+                "208 F018",
+                "20A A300",
+                "draw 1 1 1 false",
+                "20C D001",
+                "draw 1 1 1 true",
+                "20E D001",
+                "wait V1",
+                "210 F10A",
+                "210 F10A",
+                "sound off",
+                "tick 0 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn dropped_writes() {
+        // V0 = 7, I = 0x1FF, V0..V1 to [I]
+        let rom = [0x60, 0x07, 0xA1, 0xFF, 0xF1, 0x55];
+        let mut cpu = Cpu::from_bytes(&rom, StepRng::new(0, 1))
+            .unwrap()
+            .with_bus(ReadOnly::new(0..0x200, DirectBus))
+            .with_observer(Recorder::default());
+        for _ in 0..3 {
+            cpu.next();
+        }
+
+        let writes: Vec<&String> = cpu
+            .observer()
+            .events
+            .iter()
+            .filter(|event| event.starts_with("write"))
+            .collect();
+        assert_eq!(writes, ["write 200 0"]);
+    }
+
+    #[test]
+    fn shared() {
+        // V0 = 1, loop
+        let rom = [0x60, 0x01, 0x12, 0x02];
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        let mut cpu = Cpu::from_bytes(&rom, StepRng::new(0, 1))
+            .unwrap()
+            .with_observer(Some(Rc::clone(&recorder)));
+        cpu.next();
+        let mut copy = cpu.clone();
+        copy.next();
+        assert_eq!(recorder.borrow().events, ["200 6001", "202 1202"]);
+
+        let mut cpu = Cpu::from_bytes(&rom, StepRng::new(0, 1))
+            .unwrap()
+            .with_observer(None::<Recorder>);
+        cpu.next();
+        assert!(cpu.observer().is_none());
+    }
+}
@@ -0,0 +1,391 @@
+//! Execution profiler.
+//!
+//! A [`Profiler`] is an [`Observer`], attach it to the machine with
+//! [`crate::cpu::Cpu::with_observer`]. A frame ends when the timers tick.
+//! Subroutines are measured from the `2NNN` that enters them to the `00EE`
+//! that leaves them, and the call stacks can be written in the collapsed
+//! format read by flamegraph tools:
+//!
+//! ```text
+//! main;sub_2A0;sub_3C4 1520
+//! ```
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::disasm::Instruction;
+use crate::observer::Observer;
+use crate::symbols::Symbols;
+
+/// Addresses listed in the report
+const HOT_ADDRESSES: usize = 20;
+
+/// Every address a bus can decode
+const ADDRESS_SPACE: usize = 1 << 16;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub instructions: u64,
+    pub draws: u32,
+    pub collisions: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Subroutine {
+    pub address: u16,
+    pub calls: u64,
+    /// Instructions executed inside the subroutine and the ones it calls
+    pub inclusive: u64,
+    /// Instructions executed by the subroutine itself
+    pub exclusive: u64,
+}
+
+pub struct Profiler {
+    /// Executions of every address
+    addresses: Vec<u64>,
+    /// Last opcode executed at every address
+    opcodes: Vec<u16>,
+    patterns: HashMap<&'static str, u64>,
+    /// Subroutines entered and not left yet, innermost last
+    call_stack: Vec<u16>,
+    /// Instructions executed with every call stack
+    stacks: HashMap<Vec<u16>, u64>,
+    calls: HashMap<u16, u64>,
+    frame: FrameStats,
+    frames: Vec<FrameStats>,
+    symbols: Symbols,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            addresses: vec![0; ADDRESS_SPACE],
+            opcodes: vec![0; ADDRESS_SPACE],
+            patterns: HashMap::new(),
+            call_stack: Vec::new(),
+            stacks: HashMap::new(),
+            calls: HashMap::new(),
+            frame: FrameStats::default(),
+            frames: Vec::new(),
+            symbols: Symbols::new(),
+        }
+    }
+
+    /// Names addresses and subroutines after the labels of `symbols` in the
+    /// report and the call stacks
+    pub fn symbols(mut self, symbols: Symbols) -> Profiler {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Statistics of every finished frame
+    pub fn frames(&self) -> &[FrameStats] {
+        &self.frames
+    }
+
+    /// Executed addresses and how many times, the hottest first
+    pub fn hot_addresses(&self) -> Vec<(u16, u64)> {
+        let mut addresses: Vec<(u16, u64)> = self
+            .addresses
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(address, count)| (address as u16, *count))
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses
+    }
+
+    /// Executions of every opcode pattern, such as `8XY4`, the most common
+    /// first
+    pub fn patterns(&self) -> Vec<(&'static str, u64)> {
+        let mut patterns: Vec<(&'static str, u64)> = self
+            .patterns
+            .iter()
+            .map(|(pattern, count)| (*pattern, *count))
+            .collect();
+        patterns.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        patterns
+    }
+
+    /// Every called subroutine, the most expensive first
+    pub fn subroutines(&self) -> Vec<Subroutine> {
+        let mut subroutines: Vec<Subroutine> = self
+            .calls
+            .iter()
+            .map(|(address, calls)| Subroutine {
+                address: *address,
+                calls: *calls,
+                inclusive: 0,
+                exclusive: 0,
+            })
+            .collect();
+        for subroutine in subroutines.iter_mut() {
+            for (stack, count) in self.stacks.iter() {
+                if stack.contains(&subroutine.address) {
+                    subroutine.inclusive += count;
+                }
+                if stack.last() == Some(&subroutine.address) {
+                    subroutine.exclusive += count;
+                }
+            }
+        }
+        subroutines.sort_by(|a, b| {
+            b.inclusive
+                .cmp(&a.inclusive)
+                .then(a.address.cmp(&b.address))
+        });
+        subroutines
+    }
+
+    pub fn write_report<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let total: u64 = self.addresses.iter().sum();
+        let percent = |count: u64| count as f64 * 100.0 / total.max(1) as f64;
+        writeln!(
+            writer,
+            "{} instructions in {} frames",
+            total,
+            self.frames.len()
+        )?;
+
+        writeln!(writer, "\nHot addresses")?;
+        for (address, count) in self.hot_addresses().into_iter().take(HOT_ADDRESSES) {
+            writeln!(
+                writer,
+                "  {:03X}  {:>12}  {:5.1}%  {}{}",
+                address,
+                count,
+                percent(count),
+                self.symbols
+                    .instruction(Instruction::decode(self.opcodes[address as usize])),
+                self.symbols
+                    .locate(address)
+                    .map_or(String::new(), |location| format!("  ; {}", location))
+            )?;
+        }
+
+        writeln!(writer, "\nOpcodes")?;
+        for (pattern, count) in self.patterns() {
+            writeln!(
+                writer,
+                "  {:<7}  {:>12}  {:5.1}%",
+                pattern,
+                count,
+                percent(count)
+            )?;
+        }
+
+        writeln!(writer, "\nSubroutines")?;
+        writeln!(
+            writer,
+            "  {:3}  {:>12}  {:>12}  {:>12}",
+            "", "calls", "inclusive", "exclusive"
+        )?;
+        for subroutine in self.subroutines() {
+            writeln!(
+                writer,
+                "  {:03X}  {:>12}  {:>12}  {:>12}  {}",
+                subroutine.address,
+                subroutine.calls,
+                subroutine.inclusive,
+                subroutine.exclusive,
+                self.symbols.label(subroutine.address).unwrap_or_default()
+            )?;
+        }
+
+        let frames = self.frames.len().max(1) as f64;
+        let draws = self.frames.iter().map(|frame| frame.draws);
+        let collisions = self.frames.iter().map(|frame| frame.collisions);
+        writeln!(writer, "\nPer frame    average   maximum")?;
+        writeln!(
+            writer,
+            "  draws      {:>7.1}   {:>7}",
+            draws.clone().sum::<u32>() as f64 / frames,
+            draws.max().unwrap_or(0)
+        )?;
+        writeln!(
+            writer,
+            "  collisions {:>7.1}   {:>7}",
+            collisions.clone().sum::<u32>() as f64 / frames,
+            collisions.max().unwrap_or(0)
+        )
+    }
+
+    /// Writes the call stacks in the collapsed format of flamegraph tools,
+    /// the program is `main` and subroutines are named after their label or
+    /// their address.
+    pub fn write_collapsed<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let mut name = String::from("main");
+                for address in stack {
+                    match self.symbols.label(*address) {
+                        Some(label) => name.push_str(&format!(";{}", label)),
+                        None => name.push_str(&format!(";sub_{:03X}", address)),
+                    }
+                }
+                (name, *count)
+            })
+            .collect();
+        stacks.sort();
+        for (stack, count) in stacks {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+}
+
+impl Observer for Profiler {
+    fn instruction(&mut self, address: u16, opcode: u16) {
+        let instruction = Instruction::decode(opcode);
+        self.addresses[address as usize] += 1;
+        self.opcodes[address as usize] = opcode;
+        *self.patterns.entry(instruction.pattern()).or_insert(0) += 1;
+        match self.stacks.get_mut(&self.call_stack[..]) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.call_stack.clone(), 1);
+            }
+        }
+        self.frame.instructions += 1;
+
+        match instruction {
+            Instruction::Call(address) => {
+                self.call_stack.push(address);
+                *self.calls.entry(address).or_insert(0) += 1;
+            }
+            Instruction::Ret => {
+                self.call_stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn sprite_drawn(&mut self, _x: u8, _y: u8, _height: u8, collision: bool) {
+        self.frame.draws += 1;
+        if collision {
+            self.frame.collisions += 1;
+        }
+    }
+
+    fn timers_ticked(&mut self, _delay: u8, _sound: u8) {
+        self.frames.push(std::mem::take(&mut self.frame));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use rand::rngs::mock::StepRng;
+
+    use crate::bus::{Bus, DirectBus};
+    use crate::cpu::{Cpu, MEMORY_SIZE};
+    use crate::profile::{FrameStats, Profiler, Subroutine};
+    use crate::trace::TraceEntry;
+
+    // I = glyph 0, call 0x208 twice and loop, 0x208 draws a sprite twice
+    // and returns
+    const ROM: [u8; 14] = [
+        0xA0, 0x50, 0x22, 0x08, 0x22, 0x08, 0x12, 0x06, 0xD0, 0x01, 0xD0, 0x01, 0x00, 0xEE,
+    ];
+
+    #[test]
+    fn counts() {
+        let mut cpu = Cpu::new(Cursor::new(ROM))
+            .unwrap()
+            .with_observer(Profiler::new());
+        for _ in 0..10 {
+            cpu.next();
+        }
+        cpu.decrease_timers();
+        let profiler = cpu.observer();
+
+        assert_eq!(profiler.hot_addresses()[0], (0x208, 2));
+        assert_eq!(profiler.patterns()[0], ("DXYN", 4));
+        assert_eq!(
+            profiler.subroutines(),
+            vec![Subroutine {
+                address: 0x208,
+                calls: 2,
+                inclusive: 6,
+                exclusive: 6,
+            }]
+        );
+        assert_eq!(
+            profiler.frames(),
+            &[FrameStats {
+                instructions: 10,
+                draws: 4,
+                collisions: 2,
+            }]
+        );
+
+        let mut collapsed = Vec::new();
+        profiler.write_collapsed(&mut collapsed).unwrap();
+        assert_eq!(
+            String::from_utf8(collapsed).unwrap(),
+            "main 4\nmain;sub_208 6\n"
+        );
+
+        let mut cpu = Cpu::new(Cursor::new(ROM))
+            .unwrap()
+            .with_observer(Profiler::new().symbols("0x208 draw".parse().unwrap()));
+        for _ in 0..10 {
+            cpu.next();
+        }
+        let mut collapsed = Vec::new();
+        cpu.observer().write_collapsed(&mut collapsed).unwrap();
+        assert_eq!(
+            String::from_utf8(collapsed).unwrap(),
+            "main 4\nmain;draw 6\n"
+        );
+    }
+
+    /// Serves `BFFF` (jump to 0xFFF + V0) at 0x1000, past the RAM
+    struct Loop;
+
+    impl Bus for Loop {
+        fn decodes(&self, address: u16) -> bool {
+            (0x1000..0x1002).contains(&address)
+        }
+
+        fn read(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+            self.peek(memory, address)
+        }
+
+        fn peek(&self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+            match address {
+                0x1000 => 0xBF,
+                0x1001 => 0xFF,
+                _ => DirectBus.peek(memory, address),
+            }
+        }
+    }
+
+    #[test]
+    fn past_memory() {
+        // V0 = 1, jump to 0x1000 and keep jumping there
+        let mut cpu = Cpu::from_bytes(&[0x60, 0x01, 0xBF, 0xFF], StepRng::new(0, 1))
+            .unwrap()
+            .with_bus(Loop)
+            .with_observer(Profiler::new());
+        for _ in 0..4 {
+            cpu.next();
+        }
+        let profiler = cpu.observer();
+
+        assert_eq!(cpu.fault(), None);
+        assert_eq!(TraceEntry::capture(cpu.view()).opcode, 0xBFFF);
+        assert_eq!(profiler.hot_addresses()[0], (0x1000, 2));
+        assert_eq!(profiler.patterns()[0], ("BNNN", 3));
+    }
+}
@@ -0,0 +1,73 @@
+/// Behaviours that differ between CHIP-8 implementations, named after the
+/// quirks of the community chip-8-database.
+///
+/// The default matches XO-CHIP (and Octo), which is what this interpreter
+/// always did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6` and `8XYE` shift VX in place instead of storing VY shifted in VX
+    pub shift: bool,
+    /// `FX55` and `FX65` increment I by X instead of X + 1
+    pub memory_increment_by_x: bool,
+    /// `FX55` and `FX65` leave I unchanged
+    pub memory_leave_i_unchanged: bool,
+    /// Sprites wrap around the edges of the screen instead of being clipped
+    pub wrap: bool,
+    /// `BNNN` jumps to XNN + VX instead of NNN + V0
+    pub jump: bool,
+    /// `DXYN` waits for the end of the frame, at most one sprite per frame
+    pub vblank: bool,
+    /// `8XY1`, `8XY2` and `8XY3` reset VF to 0
+    pub logic: bool,
+}
+
+impl Quirks {
+    /// COSMAC VIP interpreter
+    pub const VIP: Quirks = Quirks {
+        shift: false,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: false,
+        wrap: false,
+        jump: false,
+        vblank: true,
+        logic: true,
+    };
+
+    /// SUPER-CHIP 1.1
+    pub const SCHIP: Quirks = Quirks {
+        shift: true,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: true,
+        wrap: false,
+        jump: true,
+        vblank: false,
+        logic: false,
+    };
+
+    /// XO-CHIP
+    pub const XO_CHIP: Quirks = Quirks {
+        shift: false,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: false,
+        wrap: true,
+        jump: false,
+        vblank: false,
+        logic: false,
+    };
+
+    /// The settings called `vip`, `schip` or `xo-chip`
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::VIP),
+            "schip" => Some(Quirks::SCHIP),
+            "xo-chip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::XO_CHIP
+    }
+}
@@ -0,0 +1,237 @@
+use core::fmt;
+
+#[cfg(feature = "std")]
+use std::io::Read;
+
+#[cfg(feature = "std")]
+use rand::rngs::StdRng;
+use rand::RngCore;
+#[cfg(feature = "std")]
+use rand::SeedableRng;
+
+use crate::cpu::{Cpu, MEMORY_SIZE, STACK_CAPACITY, START_ADDRESS};
+use crate::font::Font;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomError {
+    /// The ROM doesn't fit between the load address and the end of memory
+    TooLarge { size: usize, available: usize },
+    /// The load address or the entry point are outside memory
+    InvalidAddress(u16),
+    /// A machine with more memory than [`crate::cpu::MEMORY_SIZE`]
+    MemorySize(usize),
+    /// A call stack deeper than [`crate::cpu::STACK_CAPACITY`]
+    StackDepth(usize),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::TooLarge { size, available } => write!(
+                f,
+                "the ROM is {} bytes but only {} bytes are available",
+                size, available
+            ),
+            RomError::InvalidAddress(address) => {
+                write!(f, "address {:#05X} is outside memory", address)
+            }
+            RomError::MemorySize(size) => write!(
+                f,
+                "{} bytes of memory, at most {} are supported",
+                size, MEMORY_SIZE
+            ),
+            RomError::StackDepth(depth) => write!(
+                f,
+                "a call stack of {} entries, at most {} are supported",
+                depth, STACK_CAPACITY
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RomError {}
+
+#[cfg(feature = "std")]
+impl From<RomError> for std::io::Error {
+    fn from(error: RomError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Loads programs into a new [`Cpu`].
+///
+/// ```
+/// use chip_8::rom::RomLoader;
+///
+/// // ETI-660 programs start at 0x600
+/// let cpu = RomLoader::new()
+///     .load_address(0x600)
+///     .load(&[0x12, 0x00], rand::rngs::mock::StepRng::new(0, 1))
+///     .unwrap();
+/// assert_eq!(cpu.rom_size(), 2);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RomLoader {
+    load_address: u16,
+    entry_point: Option<u16>,
+    font: Font,
+}
+
+impl Default for RomLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RomLoader {
+    pub fn new() -> RomLoader {
+        RomLoader {
+            load_address: START_ADDRESS,
+            entry_point: None,
+            font: Font::default(),
+        }
+    }
+
+    /// Address the ROM is copied to, 0x200 by default.
+    pub fn load_address(mut self, address: u16) -> RomLoader {
+        self.load_address = address;
+        self
+    }
+
+    /// First instruction executed, the load address by default.
+    pub fn entry_point(mut self, address: u16) -> RomLoader {
+        self.entry_point = Some(address);
+        self
+    }
+
+    /// Font copied to memory before the ROM, [`Font::SCHIP`] by default.
+    pub fn font(mut self, font: Font) -> RomLoader {
+        self.font = font;
+        self
+    }
+
+    pub fn load<R: RngCore>(&self, rom: &[u8], rng: R) -> Result<Cpu<R>, RomError> {
+        self.load_into(Cpu::blank(rng), rom)
+    }
+
+    /// Loads the font and `rom` into the memory of `cpu`
+    pub(crate) fn load_into<R: RngCore>(
+        &self,
+        mut cpu: Cpu<R>,
+        rom: &[u8],
+    ) -> Result<Cpu<R>, RomError> {
+        cpu.load_font(&self.font)?;
+        cpu.load_program(
+            rom,
+            self.load_address,
+            self.entry_point.unwrap_or(self.load_address),
+        )?;
+        Ok(cpu)
+    }
+
+    /// Reads `reader` to the end and loads it, the random number generator
+    /// is seeded from the operating system.
+    #[cfg(feature = "std")]
+    pub fn read<Reader: Read>(&self, reader: Reader) -> std::io::Result<Cpu> {
+        self.read_into(Cpu::blank(StdRng::from_entropy()), reader)
+    }
+
+    /// Reads `reader` to the end and loads it into `cpu`
+    #[cfg(feature = "std")]
+    pub(crate) fn read_into<Reader: Read>(
+        &self,
+        cpu: Cpu,
+        mut reader: Reader,
+    ) -> std::io::Result<Cpu> {
+        let mut rom = Vec::new();
+        reader.read_to_end(&mut rom)?;
+        Ok(self.load_into(cpu, &rom)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use rand::rngs::mock::StepRng;
+
+    use crate::cpu::{MEMORY_SIZE, START_ADDRESS};
+    use crate::font::Font;
+    use crate::rom::{RomError, RomLoader};
+
+    /// Returns a single byte on every read
+    struct SlowReader<'a>(&'a [u8]);
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn reads_everything() {
+        let cpu = RomLoader::new()
+            .read(SlowReader(&[0x12, 0x34, 0x56]))
+            .unwrap();
+        assert_eq!(cpu.rom_size(), 3);
+        assert_eq!(&cpu.view().memory()[0x200..0x203], &[0x12, 0x34, 0x56]);
+    }
+
+    #[test]
+    fn rejects_large_roms() {
+        let available = MEMORY_SIZE - START_ADDRESS as usize;
+        let rom = vec![0; available + 1];
+
+        let error = RomLoader::new().load(&rom, StepRng::new(0, 1)).err();
+        assert_eq!(
+            error,
+            Some(RomError::TooLarge {
+                size: available + 1,
+                available
+            })
+        );
+
+        assert!(RomLoader::new().load(&rom[1..], StepRng::new(0, 1)).is_ok());
+    }
+
+    #[test]
+    fn load_address_and_entry_point() {
+        let cpu = RomLoader::new()
+            .load_address(0x600)
+            .load(&[0xAB], StepRng::new(0, 1))
+            .unwrap();
+        assert_eq!(cpu.view().memory()[0x600], 0xAB);
+        assert_eq!(cpu.view().program_counter(), 0x600);
+
+        let cpu = RomLoader::new()
+            .entry_point(0x300)
+            .load(&[0xAB], StepRng::new(0, 1))
+            .unwrap();
+        assert_eq!(cpu.view().memory()[0x200], 0xAB);
+        assert_eq!(cpu.view().program_counter(), 0x300);
+    }
+
+    #[test]
+    fn rejects_addresses_outside_memory() {
+        let error = RomLoader::new()
+            .entry_point(0x1000)
+            .load(&[], StepRng::new(0, 1))
+            .err();
+        assert_eq!(error, Some(RomError::InvalidAddress(0x1000)));
+    }
+
+    #[test]
+    fn rejects_fonts_outside_memory() {
+        let error = RomLoader::new()
+            .font(Font::default().at(0xFC0))
+            .load(&[], StepRng::new(0, 1))
+            .err();
+        assert_eq!(error, Some(RomError::InvalidAddress(0xFC0)));
+    }
+}
@@ -0,0 +1,276 @@
+//! Symbol files: labels of code addresses and named data regions.
+//!
+//! Every line names one address or one region, in either order, so the
+//! `label address` listings of Octo can be read as they are:
+//!
+//! ```text
+//! # comments start with #
+//! 0x2F4 draw_player
+//! main 0x200
+//! sprites = 0x300
+//! 0x300-0x340 sprite_data    # data, the end excluded
+//! ```
+//!
+//! Addresses are hexadecimal, with or without `0x` or `$`.
+//!
+//! ```
+//! use chip_8::symbols::Symbols;
+//!
+//! let symbols: Symbols = "0x2F0 draw_player".parse().unwrap();
+//! assert_eq!(symbols.describe(0x2F4), "draw_player+0x4");
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::disasm::Instruction;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+    /// Named regions that hold data rather than code, by start address
+    data: BTreeMap<u16, (u16, String)>,
+}
+
+/// An address as a label and the distance from it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub label: &'a str,
+    pub offset: u16,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.offset == 0 {
+            write!(f, "{}", self.label)
+        } else {
+            write!(f, "{}+{:#X}", self.label, self.offset)
+        }
+    }
+}
+
+/// A line of a symbol file that names no address or no label, numbered from
+/// 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseSymbolsError {
+    pub line: usize,
+}
+
+impl fmt::Display for ParseSymbolsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid symbol on line {}", self.line)
+    }
+}
+
+impl std::error::Error for ParseSymbolsError {}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.data.is_empty()
+    }
+
+    pub fn insert(&mut self, address: u16, label: &str) {
+        self.labels.insert(address, String::from(label));
+    }
+
+    pub fn insert_data(&mut self, range: Range<u16>, label: &str) {
+        self.data
+            .insert(range.start, (range.end, String::from(label)));
+    }
+
+    /// The label placed exactly at `address`
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    pub fn address(&self, label: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(_, name)| name.as_str() == label)
+            .map(|(address, _)| *address)
+            .or_else(|| {
+                self.data
+                    .iter()
+                    .find(|(_, (_, name))| name.as_str() == label)
+                    .map(|(address, _)| *address)
+            })
+    }
+
+    /// The data region holding `address`
+    pub fn data(&self, address: u16) -> Option<(Range<u16>, &str)> {
+        let (start, (end, label)) = self.data.range(..=address).next_back()?;
+        Some((*start..*end, label.as_str())).filter(|(range, _)| range.contains(&address))
+    }
+
+    /// The data region holding `address`, or else the closest label before
+    /// it
+    pub fn locate(&self, address: u16) -> Option<Location<'_>> {
+        let (start, label) = match self.data(address) {
+            Some((range, label)) => (range.start, label),
+            None => self
+                .labels
+                .range(..=address)
+                .next_back()
+                .map(|(start, label)| (*start, label.as_str()))?,
+        };
+        Some(Location {
+            label,
+            offset: address - start,
+        })
+    }
+
+    /// `label+0x4`, or the address in hexadecimal when no label comes before
+    /// it
+    pub fn describe(&self, address: u16) -> String {
+        match self.locate(address) {
+            Some(location) => location.to_string(),
+            None => format!("{:03X}", address),
+        }
+    }
+
+    /// The disassembly of `instruction` with its address operand as a label
+    pub fn instruction(&self, instruction: Instruction) -> String {
+        let (mnemonic, address) = match instruction {
+            Instruction::Sys(address) => ("SYS", address),
+            Instruction::Jump(address) => ("JP", address),
+            Instruction::Call(address) => ("CALL", address),
+            Instruction::LoadI(address) => ("LD I,", address),
+            Instruction::JumpOffset(address) => ("JP V0,", address),
+            _ => return instruction.to_string(),
+        };
+        match self.locate(address) {
+            Some(location) => format!("{} {}", mnemonic, location),
+            None => instruction.to_string(),
+        }
+    }
+}
+
+impl FromStr for Symbols {
+    type Err = ParseSymbolsError;
+
+    fn from_str(text: &str) -> Result<Symbols, ParseSymbolsError> {
+        let mut symbols = Symbols::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = ParseSymbolsError { line: index + 1 };
+            let line = line.split('#').next().unwrap_or_default();
+            let tokens: Vec<&str> = line
+                .split(|c: char| c.is_whitespace() || c == '=')
+                .filter(|token| !token.is_empty())
+                .collect();
+            let (first, second) = match tokens[..] {
+                [] => continue,
+                [first, second] => (first, second),
+                _ => return Err(error),
+            };
+
+            // An address with a prefix wins over a label that reads as hex
+            let (range, label) = match (parse_range(first), parse_range(second)) {
+                (Some((range, true)), _) => (range, second),
+                (_, Some((range, true))) => (range, first),
+                (Some((range, false)), _) => (range, second),
+                (_, Some((range, false))) => (range, first),
+                _ => return Err(error),
+            };
+            if range.end == range.start + 1 {
+                symbols.insert(range.start, label);
+            } else if range.end > range.start {
+                symbols.insert_data(range, label);
+            } else {
+                return Err(error);
+            }
+        }
+        Ok(symbols)
+    }
+}
+
+/// `start-end` or a single address, and whether they were prefixed
+fn parse_range(token: &str) -> Option<(Range<u16>, bool)> {
+    let mut parts = token.splitn(2, '-');
+    let (start, prefixed) = parse_address(parts.next()?)?;
+    match parts.next() {
+        Some(end) => Some((start..parse_address(end)?.0, prefixed)),
+        None => Some((start..start.checked_add(1)?, prefixed)),
+    }
+}
+
+fn parse_address(text: &str) -> Option<(u16, bool)> {
+    let (digits, prefixed) = match text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+    {
+        Some(digits) => (digits, true),
+        None => (text, false),
+    };
+    Some((u16::from_str_radix(digits, 16).ok()?, prefixed))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm::Instruction;
+    use crate::symbols::{Location, ParseSymbolsError, Symbols};
+
+    const FILE: &str = "\
+# labels of a game
+0x200 main
+draw_player 0x2F0
+add = $300     # a label that reads as hex
+0x380-0x390 sprites
+";
+
+    #[test]
+    fn parse() {
+        let symbols: Symbols = FILE.parse().unwrap();
+        assert_eq!(symbols.label(0x200), Some("main"));
+        assert_eq!(symbols.label(0x2F0), Some("draw_player"));
+        assert_eq!(symbols.address("add"), Some(0x300));
+        assert_eq!(symbols.data(0x385), Some((0x380..0x390, "sprites")));
+        assert_eq!(symbols.data(0x390), None);
+
+        assert_eq!(
+            "0x200 main extra".parse::<Symbols>(),
+            Err(ParseSymbolsError { line: 1 })
+        );
+        assert_eq!(
+            "\nmain start".parse::<Symbols>(),
+            Err(ParseSymbolsError { line: 2 })
+        );
+    }
+
+    #[test]
+    fn locate() {
+        let symbols: Symbols = FILE.parse().unwrap();
+        assert_eq!(symbols.locate(0x100), None);
+        assert_eq!(
+            symbols.locate(0x2F4),
+            Some(Location {
+                label: "draw_player",
+                offset: 4
+            })
+        );
+        assert_eq!(symbols.describe(0x2F4), "draw_player+0x4");
+        assert_eq!(symbols.describe(0x388), "sprites+0x8");
+        assert_eq!(symbols.describe(0x392), "add+0x92");
+        assert_eq!(symbols.describe(0x50), "050");
+
+        assert_eq!(
+            symbols.instruction(Instruction::decode(0x22F0)),
+            "CALL draw_player"
+        );
+        assert_eq!(
+            symbols.instruction(Instruction::decode(0xA384)),
+            "LD I, sprites+0x4"
+        );
+        assert_eq!(symbols.instruction(Instruction::decode(0x1050)), "JP 0x050");
+        assert_eq!(
+            symbols.instruction(Instruction::decode(0x6005)),
+            "LD V0, 0x05"
+        );
+    }
+}
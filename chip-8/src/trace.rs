@@ -0,0 +1,358 @@
+//! Instruction traces, one line per executed instruction:
+//!
+//! ```text
+//! 0000000042 0204 A22A V:05 00 00 00 00 00 00 00 00 00 00 00 00 00 00 01 I:0000 SP:00 DT:3C ST:00 ; LD I, 0x22A
+//! ```
+//!
+//! The columns are the cycle count, the program counter and the opcode,
+//! followed by the registers before the instruction runs and its
+//! disassembly. Every column has a fixed width so two traces can be compared
+//! with `diff`. With [`Tracer::symbols`] the disassembly starts with the
+//! location of the instruction and uses labels:
+//!
+//! ```text
+//! 0000000042 0204 A22A V:05 ... ST:00 ; main+0x4: LD I, sprites
+//! ```
+//!
+//! [`TraceFormat::Cadmium`] writes the register lines of the Cadmium emulator
+//! instead, to `diff` against its log.
+
+use core::convert::TryFrom;
+use core::fmt;
+#[cfg(feature = "std")]
+use core::ops::Range;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+use crate::disasm::Instruction;
+#[cfg(feature = "std")]
+use crate::symbols::Symbols;
+use crate::view::MachineView;
+
+/// The state of the machine before an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub program_counter: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub stack_pointer: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceEntry {
+    /// Entry for the instruction the machine runs next.
+    pub fn capture(view: MachineView<'_>) -> TraceEntry {
+        let program_counter = view.program_counter();
+        let opcode = view.opcode().unwrap_or(0);
+
+        TraceEntry {
+            cycle: view.cycles(),
+            program_counter,
+            opcode,
+            v: *view.registers(),
+            i: view.index_register(),
+            stack_pointer: view.call_stack().len() as u8,
+            delay_timer: view.delay_timer(),
+            sound_timer: view.sound_timer(),
+        }
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:010} {:04X} {:04X} V:",
+            self.cycle, self.program_counter, self.opcode
+        )?;
+        for (index, register) in self.v.iter().enumerate() {
+            let separator = if index == 0 { "" } else { " " };
+            write!(f, "{}{:02X}", separator, register)?;
+        }
+        write!(
+            f,
+            " I:{:04X} SP:{:02X} DT:{:02X} ST:{:02X} ; {}",
+            self.i,
+            self.stack_pointer,
+            self.delay_timer,
+            self.sound_timer,
+            Instruction::decode(self.opcode)
+        )
+    }
+}
+
+/// The line of an entry as Cadmium logs it
+#[cfg(feature = "std")]
+struct Cadmium<'a>(&'a TraceEntry);
+
+#[cfg(feature = "std")]
+impl fmt::Display for Cadmium<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entry = self.0;
+        for (index, register) in entry.v.iter().enumerate() {
+            write!(f, "V{:X}:{:02x} ", index, register)?;
+        }
+        write!(
+            f,
+            "I:{:04x} SP:{:x} PC:{:04x} O:{:04x}",
+            entry.i, entry.stack_pointer, entry.program_counter, entry.opcode
+        )
+    }
+}
+
+/// Layout of the lines a [`Tracer`] writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// [`TraceEntry`] lines
+    #[default]
+    Native,
+    /// `V0:00 ... VF:00 I:0000 SP:0 PC:0200 O:00e0`, the registers, stack
+    /// pointer, program counter and opcode as Cadmium logs them
+    Cadmium,
+}
+
+impl FromStr for TraceFormat {
+    type Err = ParseTraceError;
+
+    /// `native` or `cadmium`
+    fn from_str(name: &str) -> Result<TraceFormat, ParseTraceError> {
+        match name {
+            "native" => Ok(TraceFormat::Native),
+            "cadmium" => Ok(TraceFormat::Cadmium),
+            _ => Err(ParseTraceError),
+        }
+    }
+}
+
+/// A trace line that isn't in the format written by [`TraceEntry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseTraceError;
+
+impl fmt::Display for ParseTraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid trace line")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseTraceError {}
+
+fn hex(text: &str) -> Result<u16, ParseTraceError> {
+    u16::from_str_radix(text, 16).map_err(|_| ParseTraceError)
+}
+
+fn hex_byte(text: &str) -> Result<u8, ParseTraceError> {
+    u8::try_from(hex(text)?).map_err(|_| ParseTraceError)
+}
+
+/// Parses a line written by the `Display` implementation, the disassembly is
+/// ignored.
+impl FromStr for TraceEntry {
+    type Err = ParseTraceError;
+
+    fn from_str(line: &str) -> Result<TraceEntry, ParseTraceError> {
+        let state = line.split(" ; ").next().unwrap_or_default();
+        let mut fields = state.split_whitespace();
+        let mut next = || fields.next().ok_or(ParseTraceError);
+        let labelled = |field: &str, label: &str| -> Result<u16, ParseTraceError> {
+            hex(field.strip_prefix(label).ok_or(ParseTraceError)?)
+        };
+
+        let cycle = next()?.parse().map_err(|_| ParseTraceError)?;
+        let program_counter = hex(next()?)?;
+        let opcode = hex(next()?)?;
+        let mut v = [0; 16];
+        v[0] = hex_byte(next()?.strip_prefix("V:").ok_or(ParseTraceError)?)?;
+        for register in v[1..].iter_mut() {
+            *register = hex_byte(next()?)?;
+        }
+        let i = labelled(next()?, "I:")?;
+        let stack_pointer = labelled(next()?, "SP:")?;
+        let delay_timer = labelled(next()?, "DT:")?;
+        let sound_timer = labelled(next()?, "ST:")?;
+        if next().is_ok() {
+            return Err(ParseTraceError);
+        }
+
+        Ok(TraceEntry {
+            cycle,
+            program_counter,
+            opcode,
+            v,
+            i,
+            stack_pointer: u8::try_from(stack_pointer).map_err(|_| ParseTraceError)?,
+            delay_timer: u8::try_from(delay_timer).map_err(|_| ParseTraceError)?,
+            sound_timer: u8::try_from(sound_timer).map_err(|_| ParseTraceError)?,
+        })
+    }
+}
+
+/// Writes a [`TraceEntry`] for every instruction, optionally only for the
+/// ones inside some address ranges.
+#[cfg(feature = "std")]
+pub struct Tracer<W> {
+    writer: W,
+    ranges: Vec<Range<u16>>,
+    symbols: Option<Symbols>,
+    format: TraceFormat,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W) -> Tracer<W> {
+        Tracer {
+            writer,
+            ranges: Vec::new(),
+            symbols: None,
+            format: TraceFormat::Native,
+        }
+    }
+
+    /// Traces the instructions at addresses inside `range`, every
+    /// instruction is traced when no range is given.
+    pub fn range(mut self, range: Range<u16>) -> Tracer<W> {
+        self.ranges.push(range);
+        self
+    }
+
+    /// Shows the labels of `symbols` in the disassembly
+    pub fn symbols(mut self, symbols: Symbols) -> Tracer<W> {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    /// Writes the lines in `format`, [`TraceFormat::Native`] by default
+    pub fn format(mut self, format: TraceFormat) -> Tracer<W> {
+        self.format = format;
+        self
+    }
+
+    /// Writes the instruction the machine runs next, call it before
+    /// [`crate::cpu::Cpu::next`].
+    pub fn record(&mut self, view: MachineView<'_>) -> io::Result<()> {
+        if view.waiting_vblank() {
+            return Ok(());
+        }
+        let program_counter = view.program_counter();
+        if !self.ranges.is_empty()
+            && !self
+                .ranges
+                .iter()
+                .any(|range| range.contains(&program_counter))
+        {
+            return Ok(());
+        }
+
+        let entry = TraceEntry::capture(view);
+        match (self.format, &self.symbols) {
+            (TraceFormat::Cadmium, _) => writeln!(self.writer, "{}", Cadmium(&entry)),
+            (TraceFormat::Native, Some(symbols)) => {
+                let line = entry.to_string();
+                let state = line.split(" ; ").next().unwrap_or_default();
+                writeln!(
+                    self.writer,
+                    "{} ; {}: {}",
+                    state,
+                    symbols.describe(program_counter),
+                    symbols.instruction(Instruction::decode(entry.opcode))
+                )
+            }
+            (TraceFormat::Native, None) => writeln!(self.writer, "{}", entry),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::cpu::Cpu;
+    use crate::symbols::Symbols;
+    use crate::trace::{TraceEntry, TraceFormat, Tracer};
+
+    // V0 = 5, I = 0x22A, jump to 0x204
+    const ROM: [u8; 6] = [0x60, 0x05, 0xA2, 0x2A, 0x12, 0x04];
+
+    #[test]
+    fn line_format() {
+        let mut cpu = Cpu::new(Cursor::new(ROM)).unwrap();
+        cpu.next();
+
+        assert_eq!(
+            TraceEntry::capture(cpu.view()).to_string(),
+            "0000000001 0202 A22A V:05 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 \
+             I:0000 SP:00 DT:00 ST:00 ; LD I, 0x22A"
+        );
+    }
+
+    #[test]
+    fn parse_line() {
+        let mut cpu = Cpu::new(Cursor::new(ROM)).unwrap();
+        cpu.next();
+        let entry = TraceEntry::capture(cpu.view());
+
+        assert_eq!(entry.to_string().parse(), Ok(entry));
+        assert!("0000000001 0202 A22A V:05".parse::<TraceEntry>().is_err());
+    }
+
+    #[test]
+    fn address_ranges() {
+        let mut cpu = Cpu::new(Cursor::new(ROM)).unwrap();
+        let mut tracer = Tracer::new(Vec::new()).range(0x202..0x204);
+        for _ in 0..4 {
+            tracer.record(cpu.view()).unwrap();
+            cpu.next();
+        }
+
+        let trace = String::from_utf8(tracer.into_inner()).unwrap();
+        assert_eq!(trace.lines().count(), 1);
+        assert!(trace.starts_with("0000000001 0202 A22A"));
+    }
+
+    #[test]
+    fn symbols() {
+        let mut cpu = Cpu::new(Cursor::new(ROM)).unwrap();
+        let symbols: Symbols = "0x200 main\n0x22A sprite".parse().unwrap();
+        let mut tracer = Tracer::new(Vec::new()).symbols(symbols);
+        for _ in 0..2 {
+            tracer.record(cpu.view()).unwrap();
+            cpu.next();
+        }
+
+        let trace = String::from_utf8(tracer.into_inner()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert!(lines[0].ends_with(" ; main: LD V0, 0x05"));
+        assert!(lines[1].ends_with(" ; main+0x2: LD I, sprite"));
+        assert!(lines[1].parse::<TraceEntry>().is_ok());
+    }
+
+    #[test]
+    fn cadmium() {
+        let mut cpu = Cpu::new(Cursor::new(ROM)).unwrap();
+        let mut tracer = Tracer::new(Vec::new())
+            .symbols("0x200 main".parse().unwrap())
+            .format(TraceFormat::Cadmium);
+        for _ in 0..2 {
+            tracer.record(cpu.view()).unwrap();
+            cpu.next();
+        }
+
+        let trace = String::from_utf8(tracer.into_inner()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(
+            lines[1],
+            "V0:05 V1:00 V2:00 V3:00 V4:00 V5:00 V6:00 V7:00 V8:00 V9:00 VA:00 VB:00 \
+             VC:00 VD:00 VE:00 VF:00 I:0000 SP:0 PC:0202 O:a22a"
+        );
+        assert_eq!("cadmium".parse(), Ok(TraceFormat::Cadmium));
+        assert!("bochs".parse::<TraceFormat>().is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Inspection of a running machine, for debuggers, overlays and tests.
+//!
+//! [`crate::cpu::Cpu::view`] borrows the machine read-only, changing it takes
+//! an explicit [`crate::cpu::Cpu::poke`].
+
+use core::ops::Range;
+
+use crate::display::Display;
+
+/// Read-only view of the state of a machine.
+#[derive(Clone, Copy)]
+pub struct MachineView<'a> {
+    pub(crate) memory: &'a [u8],
+    pub(crate) v: &'a [u8; 16],
+    pub(crate) i: u16,
+    pub(crate) program_counter: u16,
+    pub(crate) opcode: Option<u16>,
+    pub(crate) stack: &'a [u16],
+    pub(crate) delay_timer: u8,
+    pub(crate) sound_timer: u8,
+    pub(crate) display: &'a Display,
+    pub(crate) cycles: u64,
+    pub(crate) waiting_vblank: bool,
+}
+
+impl<'a> MachineView<'a> {
+    pub fn memory(&self) -> &'a [u8] {
+        self.memory
+    }
+
+    /// `None` when the range isn't inside memory
+    pub fn memory_range(&self, range: Range<usize>) -> Option<&'a [u8]> {
+        self.memory.get(range)
+    }
+
+    /// V0 to VF
+    pub fn registers(&self) -> &'a [u8; 16] {
+        self.v
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.i
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    /// The instruction at the program counter as the bus serves it, `None`
+    /// when it runs past the end of memory
+    pub fn opcode(&self) -> Option<u16> {
+        self.opcode
+    }
+
+    /// Return addresses of the active subroutine calls, innermost last
+    pub fn call_stack(&self) -> &'a [u16] {
+        self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn display(&self) -> &'a Display {
+        self.display
+    }
+
+    /// Instructions executed since the program was loaded
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The `vblank` quirk holds the machine until the timers tick
+    pub fn waiting_vblank(&self) -> bool {
+        self.waiting_vblank
+    }
+}
+
+/// Write access to the state of a machine.
+pub struct Poke<'a> {
+    pub(crate) memory: &'a mut [u8],
+    pub(crate) v: &'a mut [u8; 16],
+    pub(crate) i: &'a mut u16,
+    pub(crate) program_counter: &'a mut u16,
+    pub(crate) delay_timer: &'a mut u8,
+    pub(crate) sound_timer: &'a mut u8,
+}
+
+impl Poke<'_> {
+    pub fn memory(&mut self) -> &mut [u8] {
+        self.memory
+    }
+
+    /// `None` when the range isn't inside memory
+    pub fn memory_range(&mut self, range: Range<usize>) -> Option<&mut [u8]> {
+        self.memory.get_mut(range)
+    }
+
+    /// V0 to VF
+    pub fn registers(&mut self) -> &mut [u8; 16] {
+        self.v
+    }
+
+    pub fn set_index_register(&mut self, value: u16) {
+        *self.i = value;
+    }
+
+    pub fn set_program_counter(&mut self, value: u16) {
+        *self.program_counter = value;
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        *self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        *self.sound_timer = value;
+    }
+}
@@ -0,0 +1,464 @@
+//! The COSMAC VIP, emulated down to the hardware.
+//!
+//! Instead of interpreting CHIP-8 itself, [`Vip`] runs the original
+//! interpreter on a [`Cdp1802`] like the real computer did, so timing, the
+//! display wait, the quirks and `0NNN` machine code subroutines behave as
+//! they did in 1977. The monitor ROM and the interpreter are not included,
+//! they come from a dump of the user's own machine:
+//!
+//! - the 512 byte monitor ROM, which answers at 0x8000 and at 0x0000 after
+//!   a reset, and whose interrupt routine drives the display
+//! - the interpreter, loaded at 0x0000 with the program at 0x0200
+//!
+//! Around the processor sit the RAM, the CDP1861 video chip, which reads a
+//! line of 64 pixels by DMA every 14 machine cycles and interrupts the
+//! processor before each picture, and the hexadecimal keypad, whose key
+//! selected with `OUT 2` pulls `EF3` low while pressed. `Q` drives the tone.
+
+use core::fmt;
+
+use crate::cdp1802::{Cdp1802, Io};
+
+pub const MONITOR_SIZE: usize = 0x200;
+/// Most RAM the VIP can address below the monitor
+pub const RAM_CAPACITY: usize = 0x8000;
+/// The RAM of a VIP with its on-board expansion
+pub const DEFAULT_RAM_SIZE: usize = 0x1000;
+/// Where the CHIP-8 interpreter loads programs
+pub const PROGRAM_ADDRESS: u16 = 0x200;
+
+/// Pixels of a line of the picture
+pub const WIDTH: usize = 64;
+/// Lines of the picture, the CHIP-8 interpreter repeats every row on 4
+pub const LINES: usize = 128;
+const BYTES_PER_LINE: usize = WIDTH / 8;
+
+/// Machine cycles of a 1.76 MHz clock in a line of the television
+pub const CYCLES_PER_LINE: u32 = 14;
+/// 262 lines, about 60 pictures per second
+pub const CYCLES_PER_FRAME: u32 = 262 * CYCLES_PER_LINE;
+/// The picture is shown between these lines
+const FIRST_LINE: u32 = 64;
+const LAST_LINE: u32 = FIRST_LINE + LINES as u32;
+/// The interrupt comes 29 cycles before the first DMA, the time the
+/// monitor's routine takes to get ready
+const INTERRUPT_CYCLE: u32 = FIRST_LINE * CYCLES_PER_LINE - 29;
+
+/// Value read from addresses without RAM
+const OPEN_BUS: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VipError {
+    /// The monitor ROM isn't [`MONITOR_SIZE`] bytes
+    MonitorSize(usize),
+    /// The RAM isn't a power of two between 2 KiB and [`RAM_CAPACITY`]
+    RamSize(usize),
+    /// The bytes don't fit between their address and the end of the RAM
+    TooLarge { size: usize, available: usize },
+}
+
+impl fmt::Display for VipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VipError::MonitorSize(size) => write!(
+                f,
+                "the monitor ROM is {} bytes instead of {}",
+                size, MONITOR_SIZE
+            ),
+            VipError::RamSize(size) => write!(
+                f,
+                "{} bytes of RAM, use a power of two from 2048 to {}",
+                size, RAM_CAPACITY
+            ),
+            VipError::TooLarge { size, available } => write!(
+                f,
+                "{} bytes don't fit in the {} bytes of RAM available",
+                size, available
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VipError {}
+
+/// Everything on the board but the processor.
+#[derive(Clone)]
+struct Board {
+    ram: [u8; RAM_CAPACITY],
+    ram_size: usize,
+    monitor: [u8; MONITOR_SIZE],
+    /// After a reset the monitor answers everywhere until the processor
+    /// first reaches an address with the top bit set
+    boot: bool,
+    /// The CDP1861 is turned on by `INP 1` and off by `OUT 1`
+    display_on: bool,
+    picture: [u8; LINES * BYTES_PER_LINE],
+    /// Machine cycle within the frame
+    cycle: u32,
+    /// Next line whose DMA is due
+    dma_line: u32,
+    keys: u16,
+    /// Key tested by `EF3`
+    selected_key: u8,
+}
+
+impl Io for Board {
+    fn read(&mut self, address: u16) -> u8 {
+        if address & 0x8000 != 0 {
+            self.boot = false;
+        }
+        if self.boot || address & 0x8000 != 0 {
+            return self.monitor[address as usize % MONITOR_SIZE];
+        }
+        if (address as usize) < self.ram_size {
+            self.ram[address as usize]
+        } else {
+            OPEN_BUS
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address & 0x8000 != 0 {
+            self.boot = false;
+        } else if (address as usize) < self.ram_size {
+            self.ram[address as usize] = value;
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.display_on = false,
+            2 => self.selected_key = value & 0xF,
+            _ => {}
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display_on = true;
+        }
+        OPEN_BUS
+    }
+
+    fn flag(&mut self, flag: u8) -> bool {
+        match flag {
+            // The CDP1861 pulls EF1 low for 4 lines before the picture and
+            // during its last 4
+            1 => {
+                let line = self.cycle / CYCLES_PER_LINE;
+                (FIRST_LINE - 4..FIRST_LINE).contains(&line)
+                    || (LAST_LINE - 4..LAST_LINE).contains(&line)
+            }
+            3 => self.keys & 1 << self.selected_key != 0,
+            _ => false,
+        }
+    }
+}
+
+/// A COSMAC VIP with its monitor ROM.
+///
+/// ```no_run
+/// use chip_8::vip::Vip;
+///
+/// let monitor = std::fs::read("vip-monitor.bin").unwrap();
+/// let interpreter = std::fs::read("chip-8.bin").unwrap();
+/// let program = std::fs::read("game.ch8").unwrap();
+/// let mut vip = Vip::chip_8(&monitor, &interpreter, &program).unwrap();
+/// vip.run_frame();
+/// ```
+#[derive(Clone)]
+pub struct Vip {
+    cpu: Cdp1802,
+    board: Board,
+}
+
+impl Vip {
+    /// A VIP with `ram_size` bytes of empty RAM, reset
+    pub fn new(monitor: &[u8], ram_size: usize) -> Result<Vip, VipError> {
+        if monitor.len() != MONITOR_SIZE {
+            return Err(VipError::MonitorSize(monitor.len()));
+        }
+        if !ram_size.is_power_of_two() || !(0x800..=RAM_CAPACITY).contains(&ram_size) {
+            return Err(VipError::RamSize(ram_size));
+        }
+
+        let mut board = Board {
+            ram: [0; RAM_CAPACITY],
+            ram_size,
+            monitor: [0; MONITOR_SIZE],
+            boot: true,
+            display_on: false,
+            picture: [0; LINES * BYTES_PER_LINE],
+            cycle: 0,
+            dma_line: 0,
+            keys: 0,
+            selected_key: 0,
+        };
+        board.monitor.copy_from_slice(monitor);
+        Ok(Vip {
+            cpu: Cdp1802::new(),
+            board,
+        })
+    }
+
+    /// A 4 KiB VIP with the CHIP-8 `interpreter` at 0 and `program` at
+    /// [`PROGRAM_ADDRESS`]
+    pub fn chip_8(monitor: &[u8], interpreter: &[u8], program: &[u8]) -> Result<Vip, VipError> {
+        let mut vip = Vip::new(monitor, DEFAULT_RAM_SIZE)?;
+        if interpreter.len() > PROGRAM_ADDRESS as usize {
+            return Err(VipError::TooLarge {
+                size: interpreter.len(),
+                available: PROGRAM_ADDRESS as usize,
+            });
+        }
+        vip.load(0, interpreter)?;
+        vip.load(PROGRAM_ADDRESS, program)?;
+        Ok(vip)
+    }
+
+    /// Copies `bytes` to the RAM at `address`
+    pub fn load(&mut self, address: u16, bytes: &[u8]) -> Result<(), VipError> {
+        let start = address as usize;
+        let available = self.board.ram_size.saturating_sub(start);
+        if bytes.len() > available {
+            return Err(VipError::TooLarge {
+                size: bytes.len(),
+                available,
+            });
+        }
+        self.board.ram[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Presses the reset switch, the RAM is kept
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        let board = &mut self.board;
+        board.boot = true;
+        board.display_on = false;
+        board.picture = [0; LINES * BYTES_PER_LINE];
+        board.cycle = 0;
+        board.dma_line = 0;
+    }
+
+    /// Runs an instruction, or the DMA or interrupt due, and returns the
+    /// machine cycles it took
+    pub fn step(&mut self) -> u32 {
+        let board = &mut self.board;
+        let line = board.cycle / CYCLES_PER_LINE;
+        let cycles = if board.display_on
+            && line >= board.dma_line
+            && (FIRST_LINE..LAST_LINE).contains(&line)
+        {
+            let start = (line - FIRST_LINE) as usize * BYTES_PER_LINE;
+            for offset in 0..BYTES_PER_LINE {
+                board.picture[start + offset] = self.cpu.dma_out(board);
+            }
+            board.dma_line = line + 1;
+            BYTES_PER_LINE as u32
+        } else if board.display_on
+            && (INTERRUPT_CYCLE..FIRST_LINE * CYCLES_PER_LINE).contains(&board.cycle)
+            && self.cpu.interrupts_enabled()
+        {
+            self.cpu.interrupt() as u32
+        } else {
+            self.cpu.step(board) as u32
+        };
+
+        board.cycle += cycles;
+        if board.cycle >= CYCLES_PER_FRAME {
+            board.cycle -= CYCLES_PER_FRAME;
+            board.dma_line = 0;
+        }
+        cycles
+    }
+
+    /// Runs a sixtieth of a second
+    pub fn run_frame(&mut self) {
+        let mut cycles = 0;
+        while cycles < CYCLES_PER_FRAME {
+            cycles += self.step();
+        }
+    }
+
+    pub fn set_key(&mut self, key_index: u8, status: bool) {
+        let bit = 1 << (key_index & 0xF);
+        if status {
+            self.board.keys |= bit;
+        } else {
+            self.board.keys &= !bit;
+        }
+    }
+
+    /// The speaker sounds while `Q` is set
+    pub fn should_play_sound(&self) -> bool {
+        self.cpu.q()
+    }
+
+    pub fn cpu(&self) -> &Cdp1802 {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cdp1802 {
+        &mut self.cpu
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.board.ram[..self.board.ram_size]
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.board.ram[..self.board.ram_size]
+    }
+
+    pub fn is_display_on(&self) -> bool {
+        self.board.display_on
+    }
+
+    /// Whether the pixel at `x` on `line` was lit in the last picture, both
+    /// wrap around
+    pub fn is_pixel_set(&self, x: usize, line: usize) -> bool {
+        let x = x % WIDTH;
+        let byte = self.board.picture[line % LINES * BYTES_PER_LINE + x / 8];
+        byte & 0x80 >> (x % 8) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vip::{Vip, VipError, LINES, MONITOR_SIZE, WIDTH};
+
+    /// Leaves the monitor through 0x8007 and runs the RAM from 0 with `P`
+    /// at 0, like the real monitor does without a key held
+    fn monitor() -> [u8; MONITOR_SIZE] {
+        let mut monitor = [0; MONITOR_SIZE];
+        let code = [
+            0xF8, 0x80, 0xB3, 0xF8, 0x07, 0xA3, 0xD3, // R3 = 8007, SEP 3
+            0xF8, 0x00, 0xB0, 0xA0, 0xD0, // R0 = 0, SEP 0
+        ];
+        monitor[..code.len()].copy_from_slice(&code);
+        monitor
+    }
+
+    /// Sets `P` to 3, the stack to 0BFF, the interrupt routine to 0082,
+    /// turns the display on and runs `main` from 0020
+    fn program(main: &[u8]) -> Vip {
+        let mut vip = Vip::new(&monitor(), 0x1000).unwrap();
+        let start = [
+            0xF8, 0x00, 0xB3, 0xF8, 0x20, 0xA3, 0xD3, // R3 = 0020, SEP 3
+        ];
+        let setup = [
+            0xF8, 0x00, 0xB1, 0xF8, 0x82, 0xA1, // R1 = 0082
+            0xF8, 0x0B, 0xB2, 0xF8, 0xFF, 0xA2, // R2 = 0BFF
+            0xE2, 0x69, // SEX 2, INP 1
+        ];
+        // Saves T and D, points R0 at the picture in 0C00 and returns once
+        // EF1 went off and on again, at the end of the picture
+        let interrupt = [
+            0x72, 0x70, // 80: LDXA, RET
+            0x22, 0x78, 0x22, 0x52, // 82: DEC 2, SAV, DEC 2, STR 2
+            0xF8, 0x0C, 0xB0, 0xF8, 0x00, 0xA0, // R0 = 0C00
+            0x34, 0x8C, 0x3C, 0x8E, // 8C: B1 8C, BN1 8E
+            0x30, 0x80, // BR 80
+        ];
+        vip.load(0, &start).unwrap();
+        vip.load(0x20, &setup).unwrap();
+        vip.load(0x20 + setup.len() as u16, main).unwrap();
+        vip.load(0x80, &interrupt).unwrap();
+        vip
+    }
+
+    #[test]
+    fn picture() {
+        // Waits for interrupts forever
+        let mut vip = program(&[0x00, 0x30, 0x2E]);
+        for (offset, byte) in vip.ram_mut()[0xC00..].iter_mut().enumerate() {
+            *byte = offset as u8;
+        }
+        vip.run_frame();
+        vip.run_frame();
+
+        assert!(vip.is_display_on());
+        for line in 0..LINES {
+            for x in 0..WIDTH {
+                let byte = (line * WIDTH + x) / 8;
+                let lit = byte as u8 & 0x80 >> (x % 8) != 0;
+                assert_eq!(vip.is_pixel_set(x, line), lit, "{} {}", x, line);
+            }
+        }
+    }
+
+    #[test]
+    fn keypad_and_tone() {
+        // Selects key 5, sets Q while it is held and stores 1 at 0A00 once
+        // it was seen
+        let main = [
+            0xF8, 0x05, 0x52, 0x62, // 2E: M(R2) = 5, OUT 2
+            0x36, 0x37, // 32: B3 37
+            0x7A, 0x30, 0x32, // 34: REQ, BR 32
+            0x7B, // 37: SEQ
+            0xF8, 0x0A, 0xB4, 0xF8, 0x00, 0xA4, // R4 = 0A00
+            0xF8, 0x01, 0x54, // M(R4) = 1
+            0x30, 0x32, // BR 32
+        ];
+        let mut vip = program(&main);
+        vip.run_frame();
+        assert!(!vip.should_play_sound());
+        assert_eq!(vip.ram()[0xA00], 0);
+
+        vip.set_key(0x5, true);
+        vip.run_frame();
+        assert!(vip.should_play_sound());
+        assert_eq!(vip.ram()[0xA00], 1);
+
+        vip.set_key(0x5, false);
+        vip.run_frame();
+        assert!(!vip.should_play_sound());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Vip::new(&[0; 0x100], 0x1000).err(),
+            Some(VipError::MonitorSize(0x100))
+        );
+        assert_eq!(
+            Vip::new(&monitor(), 0x1800).err(),
+            Some(VipError::RamSize(0x1800))
+        );
+        let mut vip = Vip::new(&monitor(), 0x800).unwrap();
+        assert_eq!(
+            vip.load(0x7FF, &[0, 0]),
+            Err(VipError::TooLarge {
+                size: 2,
+                available: 1
+            })
+        );
+        assert_eq!(
+            Vip::chip_8(&monitor(), &[0; 0x201], &[]).err(),
+            Some(VipError::TooLarge {
+                size: 0x201,
+                available: 0x200
+            })
+        );
+    }
+
+    #[test]
+    fn reset_runs_the_monitor() {
+        let mut vip = Vip::new(&monitor(), 0x1000).unwrap();
+        // Loops at 0000
+        vip.load(0, &[0x30, 0x00]).unwrap();
+        for _ in 0..12 {
+            vip.step();
+        }
+        assert_eq!(vip.cpu().p(), 0);
+        assert_eq!(vip.cpu().program_counter() & 0xFFFE, 0);
+
+        // The monitor answers at 0000 again
+        vip.reset();
+        assert_eq!(vip.cpu().program_counter(), 0);
+        vip.step();
+        assert_eq!(vip.cpu().d(), 0x80);
+    }
+}
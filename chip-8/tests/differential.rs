@@ -0,0 +1,216 @@
+//! Runs random programs on `Cpu` and on the reference interpreter in
+//! `reference/`, under every quirk configuration, and compares both machines
+//! after every instruction.
+
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use chip_8::cpu::{Cpu, START_ADDRESS};
+use chip_8::quirks::Quirks;
+
+mod reference;
+
+use reference::{Reference, HEIGHT, WIDTH};
+
+const MAX_INSTRUCTIONS: usize = 48;
+const STEPS: usize = 200;
+const STEPS_PER_FRAME: usize = 8;
+
+#[derive(Clone, Debug)]
+struct Case {
+    program: Vec<u16>,
+    v: [u8; 16],
+    i: u16,
+    delay: u8,
+    sound: u8,
+    keys: u16,
+    seed: u64,
+}
+
+/// Addresses inside the program, so that jumps and calls keep running it
+fn target() -> impl Strategy<Value = u16> {
+    START_ADDRESS..START_ADDRESS + 2 * MAX_INSTRUCTIONS as u16
+}
+
+fn instruction() -> impl Strategy<Value = u16> {
+    let operand = any::<u16>();
+    prop_oneof![
+        1 => any::<u16>(),
+        1 => Just(0x00E0),
+        1 => Just(0x00EE),
+        2 => target().prop_map(|address| 0x1000 | address),
+        2 => target().prop_map(|address| 0x2000 | address),
+        4 => operand.prop_map(|operand| 0x3000 | operand & 0x0FFF),
+        4 => operand.prop_map(|operand| 0x4000 | operand & 0x0FFF),
+        2 => operand.prop_map(|operand| 0x5000 | operand & 0x0FF0),
+        6 => operand.prop_map(|operand| 0x6000 | operand & 0x0FFF),
+        6 => operand.prop_map(|operand| 0x7000 | operand & 0x0FFF),
+        12 => (operand, prop::sample::select(vec![0, 1, 2, 3, 4, 5, 6, 7, 0xE]))
+            .prop_map(|(operand, n)| 0x8000 | operand & 0x0FF0 | n),
+        2 => operand.prop_map(|operand| 0x9000 | operand & 0x0FF0),
+        4 => operand.prop_map(|operand| 0xA000 | operand & 0x0FFF),
+        1 => target().prop_map(|address| 0xB000 | (address & 0xF00)),
+        4 => operand.prop_map(|operand| 0xC000 | operand & 0x0FFF),
+        6 => operand.prop_map(|operand| 0xD000 | operand & 0x0FFF),
+        2 => (operand, prop::sample::select(vec![0x9E, 0xA1]))
+            .prop_map(|(operand, nn)| 0xE000 | operand & 0x0F00 | nn),
+        12 => (
+            operand,
+            prop::sample::select(vec![0x07, 0x0A, 0x15, 0x18, 0x1E, 0x29, 0x33, 0x55, 0x65])
+        )
+            .prop_map(|(operand, nn)| 0xF000 | operand & 0x0F00 | nn),
+    ]
+}
+
+fn case() -> impl Strategy<Value = Case> {
+    (
+        prop::collection::vec(instruction(), 1..=MAX_INSTRUCTIONS),
+        any::<[u8; 16]>(),
+        0..0x1000u16,
+        any::<u8>(),
+        any::<u8>(),
+        any::<u16>(),
+        any::<u64>(),
+    )
+        .prop_map(|(program, v, i, delay, sound, keys, seed)| Case {
+            program,
+            v,
+            i,
+            delay,
+            sound,
+            keys,
+            seed,
+        })
+}
+
+/// Every combination of the quirk settings
+fn quirk_configurations() -> impl Iterator<Item = Quirks> {
+    (0..1 << 7).map(|bits: u8| {
+        let bit = |n: u8| bits & 1 << n != 0;
+        Quirks {
+            shift: bit(0),
+            memory_increment_by_x: bit(1),
+            memory_leave_i_unchanged: bit(2),
+            wrap: bit(3),
+            jump: bit(4),
+            vblank: bit(5),
+            logic: bit(6),
+        }
+    })
+}
+
+fn machines(case: &Case, quirks: Quirks) -> (Cpu, Reference<StdRng>) {
+    let rom: Vec<u8> = case
+        .program
+        .iter()
+        .flat_map(|op| op.to_be_bytes())
+        .collect();
+    let rng = StdRng::seed_from_u64(case.seed);
+    let mut cpu = Cpu::from_bytes(&rom, rng.clone()).unwrap();
+    cpu.set_quirks(quirks);
+    let mut poke = cpu.poke();
+    *poke.registers() = case.v;
+    poke.set_index_register(case.i);
+    poke.set_delay_timer(case.delay);
+    poke.set_sound_timer(case.sound);
+    for key in 0..16 {
+        cpu.set_key(key, case.keys & 1 << key != 0);
+    }
+
+    let view = cpu.view();
+    let reference = Reference {
+        memory: view.memory().to_vec(),
+        v: case.v,
+        i: case.i,
+        pc: view.program_counter(),
+        stack: Vec::new(),
+        delay: case.delay,
+        sound: case.sound,
+        keys: case.keys,
+        screen: [0; WIDTH * HEIGHT],
+        font_address: cpu.font_address(),
+        quirks,
+        waiting_vblank: false,
+        fault: None,
+        rng,
+    };
+    (cpu, reference)
+}
+
+fn compare(cpu: &Cpu, reference: &Reference<StdRng>, step: usize) -> Result<(), TestCaseError> {
+    let view = cpu.view();
+    prop_assert_eq!(view.registers(), &reference.v, "V after step {}", step);
+    prop_assert_eq!(view.index_register(), reference.i, "I after step {}", step);
+    prop_assert_eq!(
+        view.program_counter(),
+        reference.pc,
+        "PC after step {}",
+        step
+    );
+    prop_assert_eq!(
+        view.call_stack(),
+        &reference.stack[..],
+        "stack after step {}",
+        step
+    );
+    prop_assert_eq!(
+        view.delay_timer(),
+        reference.delay,
+        "DT after step {}",
+        step
+    );
+    prop_assert_eq!(
+        view.sound_timer(),
+        reference.sound,
+        "ST after step {}",
+        step
+    );
+    prop_assert_eq!(cpu.fault(), reference.fault, "fault after step {}", step);
+    prop_assert_eq!(
+        view.waiting_vblank(),
+        reference.waiting_vblank,
+        "vblank after step {}",
+        step
+    );
+    if view.memory() != &reference.memory[..] {
+        let address = (0..reference.memory.len())
+            .find(|&address| view.memory()[address] != reference.memory[address])
+            .unwrap();
+        return Err(TestCaseError::fail(format!(
+            "memory {:03X} after step {}: {:02X} / {:02X}",
+            address,
+            step,
+            view.memory()[address],
+            reference.memory[address]
+        )));
+    }
+    prop_assert!(
+        view.display().get_video_mem()[..] == reference.screen[..],
+        "framebuffer after step {}",
+        step
+    );
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn matches_reference(case in case()) {
+        for quirks in quirk_configurations() {
+            let (mut cpu, mut reference) = machines(&case, quirks);
+            compare(&cpu, &reference, 0)
+                .map_err(|error| TestCaseError::fail(format!("{:?}: {}", quirks, error)))?;
+            for step in 1..=STEPS {
+                cpu.next();
+                reference.step();
+                if step % STEPS_PER_FRAME == 0 {
+                    cpu.decrease_timers();
+                    reference.tick();
+                }
+                compare(&cpu, &reference, step)
+                    .map_err(|error| TestCaseError::fail(format!("{:?}: {}", quirks, error)))?;
+            }
+        }
+    }
+}
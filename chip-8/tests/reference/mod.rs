@@ -0,0 +1,221 @@
+//! A plain CHIP-8 interpreter written from the specification, without
+//! anything shared with `Cpu` but the quirk settings and the fault type.
+//! `Cpu` is checked against it in `differential.rs`.
+
+use rand::Rng;
+
+use chip_8::cpu::Fault;
+use chip_8::quirks::Quirks;
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+pub const STACK_DEPTH: usize = 24;
+
+pub struct Reference<R> {
+    pub memory: Vec<u8>,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>,
+    pub delay: u8,
+    pub sound: u8,
+    /// Bit `k` is set when key `k` is down
+    pub keys: u16,
+    /// One byte per pixel, 1 when it is on, row by row
+    pub screen: [u8; WIDTH * HEIGHT],
+    pub font_address: u16,
+    pub quirks: Quirks,
+    pub waiting_vblank: bool,
+    pub fault: Option<Fault>,
+    pub rng: R,
+}
+
+impl<R: Rng> Reference<R> {
+    pub fn step(&mut self) {
+        if self.waiting_vblank || self.fault.is_some() {
+            return;
+        }
+        if let Err(fault) = self.execute() {
+            self.fault = Some(fault);
+        }
+    }
+
+    /// End of a frame
+    pub fn tick(&mut self) {
+        self.waiting_vblank = false;
+        self.delay = self.delay.saturating_sub(1);
+        self.sound = self.sound.saturating_sub(1);
+    }
+
+    fn execute(&mut self) -> Result<(), Fault> {
+        let pc = self.pc as usize;
+        if pc + 2 > self.memory.len() {
+            return Err(Fault::InvalidAddress(self.pc));
+        }
+        let opcode = u16::from_be_bytes([self.memory[pc], self.memory[pc + 1]]);
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let n = opcode & 0xF;
+        let nn = opcode as u8;
+        let nnn = opcode & 0xFFF;
+
+        let mut next = self.pc + 2;
+        match opcode >> 12 {
+            0x0 => match opcode {
+                // Halt
+                0x0000 => next = self.pc,
+                0x00E0 => self.screen = [0; WIDTH * HEIGHT],
+                0x00EE => next = self.stack.pop().ok_or(Fault::StackUnderflow)?,
+                _ => {}
+            },
+            0x1 => next = nnn,
+            0x2 => {
+                if self.stack.len() == STACK_DEPTH {
+                    return Err(Fault::StackOverflow);
+                }
+                self.stack.push(next);
+                next = nnn;
+            }
+            0x3 if self.v[x] == nn => next += 2,
+            0x4 if self.v[x] != nn => next += 2,
+            0x5 if n == 0 && self.v[x] == self.v[y] => next += 2,
+            0x6 => self.v[x] = nn,
+            0x7 => self.v[x] = self.v[x].wrapping_add(nn),
+            0x8 => self.arithmetic(x, y, n),
+            0x9 if n == 0 && self.v[x] != self.v[y] => next += 2,
+            0xA => self.i = nnn,
+            0xB => {
+                let register = if self.quirks.jump { x } else { 0 };
+                next = nnn + u16::from(self.v[register]);
+            }
+            0xC => self.v[x] = self.rng.gen::<u8>() & nn,
+            0xD => self.draw(x, y, n as usize)?,
+            0xE => {
+                let down = self.keys & 1 << (self.v[x] & 0xF) != 0;
+                match nn {
+                    0x9E if down => next += 2,
+                    0xA1 if !down => next += 2,
+                    _ => {}
+                }
+            }
+            0xF => match nn {
+                0x07 => self.v[x] = self.delay,
+                // Takes the highest key that is down
+                0x0A if self.keys == 0 => next = self.pc,
+                0x0A => self.v[x] = 15 - self.keys.leading_zeros() as u8,
+                0x15 => self.delay = self.v[x],
+                0x18 => self.sound = self.v[x],
+                0x1E => self.i = self.i.wrapping_add(u16::from(self.v[x])),
+                0x29 => self.i = self.font_address + u16::from(self.v[x] & 0xF) * 5,
+                0x33 => {
+                    let start = self.range(3)?;
+                    let value = self.v[x];
+                    self.memory[start..start + 3].copy_from_slice(&[
+                        value / 100,
+                        value / 10 % 10,
+                        value % 10,
+                    ]);
+                }
+                0x55 => {
+                    let start = self.range(x + 1)?;
+                    self.memory[start..=start + x].copy_from_slice(&self.v[..=x]);
+                    self.increment_i(x);
+                }
+                0x65 => {
+                    let start = self.range(x + 1)?;
+                    self.v[..=x].copy_from_slice(&self.memory[start..=start + x]);
+                    self.increment_i(x);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        self.pc = next;
+        Ok(())
+    }
+
+    /// `8XYN`, VF is written last so it holds the flag even when X is F
+    fn arithmetic(&mut self, x: usize, y: usize, n: u16) {
+        let (vx, vy) = (self.v[x], self.v[y]);
+        let (result, flag) = match n {
+            0x0 => (vy, None),
+            0x1 => (vx | vy, self.logic_flag()),
+            0x2 => (vx & vy, self.logic_flag()),
+            0x3 => (vx ^ vy, self.logic_flag()),
+            0x4 => (
+                vx.wrapping_add(vy),
+                Some((vx as u16 + vy as u16 > 0xFF) as u8),
+            ),
+            0x5 => (vx.wrapping_sub(vy), Some((vx >= vy) as u8)),
+            0x6 => {
+                let source = if self.quirks.shift { vx } else { vy };
+                (source >> 1, Some(source & 1))
+            }
+            0x7 => (vy.wrapping_sub(vx), Some((vy >= vx) as u8)),
+            0xE => {
+                let source = if self.quirks.shift { vx } else { vy };
+                (source << 1, Some(source >> 7))
+            }
+            _ => return,
+        };
+        self.v[x] = result;
+        if let Some(flag) = flag {
+            self.v[0xF] = flag;
+        }
+    }
+
+    fn logic_flag(&self) -> Option<u8> {
+        if self.quirks.logic {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    fn draw(&mut self, x: usize, y: usize, rows: usize) -> Result<(), Fault> {
+        let start = self.range(rows)?;
+        let (left, top) = (self.v[x] as usize % WIDTH, self.v[y] as usize % HEIGHT);
+        let mut collision = false;
+        for row in 0..rows {
+            let byte = self.memory[start + row];
+            for column in 0..8 {
+                if byte & 0x80 >> column == 0 {
+                    continue;
+                }
+                let (mut px, mut py) = (left + column, top + row);
+                if self.quirks.wrap {
+                    px %= WIDTH;
+                    py %= HEIGHT;
+                } else if px >= WIDTH || py >= HEIGHT {
+                    continue;
+                }
+                collision |= self.screen[py * WIDTH + px] == 1;
+                self.screen[py * WIDTH + px] ^= 1;
+            }
+        }
+        self.v[0xF] = collision as u8;
+        self.waiting_vblank = self.quirks.vblank;
+        Ok(())
+    }
+
+    /// Start of the `length` bytes at I, if they are all in memory
+    fn range(&self, length: usize) -> Result<usize, Fault> {
+        let start = self.i as usize;
+        if start + length > self.memory.len() {
+            return Err(Fault::InvalidAddress(self.i));
+        }
+        Ok(start)
+    }
+
+    fn increment_i(&mut self, x: usize) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
+        }
+        let increment = if self.quirks.memory_increment_by_x {
+            x
+        } else {
+            x + 1
+        };
+        self.i = self.i.wrapping_add(increment as u16);
+    }
+}
@@ -0,0 +1,68 @@
+//! Programs that used to make the interpreter panic, found by hand or by the
+//! fuzz targets in `fuzz/`. Every new crash goes to `tests/regressions`, with
+//! the fault it stops on in [`expected`].
+
+use std::fs;
+
+use rand::rngs::mock::StepRng;
+
+use chip_8::cpu::{Cpu, Fault};
+use chip_8::machine::MachineBuilder;
+use chip_8::quirks::Quirks;
+
+/// The fault `program` stops on with `quirks`, `None` runs forever
+fn expected(program: &str, quirks: Quirks) -> Option<Fault> {
+    match program {
+        "bcd_past_memory.ch8" => Some(Fault::InvalidAddress(0xFFE)),
+        "draw_past_memory.ch8" | "fetch_past_memory.ch8" => Some(Fault::InvalidAddress(0xFFF)),
+        // Masked to 12 bits, the index can't overflow
+        "index_overflow.ch8" => None,
+        // BXNN jumps with VF instead of V0
+        "jump_past_memory.ch8" if quirks.jump => Some(Fault::InvalidAddress(0xFFF)),
+        "jump_past_memory.ch8" => Some(Fault::InvalidAddress(0x10FE)),
+        // Only the low nibble of VX picks a key
+        "key_out_of_range.ch8" => None,
+        "load_past_memory.ch8" | "store_past_memory.ch8" => Some(Fault::InvalidAddress(0xFF8)),
+        "stack_overflow.ch8" => Some(Fault::StackOverflow),
+        "stack_underflow.ch8" => Some(Fault::StackUnderflow),
+        _ => panic!("no expected fault for {}", program),
+    }
+}
+
+/// Runs `cpu` for a thousand frames
+fn run(cpu: &mut Cpu<StepRng>) {
+    for _ in 0..1000 {
+        for _ in 0..100 {
+            cpu.next();
+        }
+        cpu.decrease_timers();
+    }
+}
+
+#[test]
+fn regressions() {
+    let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/regressions");
+    let mut programs = 0;
+    for entry in fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let program = fs::read(&path).unwrap();
+        for quirks in [Quirks::VIP, Quirks::SCHIP, Quirks::XO_CHIP].iter() {
+            let mut cpu = Cpu::from_bytes(&program, StepRng::new(0, 1)).unwrap();
+            cpu.set_quirks(*quirks);
+            run(&mut cpu);
+            assert_eq!(cpu.fault(), expected(name, *quirks), "{}", name);
+
+            // The addresses past 2 KiB don't exist either
+            let mut cpu = MachineBuilder::new()
+                .memory_size(0x800)
+                .quirks(*quirks)
+                .load(&program, StepRng::new(0, 1))
+                .unwrap();
+            run(&mut cpu);
+            assert_eq!(cpu.fault(), expected(name, *quirks), "{} in 2 KiB", name);
+        }
+        programs += 1;
+    }
+    assert!(programs > 0, "no programs in {}", directory);
+}